@@ -0,0 +1,274 @@
+
+use clap::Parser;
+use lmp_common::MEMORY_SIZE;
+use lmp_vm::devices::{IntervalTimer, IoDevice, LedBank, Mapped, MappedDevice, Random, Tape, TextDisplay, Timer};
+use lmp_vm::Protection;
+use std::{fs, io};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A Little Man Computer simulator
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Config {
+    /// Attach a device to a port, used by `INP port` and `OUT port`. Devices are
+    /// `tape:INPUT[,OUTPUT]`, `random[:SEED]`, `timer`, `interval[:PERIOD]` and `leds[:COUNT]`.
+    /// Devices on ports 1 to 7 can raise the interrupt line of their port
+    #[arg(short, long = "device", value_name = "PORT=DEVICE")]
+    pub devices: Vec<PortDevice>,
+
+    /// Map a device into memory starting from an address, used by `LDA`, `STA` and other
+    /// instructions accessing memory. Devices are `display[:SIZE]`, or any device that can be
+    /// attached to a port, which takes up a single cell
+    #[arg(short, long = "map", value_name = "ADDRESS=DEVICE")]
+    pub mapped: Vec<MappedDeviceConfig>,
+
+    /// Values to queue as input, separated by spaces or commas. Characters can be given as
+    /// literals, as in `'a'`
+    #[arg(short, long = "input", value_name = "VALUES")]
+    pub inputs: Vec<InputList>,
+
+    /// Read values to queue as input from a file, after those given with `--input`
+    #[arg(long, value_name = "PATH")]
+    pub input_file: Option<PathBuf>,
+
+    /// Stop the program with a fault when it runs out of input, instead of asking for more
+    #[arg(long)]
+    pub fault_on_eof: bool,
+
+    /// How to handle the program writing over its own instructions: `off`, `detect` to highlight
+    /// such writes, or `write-protect` to fault
+    #[arg(long, value_name = "MODE", default_value = "off", value_parser = parse_protection)]
+    pub protection: Protection,
+}
+
+impl Config {
+    /// All values to queue as input, in order
+    pub fn input(&self) -> Result<Vec<i64>, String> {
+        let mut input: Vec<i64> = self.inputs.iter().flat_map(|list| list.0.iter().copied()).collect();
+        if let Some(path) = &self.input_file {
+            let text = fs::read_to_string(path).map_err(|error| format!("{}: {error}", path.display()))?;
+            input.extend(parse_inputs(&text).map_err(|error| format!("{}: {error}", path.display()))?);
+        }
+
+        Ok(input)
+    }
+}
+
+/// A list of input values, as given on the command line
+#[derive(Debug, Clone)]
+pub struct InputList(pub Vec<i64>);
+
+impl FromStr for InputList {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_inputs(s).map(Self)
+    }
+}
+
+fn parse_protection(mode: &str) -> Result<Protection, String> {
+    match mode {
+        "off" => Ok(Protection::Off),
+        "detect" => Ok(Protection::Detect),
+        "write-protect" => Ok(Protection::WriteProtect),
+        _ => Err(format!("unknown protection mode `{mode}`")),
+    }
+}
+
+/// Parse input values separated by whitespace or commas. Values are integers or character literals,
+/// which are given as their code point
+pub fn parse_inputs(text: &str) -> Result<Vec<i64>, String> {
+    let mut values = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(&next) = chars.peek() {
+        if next.is_whitespace() || next == ',' {
+            chars.next();
+        } else if next == '\'' {
+            chars.next();
+            match (chars.next(), chars.next()) {
+                (Some(character), Some('\'')) => values.push(character as i64),
+                _ => return Err("expected a single character between quotes".to_string()),
+            }
+        } else {
+            let mut value = String::new();
+            while let Some(&next) = chars.peek() && !next.is_whitespace() && next != ',' {
+                value.push(next);
+                chars.next();
+            }
+            values.push(value.parse().map_err(|_| format!("`{value}` is not an integer"))?);
+        }
+    }
+
+    Ok(values)
+}
+
+/// A device and the port to attach it to, as given on the command line
+#[derive(Debug, Clone)]
+pub struct PortDevice {
+    pub port: usize,
+    pub device: DeviceConfig,
+}
+
+/// A device and the address to map it at, as given on the command line
+#[derive(Debug, Clone)]
+pub struct MappedDeviceConfig {
+    pub start: usize,
+    pub device: MappedKind,
+}
+
+#[derive(Debug, Clone)]
+pub enum MappedKind {
+    Display(usize),
+    Port(DeviceConfig),
+}
+
+impl MappedKind {
+    pub fn build(&self) -> io::Result<Box<dyn MappedDevice>> {
+        Ok(match self {
+            MappedKind::Display(size) => Box::new(TextDisplay::new(*size)),
+            MappedKind::Port(device) => Box::new(Mapped(device.build()?)),
+        })
+    }
+}
+
+/// A device that can be attached to a port. There is no console device reading from standard
+/// input, as the terminal interface takes over the terminal while it runs
+#[derive(Debug, Clone)]
+pub enum DeviceConfig {
+    Tape { input: PathBuf, output: Option<PathBuf> },
+    Random(Option<u64>),
+    Timer,
+    Interval(i64),
+    Leds(u32),
+}
+
+impl DeviceConfig {
+    pub fn build(&self) -> io::Result<Box<dyn IoDevice>> {
+        Ok(match self {
+            DeviceConfig::Tape { input, output } => {
+                let tape = Tape::open(input)?;
+                Box::new(match output {
+                    Some(output) => tape.with_output(output)?,
+                    None => tape,
+                })
+            }
+            DeviceConfig::Random(seed) => {
+                let seed = seed.unwrap_or_else(|| {
+                    SystemTime::now().duration_since(UNIX_EPOCH).map_or(1, |time| time.as_nanos() as u64)
+                });
+                Box::new(Random::new(seed))
+            }
+            DeviceConfig::Timer => Box::new(Timer::new()),
+            DeviceConfig::Interval(period) => Box::new(IntervalTimer::new(*period)),
+            DeviceConfig::Leds(count) => Box::new(LedBank::new(*count)),
+        })
+    }
+}
+
+impl FromStr for PortDevice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (port, device) = s.split_once('=').ok_or("expected PORT=DEVICE")?;
+        let port = port
+            .trim()
+            .parse()
+            .ok()
+            .filter(|port| (1..=999).contains(port))
+            .ok_or_else(|| format!("`{port}` is not a port from 1 to 999"))?;
+
+        Ok(Self { port, device: device.parse()? })
+    }
+}
+
+impl FromStr for MappedDeviceConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, device) = s.split_once('=').ok_or("expected ADDRESS=DEVICE")?;
+        let start = start
+            .trim()
+            .parse()
+            .ok()
+            .filter(|start| *start < MEMORY_SIZE)
+            .ok_or_else(|| format!("`{start}` is not an address in memory"))?;
+
+        let device = match split_argument(device) {
+            ("display", size) => {
+                let size = match size {
+                    Some(size) => size
+                        .parse()
+                        .ok()
+                        .filter(|size| (1..=MEMORY_SIZE - start).contains(size))
+                        .ok_or_else(|| format!("`{size}` cells do not fit into memory from {start}"))?,
+                    None => 16.min(MEMORY_SIZE - start),
+                };
+                MappedKind::Display(size)
+            }
+            _ => MappedKind::Port(device.parse()?),
+        };
+
+        Ok(Self { start, device })
+    }
+}
+
+impl FromStr for DeviceConfig {
+    type Err = String;
+
+    fn from_str(device: &str) -> Result<Self, Self::Err> {
+        Ok(match split_argument(device) {
+            ("tape", Some(paths)) => match paths.split_once(',') {
+                Some((input, output)) => DeviceConfig::Tape { input: input.into(), output: Some(output.into()) },
+                None => DeviceConfig::Tape { input: paths.into(), output: None },
+            },
+            ("tape", None) => return Err("a tape needs a file, as in `tape:INPUT[,OUTPUT]`".to_string()),
+            ("random", seed) => DeviceConfig::Random(
+                seed.map(|seed| seed.parse().map_err(|_| format!("`{seed}` is not a seed")))
+                    .transpose()?,
+            ),
+            ("timer", None) => DeviceConfig::Timer,
+            ("interval", period) => DeviceConfig::Interval(
+                period
+                    .map(|period| period.parse().map_err(|_| format!("`{period}` is not a period")))
+                    .transpose()?
+                    .unwrap_or(0),
+            ),
+            ("leds", count) => DeviceConfig::Leds(match count {
+                Some(count) => count
+                    .parse()
+                    .ok()
+                    .filter(|count| (1..=64).contains(count))
+                    .ok_or_else(|| format!("`{count}` is not an LED count from 1 to 64"))?,
+                None => 8,
+            }),
+            _ => return Err(format!("unknown device `{device}`")),
+        })
+    }
+}
+
+/// Split a device into its kind and the argument after a `:`, if any
+fn split_argument(device: &str) -> (&str, Option<&str>) {
+    match device.split_once(':') {
+        Some((kind, argument)) => (kind, Some(argument)),
+        None => (device, None),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_inputs() {
+        assert_eq!(parse_inputs("5, -3 'a'\n' ',,-0"), Ok(vec![5, -3, 'a' as i64, ' ' as i64, 0]));
+        assert_eq!(parse_inputs("-9223372036854775808 +7"), Ok(vec![i64::MIN, 7]));
+        assert_eq!(parse_inputs(" \n, "), Ok(vec![]));
+
+        assert_eq!(parse_inputs("1 2x 3"), Err("`2x` is not an integer".to_string()));
+        assert_eq!(parse_inputs("1 - 3"), Err("`-` is not an integer".to_string()));
+        assert_eq!(parse_inputs("'ab'"), Err("expected a single character between quotes".to_string()));
+        assert_eq!(parse_inputs("'a"), Err("expected a single character between quotes".to_string()));
+    }
+}
@@ -1,4 +1,5 @@
-use super::vm::{VirtualMachine, VirtualMachineStep};
+use crate::config::parse_inputs;
+use lmp_vm::{InputKind, Protection, VirtualMachine, VirtualMachineStep, INTERRUPT_LINES};
 use derive_setters::Setters;
 use lmp_common::ClonableFn;
 use ratatui::crossterm::event;
@@ -7,6 +8,7 @@ use ratatui::prelude::*;
 use ratatui::style::Styled;
 use ratatui::widgets::*;
 use ratatui::DefaultTerminal;
+use std::fmt;
 use std::fmt::Debug;
 use std::str::FromStr;
 use tui_textarea::{CursorMove, TextArea};
@@ -20,19 +22,30 @@ pub struct TerminalInterface<'a> {
     program_textarea: TextArea<'a>,
     outputs: Vec<i64>,
     outputs_state: ListState,
-    inputs: Vec<i64>,
+    /// Characters output by the program
+    text_output: String,
+    /// Values given to the VM, the last of which may still be queued
+    inputs: Vec<IoValue>,
     inputs_state: ListState,
+    /// Values queued as input whenever the program is run
+    input_tape: Vec<i64>,
+    /// What to do with the value entered into the current modal
+    modal_action: ModalAction,
     memory_state: ListState,
     vm_on: bool,
     // Without WidgetRef, these cannot be Boxed
     current_popup: Option<Popup<'a>>,
     current_modal: Option<Modal<'a>>,
-    interface_mode: InterfaceMode,
 }
 
 // See other impl for rendering logic
 impl TerminalInterface<'_> {
     pub fn new() -> Self {
+        Self::with_vm(VirtualMachine::new())
+    }
+
+    /// Create an interface around an existing VM, such as one with devices attached
+    pub fn with_vm(vm: VirtualMachine) -> Self {
         // Textarea styling
         let mut program_textarea = TextArea::default();
         let textarea_block = Block::bordered().title("Program");
@@ -41,18 +54,20 @@ impl TerminalInterface<'_> {
         program_textarea.set_cursor_line_style(Style::default());
 
         Self {
-            vm: VirtualMachine::new(),
+            vm,
             should_exit: false,
             program_textarea,
             outputs: Vec::new(),
             outputs_state: ListState::default(),
+            text_output: String::new(),
             inputs: Vec::new(),
             inputs_state: ListState::default(),
+            input_tape: Vec::new(),
+            modal_action: ModalAction::Input(InputKind::Number),
             memory_state: ListState::default(),
             vm_on: false,
             current_popup: None,
             current_modal: None,
-            interface_mode: InterfaceMode::default(),
         }
     }
 
@@ -88,10 +103,10 @@ impl TerminalInterface<'_> {
                 }
             }).unwrap();
             // Do not block when there aren't any events to read
-            if let Ok(true) = event::poll(core::time::Duration::from_secs(0)) {
-                if let Event::Key(event) = event::read().unwrap() {
-                    self.handle_key(event);
-                }
+            if let Ok(true) = event::poll(core::time::Duration::from_secs(0))
+                && let Event::Key(event) = event::read().unwrap()
+            {
+                self.handle_key(event);
             }
 
             // Step the vm, if on
@@ -106,22 +121,31 @@ impl TerminalInterface<'_> {
                     VirtualMachineStep::Output(value) => {
                         self.outputs.push(value);
                     }
-                    VirtualMachineStep::InputRequired => {
+                    VirtualMachineStep::OutputCharacter(character) => {
+                        self.text_output.push(character);
+                    }
+                    VirtualMachineStep::InputRequired(kind) => {
                         // Show input modal
+                        let description = match kind {
+                            InputKind::Number => "The virtual machine required input (integer)",
+                            InputKind::Character => "The virtual machine required input (character)",
+                        };
                         let input_modal = Modal::default()
                             .title("Input Required")
-                            .description("The virtual machine required input (integer)")
+                            .description(description)
                             .input_title("Input")
                             .validate(Some(
-                                Box::new(|inp| {
-                                    if let Err(err) = <i64>::from_str(&inp) {
-                                        Some(err.to_string())
-                                    } else { None }
-                                })
+                                Box::new(move |inp| IoValue::parse(kind, &inp).err())
                             ));
 
+                        self.modal_action = ModalAction::Input(kind);
                         self.current_modal = Some(input_modal);
                     }
+                    VirtualMachineStep::Fault(fault) => {
+                        let fault_popup = Popup::default().title("Runtime Fault")
+                            .content(format!("The virtual machine stopped: {fault}"));
+                        self.current_popup = Some(fault_popup);
+                    }
                     _ => {}
                 }
             }
@@ -136,14 +160,16 @@ impl TerminalInterface<'_> {
         self.program_textarea.set_yank_text("");
     }
 
+    /// Set the values queued as input whenever the program is run
+    pub fn set_input_tape(&mut self, tape: Vec<i64>) {
+        self.input_tape = tape;
+    }
+
     fn handle_key(&mut self, key: KeyEvent) {
         // Catch all key events if a popup is up
         if self.current_popup.is_some() {
-            match key.code {
-                KeyCode::Enter => {
-                    self.current_popup = None;
-                }
-                _ => {} // No-op
+            if key.code == KeyCode::Enter {
+                self.current_popup = None;
             }
             return
         }
@@ -155,12 +181,22 @@ impl TerminalInterface<'_> {
             if key.code == KeyCode::Enter {
                 // SAFE UNWRAP: We checked if there was a modal before
                 let modal = self.current_modal.take().unwrap();
-
-                let Ok(input) = modal.textarea.lines()[0].parse() else {
-                    return
-                };
-                self.inputs.push(input);
-                self.vm.input(input);
+                let text = &modal.textarea.lines()[0];
+
+                match self.modal_action {
+                    ModalAction::Input(kind) => {
+                        let Ok(input) = IoValue::parse(kind, text) else {
+                            return
+                        };
+                        self.inputs.push(input);
+                        self.vm.input(input.into());
+                    }
+                    ModalAction::InputTape => match parse_inputs(text) {
+                        Ok(tape) => self.input_tape = tape,
+                        // Keep editing
+                        Err(_) => self.current_modal = Some(modal),
+                    },
+                }
 
                 return
             }
@@ -233,17 +269,40 @@ impl TerminalInterface<'_> {
                         return;
                     }
 
-                    self.inputs.clear();
+                    self.inputs = self.input_tape.iter().copied().map(IoValue::Number).collect();
                     self.outputs.clear();
+                    self.text_output.clear();
+                    self.vm.clear_input();
+                    self.vm.input_all(self.input_tape.iter().copied());
 
                     self.vm_on = true;
                 }
-                KeyCode::Char('n') => {
-                    if !self.vm_on && !in_modal { // only allow clearing when VM is not on, ignore in modals
-                        self.inputs.clear();
-                        self.outputs.clear();
-                        self.vm.reset();
-                    }
+                // Edit the input tape, only when the VM is not on so it is not asked for input
+                // at the same time
+                KeyCode::Char('e') if !self.vm_on && !in_modal => {
+                    let tape = self.input_tape.iter().map(i64::to_string).collect::<Vec<_>>().join(" ");
+                    let tape_modal = Modal::default()
+                        .title("Input Tape")
+                        .description("Values queued as input when the program is run, separated by spaces")
+                        .input_title("Values")
+                        .textarea(TextArea::new(vec![tape]))
+                        .validate(Some(
+                            Box::new(|inp: String| parse_inputs(&inp).err())
+                        ));
+
+                    self.modal_action = ModalAction::InputTape;
+                    self.current_modal = Some(tape_modal);
+                }
+                // Raise the interrupt line reserved for the user
+                KeyCode::Char('t') if self.vm_on && !in_modal => {
+                    self.vm.interrupt(0);
+                }
+                // only allow clearing when VM is not on, ignore in modals
+                KeyCode::Char('n') if !self.vm_on && !in_modal => {
+                    self.inputs.clear();
+                    self.outputs.clear();
+                    self.text_output.clear();
+                    self.vm.reset();
                 }
                 _ => {} // No-op
             }
@@ -300,14 +359,40 @@ impl TerminalInterface<'_> {
     fn render_cpu(&self, area: Rect, buf: &mut Buffer) {
         let outer_block = Block::bordered().title("Central Processing Unit");
 
-        let [status_area, stats_area] = Layout::vertical([Constraint::Length(2), Constraint::Length(3)]).areas(outer_block.inner(area));
+        let [status_area, stats_area, stack_area] = Layout::vertical([
+            Constraint::Length(2),
+            Constraint::Length(3),
+            Constraint::Fill(1),
+        ]).areas(outer_block.inner(area));
+        let flag = |name: &'static str, set: bool| {
+            if set { name.fg(Color::Black).bg(Color::White) } else { name.fg(Color::DarkGray) }
+        };
+        let flags = self.vm.flags();
+        let interrupts = self.vm.interrupts();
+        let mut interrupt_line = Line::from(vec![
+            "Interrupts: ".fg(Color::DarkGray),
+            flag("Enabled", interrupts.enabled),
+            " | Pending: ".fg(Color::DarkGray),
+        ]);
+        for line in interrupts.pending_lines() {
+            interrupt_line.push_span(format!("{line} "));
+        }
         Paragraph::new(vec![
-            if self.vm_on { "VM Running".bold().fg(Color::Green).into() } else { "VM Halted".fg(Color::Red).bold().into() }
+            Line::from(vec![
+                if self.vm_on { "VM Running".bold().fg(Color::Green) } else { "VM Halted".fg(Color::Red).bold() },
+                " | Flags: ".fg(Color::DarkGray),
+                flag("Carry", flags.carry),
+                " ".into(),
+                flag("Overflow", flags.overflow),
+            ]),
+            interrupt_line,
         ]).render(status_area, buf);
-        let [program_counter_area, accumulator_area, cycles_area] = Layout::horizontal([
-            Constraint::Ratio(1, 3),
-            Constraint::Ratio(1, 3),
-            Constraint::Ratio(1, 3),
+        let [program_counter_area, accumulator_area, index_area, stack_pointer_area, cycles_area] = Layout::horizontal([
+            Constraint::Ratio(1, 5),
+            Constraint::Ratio(1, 5),
+            Constraint::Ratio(1, 5),
+            Constraint::Ratio(1, 5),
+            Constraint::Ratio(1, 5),
         ]).areas(stats_area);
 
         let program_counter_block = Block::bordered().title("Program Counter");
@@ -320,11 +405,30 @@ impl TerminalInterface<'_> {
             format!("{}", self.vm.accumulator()).into()
         ]).block(accumulator_block).render(accumulator_area, buf);
 
+        let index_block = Block::bordered().title("Index");
+        Paragraph::new(vec![
+            format!("{}", self.vm.index()).into()
+        ]).block(index_block).render(index_area, buf);
+
+        let stack_pointer_block = Block::bordered().title("Stack Pointer");
+        Paragraph::new(vec![
+            format!("{}", self.vm.stack_pointer()).into()
+        ]).block(stack_pointer_block).render(stack_pointer_area, buf);
+
         let cycles_block = Block::bordered().title("Cycles");
         Paragraph::new(vec![
             format!("{}", self.vm.cycles()).into()
         ]).block(cycles_block).render(cycles_area, buf);
 
+        // Top of the stack first
+        let stack_block = Block::bordered().title("Stack");
+        let stack_items: Vec<ListItem> = self.vm.stack().iter().enumerate().map(|(offset, cell)| {
+            ListItem::new(vec![
+                format!("{:0>3}: {}", self.vm.stack_pointer() + offset, cell.data).into()
+            ])
+        }).collect();
+        Widget::render(List::new(stack_items).block(stack_block), stack_area, buf);
+
         outer_block.render(area, buf);
     }
 
@@ -336,17 +440,26 @@ impl TerminalInterface<'_> {
             Constraint::Ratio(1, 2),
         ]).areas(outer_block.inner(area));
 
-        let input_block = Block::bordered().title("Input");
+        let queued = self.vm.queued_input().len();
+        let input_block = Block::bordered().title(format!("Input ({queued} queued)"));
 
-        let input_list_items: Vec<ListItem> = self.inputs.iter().map(|input| {
+        // Queued values are the last given to the VM, and are shown dimmed until they are read
+        let read = self.inputs.len().saturating_sub(queued);
+        let input_list_items: Vec<ListItem> = self.inputs.iter().enumerate().map(|(i, input)| {
+            let line = format!("{}", input);
             ListItem::new(vec![
-                format!("{}", input).into()
+                if i < read { line.into() } else { line.fg(Color::DarkGray).into() }
             ])
         }).collect();
 
         let input_list = List::new(input_list_items).block(input_block);
         StatefulWidget::render(input_list, input_area, buf, &mut self.inputs_state);
 
+        let [output_area, text_area] = Layout::vertical([
+            Constraint::Ratio(1, 2),
+            Constraint::Ratio(1, 2),
+        ]).areas(output_area);
+
         let output_block = Block::bordered().title("Output");
 
         let output_list_items: Vec<ListItem> = self.outputs.iter().map(|output| {
@@ -358,16 +471,30 @@ impl TerminalInterface<'_> {
         let output_list = List::new(output_list_items).block(output_block);
         StatefulWidget::render(output_list, output_area, buf, &mut self.outputs_state);
 
+        let text_block = Block::bordered().title("Text Output");
+        Paragraph::new(self.text_output.as_str())
+            .wrap(Wrap { trim: false })
+            .block(text_block)
+            .render(text_area, buf);
+
         outer_block.render(area, buf);
     }
 
     fn render_ram(&mut self, area: Rect, buf: &mut Buffer) {
         let outer_block = Block::bordered().title("Memory");
 
+        let mapped: Vec<_> = self.vm.mapped().map(|(cells, _)| cells).collect();
+        let modified: Vec<_> = self.vm.self_modifications().iter().map(|write| write.target).collect();
         let list_items: Vec<ListItem> = self.vm.memory().iter().enumerate().map(|(addr, cell)| {
-            ListItem::new(vec![
-                format!("{:<3}{addr:0>3}: {}", if self.vm.program_counter() == addr { ">>" } else { "" }, cell.data).into(),
-            ])
+            let line = format!("{:<3}{addr:0>3}: {}", if self.vm.program_counter() == addr { ">>" } else { "" }, cell.data);
+            // Cells handled by a device or overwritten code are shown in a different colour
+            if modified.contains(&addr) {
+                ListItem::new(line.fg(Color::Magenta))
+            } else if mapped.iter().any(|cells| cells.contains(&addr)) {
+                ListItem::new(line.fg(Color::Yellow))
+            } else {
+                ListItem::new(line)
+            }
         }).collect();
 
         let list = List::new(list_items).block(outer_block).highlight_style(Style::default().fg(Color::Black).bg(Color::White));
@@ -381,7 +508,39 @@ impl TerminalInterface<'_> {
     fn render_config(&mut self, area: Rect, buf: &mut Buffer) {
         let outer_block = Block::bordered().title("Configuration");
 
-        outer_block.render(area, buf);
+        let stack = &self.vm.config().stack;
+        let vector = self.vm.config().interrupt_vector;
+        let mut lines: Vec<Line> = vec![
+            format!("Stack: {:0>3}-{:0>3}", stack.start, stack.end - 1).into(),
+            format!("Interrupt Vector: {:0>3}-{:0>3}", vector, vector + INTERRUPT_LINES - 1).into(),
+            format!("End of Input: {}", if self.vm.config().fault_on_end_of_input { "Fault" } else { "Ask" }).into(),
+            format!("Protection: {}", match self.vm.config().protection {
+                Protection::Off => "Off",
+                Protection::Detect => "Detect",
+                Protection::WriteProtect => "Write Protect",
+            }).into(),
+        ];
+        // Most recent first
+        for write in self.vm.self_modifications().iter().rev() {
+            lines.push(format!("{:0>3} wrote over {:0>3} (cycle {})", write.address, write.target, write.cycle).fg(Color::Magenta).into());
+        }
+        for (port, device) in self.vm.devices() {
+            let mut line = Line::from(format!("Port {port}: {}", device.name()));
+            if let Some(status) = device.status() {
+                line.push_span(" ");
+                line.push_span(status.fg(Color::Yellow));
+            }
+            lines.push(line);
+        }
+        for (cells, device) in self.vm.mapped() {
+            let mut line = Line::from(format!("{:0>3}-{:0>3}: {}", cells.start, cells.end - 1, device.name()));
+            if let Some(status) = device.status() {
+                line.push_span(" ");
+                line.push_span(status.fg(Color::Yellow));
+            }
+            lines.push(line);
+        }
+        Paragraph::new(lines).wrap(Wrap { trim: false }).block(outer_block).render(area, buf);
     }
 
     fn render_footer(&mut self, area: Rect, buf: &mut Buffer) {
@@ -393,12 +552,57 @@ impl TerminalInterface<'_> {
                 " Run Program ".into(),
                 "Ctrl+N".fg(Color::Black).bg(Color::White),
                 " Reset VM ".into(),
+                "Ctrl+E".fg(Color::Black).bg(Color::White),
+                " Input Tape ".into(),
+                "Ctrl+T".fg(Color::Black).bg(Color::White),
+                " Interrupt ".into(),
                 " | ".fg(Color::DarkGray),
             ])
         ]).block(block).render(area, buf);
     }
 }
 
+/// A value input into the VM by the user
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum IoValue {
+    Number(i64),
+    Character(char),
+}
+
+impl IoValue {
+    /// Parse the text entered into the input modal, returning an error message if it is invalid
+    fn parse(kind: InputKind, input: &str) -> Result<Self, String> {
+        match kind {
+            InputKind::Number => i64::from_str(input).map(IoValue::Number).map_err(|err| err.to_string()),
+            InputKind::Character => {
+                let mut chars = input.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(character), None) => Ok(IoValue::Character(character)),
+                    _ => Err("expected a single character".to_string()),
+                }
+            }
+        }
+    }
+}
+
+impl From<IoValue> for i64 {
+    fn from(value: IoValue) -> i64 {
+        match value {
+            IoValue::Number(number) => number,
+            IoValue::Character(character) => character as i64,
+        }
+    }
+}
+
+impl fmt::Display for IoValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IoValue::Number(number) => write!(f, "{number}"),
+            IoValue::Character(character) => write!(f, "{character:?}"),
+        }
+    }
+}
+
 #[derive(Debug, Default, Setters, Clone)]
 struct Popup<'a> {
     #[setters(into)]
@@ -416,7 +620,7 @@ impl Widget for Popup<'_> {
         self.content.push_line(Line::default());
         self.content.push_line(Line::from(vec![
             "Press ".into(),
-            "Enter".fg(Color::Black).bg(Color::White).into(),
+            "Enter".fg(Color::Black).bg(Color::White),
             " to dismiss".into(),
         ]));
 
@@ -503,9 +707,11 @@ impl Widget for Modal<'_> {
     }
 }
 
-#[derive(Debug, Default)]
-enum InterfaceMode {
-    #[default]
-    Program,
-    Configuration,
+/// What the value entered into a modal is used for
+#[derive(Debug, Clone, Copy)]
+enum ModalAction {
+    /// Input requested by the VM
+    Input(InputKind),
+    /// Replacing the input tape
+    InputTape,
 }
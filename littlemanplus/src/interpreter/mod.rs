@@ -1,2 +1 @@
-pub mod vm;
 pub mod interface;
\ No newline at end of file
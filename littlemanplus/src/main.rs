@@ -18,86 +18,105 @@
 mod interpreter;
 mod config;
 
+use clap::Parser;
+use config::Config;
 use interpreter::interface::TerminalInterface;
+use lmp_vm::{VirtualMachine, VirtualMachineConfig};
 
 fn main() {
     const PROGRAM: &str = r#"
-       INP
-       STA 90
-       INP
-       STA 91
-       INP
-       STA 92
-       INP
-       STA 93
-       INP
-       STA 94
-       INP
-       STA 95
-       INP
-       STA 96
-       INP
-       STA 97
-       INP
-       STA 98
-       INP
-       STA 99
-loop   LDA true
+// Bubble sort ten numbers stored from `list` onwards
+list   EQU 90
+size   EQU 10
+       LDA #0
+       TAX
+read   INP
+       STA list,X
+       TXA
+       ADD #1
+       TAX
+       SUB #size
+       BRZ loop
+       BRA read
+loop   LDA #1
        STA sorted
-       LDA first
-       STA pos
-       ADD one
-       STA next
-step   LDA @pos
-       SUB @next
+       LDA #0
+       TAX
+step   LDA list,X
+       SUB list+1,X
        BRZ pass
        BRP swap
-pass   LDA pos
-       ADD one
-       STA pos
-       LDA next
-       ADD one
-       STA next
-       LDA pos
-       SUB last
+pass   TXA
+       ADD #1
+       TAX
+       SUB #size-1
        BRZ repeat
        BRA step
-swap   LDA @next
+swap   LDA list+1,X
        STA temp
-       LDA @pos
-       STA @next
+       LDA list,X
+       STA list+1,X
        LDA temp
-       STA @pos
-       LDA false
+       STA list,X
+       LDA #0
        STA sorted
        BRA pass
 repeat LDA sorted
-       SUB true
-       BRZ exit
-       BRA loop
-exit   LDA first
-       STA pos
-outs   LDA @pos
+       BRZ loop
+       LDA #0
+       TAX
+outs   LDA list,X
        OUT
-       LDA pos
-       ADD one
-       STA pos
-       LDA last
-       SUB pos
-       BRP outs
-       HLT
-pos    DAT
-next   DAT
+       TXA
+       ADD #1
+       TAX
+       SUB #size
+       BRZ exit
+       BRA outs
+exit   HLT
 temp   DAT
 sorted DAT 0
-true   DAT 1
-false  DAT 0
-one    DAT 1
-first  DAT 90
-last   DAT 99
 "#;
+    let config = Config::parse();
+    let input = config.input().unwrap_or_else(|error| {
+        eprintln!("Could not read input: {error}");
+        std::process::exit(1);
+    });
+
+    let mut vm = VirtualMachine::with_config(VirtualMachineConfig {
+        fault_on_end_of_input: config.fault_on_eof,
+        protection: config.protection,
+        ..VirtualMachineConfig::default()
+    });
+    for device in &config.devices {
+        match device.device.build() {
+            Ok(built) => {
+                vm.attach(device.port, built);
+            }
+            Err(error) => {
+                eprintln!("Could not attach device to port {}: {error}", device.port);
+                std::process::exit(1);
+            }
+        }
+    }
+    for device in &config.mapped {
+        match device.device.build() {
+            Ok(built) => {
+                if let Err(error) = vm.map(device.start, built) {
+                    eprintln!("Could not map device at {}: {error}", device.start);
+                    std::process::exit(1);
+                }
+            }
+            Err(error) => {
+                eprintln!("Could not map device at {}: {error}", device.start);
+                std::process::exit(1);
+            }
+        }
+    }
+
     let terminal = ratatui::init();
-    let mut tui = TerminalInterface::new();
+    let mut tui = TerminalInterface::with_vm(vm);
+    tui.set_input_tape(input);
     tui.set_program(PROGRAM);
     tui.run(terminal);
     ratatui::restore();
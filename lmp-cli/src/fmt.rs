@@ -0,0 +1,92 @@
+//! `lmp fmt`, which formats programs with [`lmp_lang::parser::format`]
+
+use lmp_lang::parser::format;
+use lmp_lang::{SourceMap, SourceOrigin};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// Programs to format in place. Reads from stdin and writes to stdout if none are given
+    files: Vec<PathBuf>,
+
+    /// Do not write anything, instead failing if any program is not already formatted
+    #[arg(long)]
+    check: bool,
+}
+
+pub fn run(args: Args) -> ExitCode {
+    if args.files.is_empty() {
+        let mut source = String::new();
+        if let Err(error) = io::stdin().read_to_string(&mut source) {
+            eprintln!("Could not read input: {error}");
+            return ExitCode::FAILURE;
+        }
+
+        return match format_source(SourceOrigin::Input, &source) {
+            Some(formatted) if args.check => {
+                if formatted == source {
+                    ExitCode::SUCCESS
+                } else {
+                    eprintln!("Input would be reformatted");
+                    ExitCode::FAILURE
+                }
+            }
+            Some(formatted) => match io::stdout().write_all(formatted.as_bytes()) {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(error) => {
+                    eprintln!("Could not write output: {error}");
+                    ExitCode::FAILURE
+                }
+            },
+            None => ExitCode::FAILURE,
+        };
+    }
+
+    // Carry on through the other files if one fails, so that all problems are reported at once
+    let mut success = true;
+    for path in args.files {
+        let source = match fs::read_to_string(&path) {
+            Ok(source) => source,
+            Err(error) => {
+                eprintln!("Could not read {}: {error}", path.display());
+                success = false;
+                continue;
+            }
+        };
+        let Some(formatted) = format_source(SourceOrigin::File(path.clone()), &source) else {
+            success = false;
+            continue;
+        };
+        if formatted == source {
+            continue;
+        }
+
+        if args.check {
+            eprintln!("{} would be reformatted", path.display());
+            success = false;
+        } else if let Err(error) = fs::write(&path, formatted) {
+            eprintln!("Could not write {}: {error}", path.display());
+            success = false;
+        }
+    }
+
+    if success { ExitCode::SUCCESS } else { ExitCode::FAILURE }
+}
+
+/// Format a program, printing its syntax errors if it has any
+fn format_source(origin: SourceOrigin, source: &str) -> Option<String> {
+    format(source)
+        .map_err(|errors| {
+            // The program is the only file, so errors are reported against it
+            let mut sources = SourceMap::new();
+            sources.add(origin.clone(), source);
+            eprintln!("Could not format {origin}, it is not a valid program.");
+            for error in errors {
+                eprintln!("{}", error.report(&sources));
+            }
+        })
+        .ok()
+}
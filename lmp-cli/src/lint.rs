@@ -0,0 +1,85 @@
+//! `lmp lint`, which checks programs with [`lmp_lang::lint`]
+
+use lmp_lang::lint::{Lint, LintConfig, Severity, lint};
+use lmp_lang::parser::{assemble_sources, index};
+use lmp_lang::{SourceId, SourceMap, SourceOrigin};
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// Programs to check. Reads from stdin if none are given
+    files: Vec<PathBuf>,
+
+    /// Do not check for a lint, given by its code or name (e.g. `L004` or `unused-label`)
+    #[arg(short = 'A', long, value_name = "LINT")]
+    allow: Vec<Lint>,
+
+    /// Report a lint as a warning, which is the default
+    #[arg(short = 'W', long, value_name = "LINT")]
+    warn: Vec<Lint>,
+
+    /// Report a lint as an error, failing if it is found
+    #[arg(short = 'D', long, value_name = "LINT")]
+    deny: Vec<Lint>,
+}
+
+pub fn run(args: Args) -> ExitCode {
+    let mut config = LintConfig::new();
+    let severities = [(&args.allow, Severity::Allow), (&args.warn, Severity::Warning), (&args.deny, Severity::Error)];
+    for (lints, severity) in severities {
+        for &lint in lints {
+            config.set(lint, severity);
+        }
+    }
+
+    if args.files.is_empty() {
+        let mut source = String::new();
+        if let Err(error) = io::stdin().read_to_string(&mut source) {
+            eprintln!("Could not read input: {error}");
+            return ExitCode::FAILURE;
+        }
+
+        let mut sources = SourceMap::new();
+        let root = sources.add(SourceOrigin::Input, source);
+        return if check(&mut sources, root, &config) { ExitCode::SUCCESS } else { ExitCode::FAILURE };
+    }
+
+    let mut success = true;
+    for path in args.files {
+        let mut sources = SourceMap::new();
+        match sources.add_file(&path) {
+            Ok(root) => success &= check(&mut sources, root, &config),
+            Err(error) => {
+                eprintln!("Could not read {}: {error}", path.display());
+                success = false;
+            }
+        }
+    }
+
+    if success { ExitCode::SUCCESS } else { ExitCode::FAILURE }
+}
+
+/// Lint the program in `root`, printing what is found. Returns whether it assembled without any
+/// lints reported as errors
+fn check(sources: &mut SourceMap, root: SourceId, config: &LintConfig) -> bool {
+    let symbols = index(sources, root);
+    let program = assemble_sources(sources, root);
+    let (program, symbols) = match (program, symbols) {
+        (Ok(program), Ok(symbols)) => (program, symbols),
+        (Err(errors), _) | (_, Err(errors)) => {
+            eprintln!("Could not lint {}, it does not assemble.", sources.file(root).origin);
+            for error in errors {
+                eprintln!("{}", error.report(sources));
+            }
+            return false;
+        }
+    };
+
+    let diagnostics = lint(&program, &symbols, config);
+    for diagnostic in &diagnostics {
+        eprintln!("{}", diagnostic.report(sources));
+    }
+    diagnostics.iter().all(|diagnostic| diagnostic.severity != Severity::Error)
+}
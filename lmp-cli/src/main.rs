@@ -0,0 +1,45 @@
+/*
+    littlemanplus — a Rust-based Little Man Computer simulator
+    Copyright (C) 2025 azyrite
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+mod fmt;
+mod lint;
+
+use clap::{Parser, Subcommand};
+use std::process::ExitCode;
+
+/// Tools for working with LMC assembly
+#[derive(Debug, Parser)]
+#[command(name = "lmp", version, about)]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Lay out programs in aligned columns, with opcodes and directives in upper case
+    Fmt(fmt::Args),
+    /// Check programs for likely mistakes, such as unreachable code or branches into data
+    Lint(lint::Args),
+}
+
+fn main() -> ExitCode {
+    match Args::parse().command {
+        Command::Fmt(args) => fmt::run(args),
+        Command::Lint(args) => lint::run(args),
+    }
+}
@@ -1,4 +1,4 @@
-use std::fmt;
+use core::fmt;
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum DataOrLabel<'a, Data> {
@@ -6,72 +6,333 @@ pub enum DataOrLabel<'a, Data> {
     Label(&'a str),
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
-#[repr(i64)]
-pub enum Instruction<Data> {
-    /// Add the contents of the memory at the specified address to the accumulator
-    ADD(Data) = 1000,
-    /// Subtract the contents of the memory at the specified address to the accumulator
-    SUB(Data) = 2000,
-    /// Store the contents of the accumulator at the specified memory address, overwriting
-    STA(Data) = 3000,
-    /// Load the contents of the specified memory address into the accumulator, overwriting
-    LDA(Data) = 5000,
-    /// Branch always: set the program counter to the specified memory address
-    BRA(Data) = 6000,
-    /// Branch if zero, sets the program counter to the specified memory address if the accumulator is
-    /// zero
-    BRZ(Data) = 7000,
-    /// Branch if positive, sets the program counter to the specified memory address if the \
-    /// accumulator is positive
-    BRP(Data) = 8000,
-
-    // Bitwise operations
-    /// Bitwise NOT the accumulator
-    BWN = 10000,
-    /// Bitwise AND the accumulator with the specified memory address
-    BWA(Data) = 11000,
-    /// Bitwise OR the accumulator with the specified memory address
-    BWO(Data) = 12000,
-    /// Bitwise XOR the accumulator with the specified memory address
-    BWX(Data) = 13000,
-
-    /// Load the contents of the memory address currently stored in the accumulator ("Load from Register")
-    LDR = 900,
-    /// Request input from the user which is stored into the accumulator, overwriting
-    INP = 901,
-    /// Output the value currently in the accumulator, does not overwrite
-    OUT = 902,
-    /// Stop the program
-    HLT = 1,
-    /// Store a piece of data at a free memory address, usually associating it with a label.
-    ///
-    /// Data defaults to `0`
-    DAT(Data) = 0,
+/// An instruction as named in [`MNEMONICS`], along with its opcode and documentation
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Mnemonic {
+    /// Name of the variant of [`Instruction`], e.g. `ADDI`
+    pub name: &'static str,
+    /// Discriminant of the variant
+    pub opcode: i64,
+    /// Lines of the doc comment on the variant, with the leading space of each kept
+    pub doc: &'static [&'static str],
+}
+
+/// Declare [`Instruction`] along with [`MNEMONICS`], so that the doc comments on its variants are
+/// also available at runtime
+macro_rules! instructions {
+    (
+        $(#[$attribute:meta])*
+        pub enum $name:ident<$data:ident> {
+            $($(#[doc = $doc:literal])* $variant:ident $(($operand:ident))? = $opcode:literal,)*
+        }
+    ) => {
+        $(#[$attribute])*
+        pub enum $name<$data> {
+            $($(#[doc = $doc])* $variant $(($operand))? = $opcode,)*
+        }
+
+        /// Every variant of [`Instruction`] in order of declaration, for showing documentation
+        pub const MNEMONICS: &[Mnemonic] = &[
+            $(Mnemonic { name: stringify!($variant), opcode: $opcode, doc: &[$($doc),*] },)*
+        ];
+    };
+}
+
+instructions! {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    #[repr(i64)]
+    pub enum Instruction<Data> {
+        /// Add the contents of the memory at the specified address to the accumulator
+        ADD(Data) = 1000,
+        /// Subtract the contents of the memory at the specified address to the accumulator
+        SUB(Data) = 2000,
+        /// Store the contents of the accumulator at the specified memory address, overwriting
+        STA(Data) = 3000,
+        /// Store the contents of the accumulator at the memory address held in the specified memory
+        /// address ("Store to Register"), the counterpart to [`Instruction::LDR`]
+        STR(Data) = 4000,
+        /// Load the contents of the specified memory address into the accumulator, overwriting
+        LDA(Data) = 5000,
+        /// Branch always: set the program counter to the specified memory address
+        BRA(Data) = 6000,
+        /// Branch if zero, sets the program counter to the specified memory address if the accumulator is
+        /// zero
+        BRZ(Data) = 7000,
+        /// Branch if positive, sets the program counter to the specified memory address if the
+        /// accumulator is positive
+        BRP(Data) = 8000,
+        /// Branch if negative, sets the program counter to the specified memory address if the
+        /// accumulator is negative
+        BRN(Data) = 9000,
+
+        // Bitwise operations
+        /// Bitwise NOT the accumulator
+        BWN = 10000,
+        /// Bitwise AND the accumulator with the specified memory address
+        BWA(Data) = 11000,
+        /// Bitwise OR the accumulator with the specified memory address
+        BWO(Data) = 12000,
+        /// Bitwise XOR the accumulator with the specified memory address
+        BWX(Data) = 13000,
+
+        // Multiplicative arithmetic, wrapping on overflow
+        /// Multiply the accumulator by the contents of the specified memory address
+        MUL(Data) = 14000,
+        /// Divide the accumulator by the contents of the specified memory address, rounding towards
+        /// zero. Dividing by zero is a fault
+        DIV(Data) = 15000,
+        /// Set the accumulator to the remainder of dividing it by the contents of the specified memory
+        /// address, which has the same sign as the accumulator. Dividing by zero is a fault
+        MOD(Data) = 16000,
+
+        // Shifts, by the number of bits in the specified memory address. Shifting by an amount outside
+        // of 0 to 63 shifts out every bit
+        /// Shift the accumulator left
+        SHL(Data) = 17000,
+        /// Shift the accumulator right, filling with zeroes ("logical shift")
+        SHR(Data) = 18000,
+        /// Shift the accumulator right, filling with the sign bit ("arithmetic shift")
+        SAR(Data) = 19000,
+
+        /// Call a subroutine: push the address of the next instruction onto the stack and set the
+        /// program counter to the specified memory address
+        CAL(Data) = 40000,
+
+        /// Branch if not zero, sets the program counter to the specified memory address if the
+        /// accumulator is not zero
+        BNZ(Data) = 43000,
+        /// Branch if carry, sets the program counter to the specified memory address if the last `ADD`
+        /// or `SUB` carried out of the highest bit, i.e., overflowed as an unsigned value
+        BRC(Data) = 44000,
+        /// Branch if overflow, sets the program counter to the specified memory address if the last
+        /// `ADD` or `SUB` overflowed as a signed value
+        BRV(Data) = 45000,
+
+        /// Load the contents of the specified memory address into the index register, overwriting
+        LDX(Data) = 41000,
+        /// Store the contents of the index register at the specified memory address, overwriting
+        STX(Data) = 42000,
+        /// Copy the accumulator into the index register
+        TAX = 910,
+        /// Copy the index register into the accumulator
+        TXA = 911,
+
+        // Indexed addressing, written with `,X` after the operand (e.g. `LDA table,X`). The index
+        // register is added to the address, after following pointers
+        /// Add the contents of the indexed memory address to the accumulator
+        ADDX(Data) = 51000,
+        /// Subtract the contents of the indexed memory address from the accumulator
+        SUBX(Data) = 52000,
+        /// Store the contents of the accumulator at the indexed memory address, overwriting
+        STAX(Data) = 53000,
+        /// Load the contents of the indexed memory address into the accumulator, overwriting
+        LDAX(Data) = 55000,
+
+        // Immediate addressing, written with a `#` before the operand (e.g. `ADD #1`). The operand is
+        // the value itself rather than the address of a cell containing it
+        /// Add the specified value to the accumulator
+        ADDI(Data) = 21000,
+        /// Subtract the specified value from the accumulator
+        SUBI(Data) = 22000,
+        /// Load the specified value into the accumulator, overwriting
+        LDAI(Data) = 25000,
+        /// Bitwise AND the accumulator with the specified value
+        BWAI(Data) = 31000,
+        /// Bitwise OR the accumulator with the specified value
+        BWOI(Data) = 32000,
+        /// Bitwise XOR the accumulator with the specified value
+        BWXI(Data) = 33000,
+        /// Multiply the accumulator by the specified value
+        MULI(Data) = 34000,
+        /// Divide the accumulator by the specified value
+        DIVI(Data) = 35000,
+        /// Set the accumulator to the remainder of dividing it by the specified value
+        MODI(Data) = 36000,
+        /// Shift the accumulator left by the specified number of bits
+        SHLI(Data) = 37000,
+        /// Shift the accumulator right by the specified number of bits, filling with zeroes
+        SHRI(Data) = 38000,
+        /// Shift the accumulator right by the specified number of bits, filling with the sign bit
+        SARI(Data) = 39000,
+
+        /// Load the contents of the memory address currently stored in the accumulator ("Load from Register")
+        LDR = 900,
+        /// Request input from the user which is stored into the accumulator, overwriting
+        INP = 901,
+        /// Output the value currently in the accumulator, does not overwrite
+        OUT = 902,
+        /// Read a value from the device attached to the specified port into the accumulator,
+        /// overwriting. Port 0 is the same as [`Instruction::INP`]
+        INPP(Data) = 46000,
+        /// Write the value in the accumulator to the device attached to the specified port. Port 0 is
+        /// the same as [`Instruction::OUT`]
+        OUTP(Data) = 47000,
+        /// Request a character from the user, storing its code point into the accumulator, overwriting
+        INC = 921,
+        /// Output the character with the code point currently in the accumulator, does not overwrite
+        OTC = 922,
+        /// Return from a subroutine, popping the return address off the stack into the program counter
+        RET = 903,
+        /// Push the accumulator onto the stack
+        PSH = 904,
+        /// Pop the top of the stack into the accumulator, overwriting
+        POP = 905,
+        /// Enable interrupts
+        EI = 906,
+        /// Disable interrupts, deferring any that are raised until they are enabled again
+        DI = 907,
+        /// Return from an interrupt handler, popping the flags and then the program counter off the
+        /// stack and enabling interrupts again
+        RTI = 908,
+        /// Stop the program
+        HLT = 1,
+        /// Store a piece of data at a free memory address, usually associating it with a label.
+        ///
+        /// Data defaults to `0`
+        DAT(Data) = 0,
+    }
 }
 
-impl Into<i64> for Instruction<i64> {
-    fn into(self) -> i64 {
+impl<Data> Instruction<Data> {
+    /// Convert the data attached to the instruction, keeping the instruction the same
+    pub fn map<U, F: FnOnce(Data) -> U>(self, f: F) -> Instruction<U> {
         use Instruction::*;
         match self {
+            HLT => HLT,
+            LDR => LDR,
+            INP => INP,
+            OUT => OUT,
+            INC => INC,
+            OTC => OTC,
+            RET => RET,
+            PSH => PSH,
+            POP => POP,
+            EI => EI,
+            DI => DI,
+            RTI => RTI,
+            TAX => TAX,
+            TXA => TXA,
+            BWN => BWN,
+
+            ADD(data) => ADD(f(data)),
+            SUB(data) => SUB(f(data)),
+            STA(data) => STA(f(data)),
+            STR(data) => STR(f(data)),
+            LDA(data) => LDA(f(data)),
+            BRA(data) => BRA(f(data)),
+            BRZ(data) => BRZ(f(data)),
+            BRP(data) => BRP(f(data)),
+            BRN(data) => BRN(f(data)),
+            BNZ(data) => BNZ(f(data)),
+            BRC(data) => BRC(f(data)),
+            BRV(data) => BRV(f(data)),
+            CAL(data) => CAL(f(data)),
+            INPP(data) => INPP(f(data)),
+            OUTP(data) => OUTP(f(data)),
+            LDX(data) => LDX(f(data)),
+            STX(data) => STX(f(data)),
+
+            BWA(data) => BWA(f(data)),
+            BWO(data) => BWO(f(data)),
+            BWX(data) => BWX(f(data)),
+
+            MUL(data) => MUL(f(data)),
+            DIV(data) => DIV(f(data)),
+            MOD(data) => MOD(f(data)),
+            SHL(data) => SHL(f(data)),
+            SHR(data) => SHR(f(data)),
+            SAR(data) => SAR(f(data)),
+
+            ADDI(data) => ADDI(f(data)),
+            SUBI(data) => SUBI(f(data)),
+            LDAI(data) => LDAI(f(data)),
+            BWAI(data) => BWAI(f(data)),
+            BWOI(data) => BWOI(f(data)),
+            BWXI(data) => BWXI(f(data)),
+            MULI(data) => MULI(f(data)),
+            DIVI(data) => DIVI(f(data)),
+            MODI(data) => MODI(f(data)),
+            SHLI(data) => SHLI(f(data)),
+            SHRI(data) => SHRI(f(data)),
+            SARI(data) => SARI(f(data)),
+
+            ADDX(data) => ADDX(f(data)),
+            SUBX(data) => SUBX(f(data)),
+            STAX(data) => STAX(f(data)),
+            LDAX(data) => LDAX(f(data)),
+
+            DAT(data) => DAT(f(data)),
+        }
+    }
+}
+
+impl From<Instruction<i64>> for i64 {
+    fn from(instruction: Instruction<i64>) -> i64 {
+        use Instruction::*;
+        match instruction {
             HLT => 1,
             LDR => 900,
             INP => 901,
             OUT => 902,
+            INC => 921,
+            OTC => 922,
+            RET => 903,
+            PSH => 904,
+            POP => 905,
+            EI => 906,
+            DI => 907,
+            RTI => 908,
+            TAX => 910,
+            TXA => 911,
             BWN => 10000,
 
             ADD(addr) => 1000 + addr,
             SUB(addr) => 2000 + addr,
             STA(addr) => 3000 + addr,
+            STR(addr) => 4000 + addr,
             LDA(addr) => 5000 + addr,
             BRA(addr) => 6000 + addr,
             BRZ(addr) => 7000 + addr,
             BRP(addr) => 8000 + addr,
+            BRN(addr) => 9000 + addr,
+            BNZ(addr) => 43000 + addr,
+            BRC(addr) => 44000 + addr,
+            BRV(addr) => 45000 + addr,
+            CAL(addr) => 40000 + addr,
+            INPP(port) => 46000 + port,
+            OUTP(port) => 47000 + port,
+            LDX(addr) => 41000 + addr,
+            STX(addr) => 42000 + addr,
 
             BWA(addr) => 11000 + addr,
             BWO(addr) => 12000 + addr,
             BWX(addr) => 13000 + addr,
 
+            MUL(addr) => 14000 + addr,
+            DIV(addr) => 15000 + addr,
+            MOD(addr) => 16000 + addr,
+            SHL(addr) => 17000 + addr,
+            SHR(addr) => 18000 + addr,
+            SAR(addr) => 19000 + addr,
+
+            ADDI(value) => 21000 + value,
+            SUBI(value) => 22000 + value,
+            LDAI(value) => 25000 + value,
+            BWAI(value) => 31000 + value,
+            BWOI(value) => 32000 + value,
+            BWXI(value) => 33000 + value,
+            MULI(value) => 34000 + value,
+            DIVI(value) => 35000 + value,
+            MODI(value) => 36000 + value,
+            SHLI(value) => 37000 + value,
+            SHRI(value) => 38000 + value,
+            SARI(value) => 39000 + value,
+
+            ADDX(addr) => 51000 + addr,
+            SUBX(addr) => 52000 + addr,
+            STAX(addr) => 53000 + addr,
+            LDAX(addr) => 55000 + addr,
+
             // Not really an instruction, return the data
             DAT(data) => data,
         }
@@ -90,18 +351,60 @@ impl TryFrom<i64> for Instruction<i64> {
             900 => Ok(LDR),
             901 => Ok(INP),
             902 => Ok(OUT),
+            903 => Ok(RET),
+            904 => Ok(PSH),
+            905 => Ok(POP),
+            906 => Ok(EI),
+            907 => Ok(DI),
+            908 => Ok(RTI),
+            910 => Ok(TAX),
+            911 => Ok(TXA),
+            921 => Ok(INC),
+            922 => Ok(OTC),
             10000 => Ok(BWN),
             // Dynamic instructions
             1000..=1999 => Ok(ADD(value - 1000)),
             2000..=2999 => Ok(SUB(value - 2000)),
             3000..=3999 => Ok(STA(value - 3000)),
+            4000..=4999 => Ok(STR(value - 4000)),
             5000..=5999 => Ok(LDA(value - 5000)),
             6000..=6999 => Ok(BRA(value - 6000)),
             7000..=7999 => Ok(BRZ(value - 7000)),
             8000..=8999 => Ok(BRP(value - 8000)),
+            9000..=9999 => Ok(BRN(value - 9000)),
             11000..=11999 => Ok(BWA(value - 11000)),
             12000..=12999 => Ok(BWO(value - 12000)),
             13000..=13999 => Ok(BWX(value - 13000)),
+            14000..=14999 => Ok(MUL(value - 14000)),
+            15000..=15999 => Ok(DIV(value - 15000)),
+            16000..=16999 => Ok(MOD(value - 16000)),
+            17000..=17999 => Ok(SHL(value - 17000)),
+            18000..=18999 => Ok(SHR(value - 18000)),
+            19000..=19999 => Ok(SAR(value - 19000)),
+            21000..=21999 => Ok(ADDI(value - 21000)),
+            22000..=22999 => Ok(SUBI(value - 22000)),
+            25000..=25999 => Ok(LDAI(value - 25000)),
+            31000..=31999 => Ok(BWAI(value - 31000)),
+            32000..=32999 => Ok(BWOI(value - 32000)),
+            33000..=33999 => Ok(BWXI(value - 33000)),
+            34000..=34999 => Ok(MULI(value - 34000)),
+            35000..=35999 => Ok(DIVI(value - 35000)),
+            36000..=36999 => Ok(MODI(value - 36000)),
+            37000..=37999 => Ok(SHLI(value - 37000)),
+            38000..=38999 => Ok(SHRI(value - 38000)),
+            39000..=39999 => Ok(SARI(value - 39000)),
+            40000..=40999 => Ok(CAL(value - 40000)),
+            41000..=41999 => Ok(LDX(value - 41000)),
+            42000..=42999 => Ok(STX(value - 42000)),
+            43000..=43999 => Ok(BNZ(value - 43000)),
+            44000..=44999 => Ok(BRC(value - 44000)),
+            45000..=45999 => Ok(BRV(value - 45000)),
+            46000..=46999 => Ok(INPP(value - 46000)),
+            47000..=47999 => Ok(OUTP(value - 47000)),
+            51000..=51999 => Ok(ADDX(value - 51000)),
+            52000..=52999 => Ok(SUBX(value - 52000)),
+            53000..=53999 => Ok(STAX(value - 53000)),
+            55000..=55999 => Ok(LDAX(value - 55000)),
             _ => Err(()),
         }
     }
@@ -113,24 +416,69 @@ impl<Data: fmt::Display> fmt::Display for Instruction<Data> {
         match self {
             INP => write!(f, "INP"),
             OUT => write!(f, "OUT"),
+            INC => write!(f, "INC"),
+            OTC => write!(f, "OTC"),
             HLT => write!(f, "HLT"),
             LDR => write!(f, "LDR"),
+            RET => write!(f, "RET"),
+            PSH => write!(f, "PSH"),
+            POP => write!(f, "POP"),
+            EI => write!(f, "EI"),
+            DI => write!(f, "DI"),
+            RTI => write!(f, "RTI"),
+            TAX => write!(f, "TAX"),
+            TXA => write!(f, "TXA"),
 
             ADD(loc) => write!(f, "ADD {}", loc),
             SUB(loc) => write!(f, "SUB {}", loc),
 
             STA(loc) => write!(f, "STA {}", loc),
             LDA(loc) => write!(f, "LDA {}", loc),
+            STR(loc) => write!(f, "STR {}", loc),
 
             BRA(loc) => write!(f, "BRA {}", loc),
             BRZ(loc) => write!(f, "BRZ {}", loc),
             BRP(loc) => write!(f, "BRP {}", loc),
+            BRN(loc) => write!(f, "BRN {}", loc),
+            BNZ(loc) => write!(f, "BNZ {}", loc),
+            BRC(loc) => write!(f, "BRC {}", loc),
+            BRV(loc) => write!(f, "BRV {}", loc),
+            CAL(loc) => write!(f, "CAL {}", loc),
+            INPP(port) => write!(f, "INP {}", port),
+            OUTP(port) => write!(f, "OUT {}", port),
+            LDX(loc) => write!(f, "LDX {}", loc),
+            STX(loc) => write!(f, "STX {}", loc),
 
             BWN => write!(f, "BWN"),
             BWA(loc) => write!(f, "BWA {}", loc),
             BWO(loc) => write!(f, "BWO {}", loc),
             BWX(loc) => write!(f, "BWX {}", loc),
 
+            MUL(loc) => write!(f, "MUL {}", loc),
+            DIV(loc) => write!(f, "DIV {}", loc),
+            MOD(loc) => write!(f, "MOD {}", loc),
+            SHL(loc) => write!(f, "SHL {}", loc),
+            SHR(loc) => write!(f, "SHR {}", loc),
+            SAR(loc) => write!(f, "SAR {}", loc),
+
+            ADDI(value) => write!(f, "ADD #{}", value),
+            SUBI(value) => write!(f, "SUB #{}", value),
+            LDAI(value) => write!(f, "LDA #{}", value),
+            BWAI(value) => write!(f, "BWA #{}", value),
+            BWOI(value) => write!(f, "BWO #{}", value),
+            BWXI(value) => write!(f, "BWX #{}", value),
+            MULI(value) => write!(f, "MUL #{}", value),
+            DIVI(value) => write!(f, "DIV #{}", value),
+            MODI(value) => write!(f, "MOD #{}", value),
+            SHLI(value) => write!(f, "SHL #{}", value),
+            SHRI(value) => write!(f, "SHR #{}", value),
+            SARI(value) => write!(f, "SAR #{}", value),
+
+            ADDX(loc) => write!(f, "ADD {},X", loc),
+            SUBX(loc) => write!(f, "SUB {},X", loc),
+            STAX(loc) => write!(f, "STA {},X", loc),
+            LDAX(loc) => write!(f, "LDA {},X", loc),
+
             DAT(loc) => write!(f, "DAT {}", loc),
         }
     }
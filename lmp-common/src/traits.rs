@@ -1,4 +1,5 @@
-use std::fmt::{Debug, Formatter};
+use alloc::boxed::Box;
+use core::fmt::{Debug, Formatter};
 
 pub trait ClonableFn<P, R>: Fn(P) -> R {
     fn clone_box<'a>(&self) -> Box<dyn 'a + ClonableFn<P, R>>
@@ -19,7 +20,7 @@ impl<'a, F: 'a, R: 'a> Clone for Box<dyn 'a + ClonableFn<F, R>> {
 }
 
 impl<F, R> Debug for Box<dyn ClonableFn<F, R>> {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         // Not the best debug output, lacks detail, but it's alright for our purposes
         write!(f, "Box<dyn ClonableFn<F, R>>")
     }
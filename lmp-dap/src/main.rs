@@ -0,0 +1,85 @@
+/*
+    littlemanplus — a Rust-based Little Man Computer simulator
+    Copyright (C) 2025 azyrite
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+mod protocol;
+mod session;
+mod target;
+
+use clap::Parser;
+use session::Session;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::mpsc;
+use target::Target;
+
+/// Debug adapter for LMC programs, speaking the Debug Adapter Protocol over stdin and stdout
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Args {
+    /// Program to debug when the editor attaches rather than launches
+    program: Option<PathBuf>,
+
+    /// Wait for the editor to connect to this address, e.g. `127.0.0.1:4711`, instead of using
+    /// stdin and stdout
+    #[arg(long, value_name = "ADDRESS")]
+    listen: Option<SocketAddr>,
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+
+    let attach = match args.program.as_deref().map(Target::assemble).transpose() {
+        Ok(attach) => attach,
+        Err(error) => {
+            eprintln!("{error}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let result = match args.listen {
+        Some(address) => TcpListener::bind(address).and_then(|listener| {
+            eprintln!("Listening on {}", listener.local_addr()?);
+            let (stream, _) = listener.accept()?;
+            serve(BufReader::new(stream.try_clone()?), stream, attach)
+        }),
+        None => serve(BufReader::new(io::stdin()), io::stdout(), attach),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("lmp-dap: {error}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Debug a session, reading requests on another thread so the program can be paused while it runs
+fn serve(mut reader: impl BufRead + Send + 'static, writer: impl Write, attach: Option<Target>) -> io::Result<()> {
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        while let Ok(Some(message)) = protocol::read_message(&mut reader) {
+            if sender.send(message).is_err() {
+                break;
+            }
+        }
+    });
+
+    Session::new(writer, attach).run(receiver)
+}
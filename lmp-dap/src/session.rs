@@ -0,0 +1,528 @@
+//! Handling of requests from the editor, and running the program between them
+
+use crate::protocol::{Request, Sender};
+use crate::target::Target;
+use lmp_common::MEMORY_SIZE;
+use lmp_common::assembly::Instruction;
+use lmp_vm::{InputKind, VirtualMachine, VirtualMachineStep};
+use serde_json::{Value, json};
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, TryRecvError};
+
+/// The program is run in batches of this many steps, checking for requests such as `pause` in
+/// between
+const BATCH: usize = 10_000;
+/// The VM has a single thread of execution
+const THREAD: i64 = 1;
+const REGISTERS: i64 = 1;
+const MEMORY: i64 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Waiting for the program to be launched and breakpoints to be configured
+    Configuring,
+    Stopped,
+    Running(Resume),
+    Exited,
+}
+
+/// How the program was resumed, which decides when it next stops
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Resume {
+    /// Run until a breakpoint
+    Continue,
+    /// Execute one instruction
+    Step,
+    /// Execute one instruction, running through the subroutine if it is `CAL`
+    Over { address: usize, stack_pointer: usize },
+    /// Run until the current subroutine returns
+    Out { stack_pointer: usize },
+}
+
+pub struct Session<W> {
+    out: Sender<W>,
+    vm: VirtualMachine,
+    target: Option<Target>,
+    /// Program given on the command line, used when the editor attaches
+    attach: Option<Target>,
+    /// Addresses of breakpoints in each file
+    breakpoints: HashMap<PathBuf, Vec<usize>>,
+    /// Addresses of every breakpoint
+    stops: HashSet<usize>,
+    stop_on_entry: bool,
+    state: State,
+    /// The kind of input the program is waiting for, if any
+    input: Option<InputKind>,
+}
+
+impl<W: Write> Session<W> {
+    pub fn new(writer: W, attach: Option<Target>) -> Self {
+        Self {
+            out: Sender::new(writer),
+            vm: VirtualMachine::new(),
+            target: None,
+            attach,
+            breakpoints: HashMap::new(),
+            stops: HashSet::new(),
+            stop_on_entry: false,
+            state: State::Configuring,
+            input: None,
+        }
+    }
+
+    /// Handle messages until the editor disconnects, running the program whenever it is not
+    /// stopped
+    pub fn run(mut self, messages: Receiver<Value>) -> io::Result<()> {
+        loop {
+            let running = matches!(self.state, State::Running(_)) && self.input.is_none();
+            let message = match running {
+                true => match messages.try_recv() {
+                    Ok(message) => Some(message),
+                    Err(TryRecvError::Empty) => None,
+                    Err(TryRecvError::Disconnected) => return Ok(()),
+                },
+                false => match messages.recv() {
+                    Ok(message) => Some(message),
+                    Err(_) => return Ok(()),
+                },
+            };
+
+            match message {
+                // Only requests are expected, as no requests are sent to the editor
+                Some(message) if message["type"] == "request" => {
+                    let Ok(request) = serde_json::from_value(message) else { continue };
+                    if !self.request(request)? {
+                        return Ok(());
+                    }
+                }
+                Some(_) => {}
+                None => self.run_batch()?,
+            }
+        }
+    }
+
+    /// Respond to a request, returning `false` once the editor has disconnected
+    fn request(&mut self, request: Request) -> io::Result<bool> {
+        let arguments = &request.arguments;
+        let result = match request.command.as_str() {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsSetVariable": true,
+                "supportsTerminateRequest": true,
+            })),
+            "launch" => self.launch(arguments),
+            "attach" => self.attach(arguments),
+            "setBreakpoints" => self.set_breakpoints(arguments),
+            "setExceptionBreakpoints" => Ok(Value::Null),
+            "configurationDone" => self.target.as_ref().map(|_| Value::Null).ok_or_else(not_loaded),
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD, "name": "main" }] })),
+            "stackTrace" => Ok(self.stack_trace()),
+            "scopes" => Ok(json!({ "scopes": [
+                { "name": "Registers", "presentationHint": "registers", "variablesReference": REGISTERS, "expensive": false },
+                { "name": "Memory", "variablesReference": MEMORY, "indexedVariables": MEMORY_SIZE, "expensive": false },
+            ]})),
+            "variables" => Ok(self.variables(arguments)),
+            "setVariable" => self.set_variable(arguments),
+            "source" => self.source(arguments),
+            "continue" => self.resume(Resume::Continue).map(|()| json!({ "allThreadsContinued": true })),
+            "next" => self.step_over().map(|()| Value::Null),
+            "stepIn" => self.resume(Resume::Step).map(|()| Value::Null),
+            "stepOut" => {
+                let stack_pointer = self.vm.stack_pointer();
+                self.resume(Resume::Out { stack_pointer }).map(|()| Value::Null)
+            }
+            "pause" => Ok(Value::Null),
+            "evaluate" => self.evaluate(arguments),
+            "terminate" | "disconnect" => Ok(Value::Null),
+            command => Err(format!("Unsupported request `{command}`")),
+        };
+
+        let success = result.is_ok();
+        self.out.respond(&request, result)?;
+
+        // Events that must follow the response
+        match request.command.as_str() {
+            "launch" | "attach" if success => self.out.event("initialized", Value::Null)?,
+            "configurationDone" if success => self.start()?,
+            "pause" if matches!(self.state, State::Running(_)) => self.stop("pause", None)?,
+            "terminate" if self.state != State::Exited => {
+                self.state = State::Exited;
+                self.out.event("terminated", Value::Null)?;
+            }
+            "disconnect" => return Ok(false),
+            _ => {}
+        }
+
+        Ok(true)
+    }
+
+    fn launch(&mut self, arguments: &Value) -> Result<Value, String> {
+        let Some(program) = arguments["program"].as_str() else {
+            return Err("The `program` to debug must be given".to_string());
+        };
+
+        let target = Target::assemble(Path::new(program))?;
+        self.load(target, arguments)
+    }
+
+    fn attach(&mut self, arguments: &Value) -> Result<Value, String> {
+        let Some(target) = self.attach.take() else {
+            return Err("lmp-dap was not started with a program to attach to".to_string());
+        };
+
+        self.load(target, arguments)
+    }
+
+    fn load(&mut self, target: Target, arguments: &Value) -> Result<Value, String> {
+        self.vm = VirtualMachine::new();
+        self.vm.load(target.program().cells.iter().map(|cell| (cell.address, cell.instruction)));
+        self.target = Some(target);
+        self.stop_on_entry = arguments["stopOnEntry"].as_bool().unwrap_or(false);
+
+        Ok(Value::Null)
+    }
+
+    /// Start running the program once the editor has set its breakpoints
+    fn start(&mut self) -> io::Result<()> {
+        if self.stop_on_entry {
+            self.stop("entry", None)
+        } else if self.stops.contains(&self.vm.program_counter()) {
+            self.stop("breakpoint", None)
+        } else {
+            self.state = State::Running(Resume::Continue);
+            Ok(())
+        }
+    }
+
+    fn set_breakpoints(&mut self, arguments: &Value) -> Result<Value, String> {
+        let Some(path) = arguments["source"]["path"].as_str() else {
+            return Err("Breakpoints can only be set in files".to_string());
+        };
+        let target = self.target.as_ref().ok_or_else(not_loaded)?;
+
+        let mut addresses = Vec::new();
+        let breakpoints: Vec<_> = arguments["breakpoints"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|breakpoint| {
+                let line = breakpoint["line"].as_u64().unwrap_or(0) as usize;
+                match target.breakpoint(Path::new(path), line) {
+                    Some((line, mut at)) => {
+                        addresses.append(&mut at);
+                        json!({ "verified": true, "line": line })
+                    }
+                    None => json!({ "verified": false, "line": line, "message": "No instructions on or after this line" }),
+                }
+            })
+            .collect();
+
+        self.breakpoints.insert(PathBuf::from(path), addresses);
+        self.stops = self.breakpoints.values().flatten().copied().collect();
+
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    fn resume(&mut self, resume: Resume) -> Result<(), String> {
+        match self.state {
+            State::Stopped => {
+                self.state = State::Running(resume);
+                Ok(())
+            }
+            State::Running(_) => Err("The program is already running".to_string()),
+            State::Configuring => Err(not_loaded()),
+            State::Exited => Err("The program has exited".to_string()),
+        }
+    }
+
+    fn step_over(&mut self) -> Result<(), String> {
+        let program_counter = self.vm.program_counter();
+        let current = self.vm.memory().get(program_counter).map(|cell| Instruction::try_from(cell.data));
+
+        match current {
+            Some(Ok(Instruction::CAL(_))) => self.resume(Resume::Over {
+                address: program_counter + 1,
+                stack_pointer: self.vm.stack_pointer(),
+            }),
+            _ => self.resume(Resume::Step),
+        }
+    }
+
+    fn stop(&mut self, reason: &str, text: Option<String>) -> io::Result<()> {
+        self.state = State::Stopped;
+        let mut body = json!({ "reason": reason, "threadId": THREAD, "allThreadsStopped": true });
+        if let Some(text) = text {
+            body["text"] = json!(text);
+        }
+
+        self.out.event("stopped", body)
+    }
+
+    /// Run the program for a batch of steps, stopping early at breakpoints, when a step has
+    /// finished, or when it needs input
+    fn run_batch(&mut self) -> io::Result<()> {
+        let State::Running(resume) = self.state else { return Ok(()) };
+
+        for _ in 0..BATCH {
+            match self.vm.step() {
+                VirtualMachineStep::Output(value) => self.out.output("stdout", format!("{value}\n"))?,
+                VirtualMachineStep::OutputCharacter(character) => self.out.output("stdout", character)?,
+                VirtualMachineStep::InputRequired(kind) => {
+                    self.input = Some(kind);
+                    let expected = match kind {
+                        InputKind::Number => "a number",
+                        InputKind::Character => "a character",
+                    };
+                    return self.out.output("console", format!("Waiting for input, enter {expected} in the debug console\n"));
+                }
+                VirtualMachineStep::Halted => {
+                    self.state = State::Exited;
+                    self.out.output("console", format!("Halted after {} cycles\n", self.vm.cycles()))?;
+                    self.out.event("exited", json!({ "exitCode": 0 }))?;
+                    return self.out.event("terminated", Value::Null);
+                }
+                VirtualMachineStep::Fault(fault) => {
+                    self.out.output("stderr", format!("{fault}\n"))?;
+                    return self.stop("exception", Some(fault.to_string()));
+                }
+                _ => {}
+            }
+
+            let program_counter = self.vm.program_counter();
+            let stack_pointer = self.vm.stack_pointer();
+            let finished = match resume {
+                Resume::Continue => false,
+                Resume::Step => true,
+                Resume::Over { address, stack_pointer: from } => program_counter == address && stack_pointer >= from,
+                Resume::Out { stack_pointer: from } => stack_pointer > from,
+            };
+
+            if finished {
+                return self.stop("step", None);
+            } else if self.stops.contains(&program_counter) {
+                return self.stop("breakpoint", None);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The VM has no call frames, so the stack trace is the current instruction
+    fn stack_trace(&self) -> Value {
+        let program_counter = self.vm.program_counter();
+        let instruction = self
+            .vm
+            .memory()
+            .get(program_counter)
+            .and_then(|cell| Instruction::try_from(cell.data).ok())
+            .map_or("???".to_string(), |instruction| instruction.to_string());
+
+        let mut frame = json!({
+            "id": 0,
+            "name": format!("{program_counter}: {instruction}"),
+            "line": 0,
+            "column": 0,
+            "instructionPointerReference": program_counter.to_string(),
+        });
+        if let Some(target) = &self.target
+            && let Some((id, line)) = target.location(program_counter)
+        {
+            frame["source"] = target.source(id);
+            frame["line"] = json!(line);
+            frame["column"] = json!(1);
+        }
+
+        json!({ "stackFrames": [frame], "totalFrames": 1 })
+    }
+
+    fn variables(&self, arguments: &Value) -> Value {
+        let variables: Vec<_> = match arguments["variablesReference"].as_i64() {
+            Some(REGISTERS) => [
+                ("Accumulator", self.vm.accumulator()),
+                ("Program counter", self.vm.program_counter() as i64),
+                ("Index", self.vm.index()),
+                ("Stack pointer", self.vm.stack_pointer() as i64),
+                ("Cycles", self.vm.cycles()),
+            ]
+            .into_iter()
+            .map(|(name, value)| json!({ "name": name, "value": value.to_string(), "variablesReference": 0 }))
+            .collect(),
+            Some(MEMORY) => {
+                let start = arguments["start"].as_u64().map_or(0, |start| start as usize).min(MEMORY_SIZE);
+                let count = arguments["count"].as_u64().map_or(MEMORY_SIZE, |count| count as usize);
+
+                (start..start.saturating_add(count).min(MEMORY_SIZE))
+                    .map(|address| {
+                        json!({ "name": address.to_string(), "value": self.cell(address), "variablesReference": 0 })
+                    })
+                    .collect()
+            }
+            _ => Vec::new(),
+        };
+
+        json!({ "variables": variables })
+    }
+
+    /// Value of a memory cell, along with the instruction it encodes if it holds code
+    fn cell(&self, address: usize) -> String {
+        let data = self.vm.memory()[address].data;
+        match Instruction::try_from(data) {
+            Ok(instruction) if self.vm.code()[address] => format!("{data} ({instruction})"),
+            _ => data.to_string(),
+        }
+    }
+
+    fn set_variable(&mut self, arguments: &Value) -> Result<Value, String> {
+        if arguments["variablesReference"].as_i64() != Some(MEMORY) {
+            return Err("Only memory can be changed".to_string());
+        }
+
+        let name = arguments["name"].as_str().unwrap_or_default();
+        let address = name.parse().ok().filter(|&address| address < MEMORY_SIZE);
+        let Some(address) = address else { return Err(format!("`{name}` is not an address")) };
+        let value = arguments["value"].as_str().unwrap_or_default().trim();
+        let Ok(value) = value.parse() else { return Err(format!("`{value}` is not a number")) };
+
+        self.vm.set_memory(address, value);
+        Ok(json!({ "value": self.cell(address) }))
+    }
+
+    fn source(&self, arguments: &Value) -> Result<Value, String> {
+        let reference = arguments["sourceReference"].as_u64().unwrap_or(0) as usize;
+        let text = self.target.as_ref().and_then(|target| target.source_text(reference));
+
+        text.map(|text| json!({ "content": text })).ok_or_else(|| "No such source".to_string())
+    }
+
+    /// Text entered in the debug console is queued as input for the program: numbers separated
+    /// by spaces or commas, or otherwise each character
+    fn evaluate(&mut self, arguments: &Value) -> Result<Value, String> {
+        if arguments["context"].as_str().is_some_and(|context| context != "repl") {
+            return Err("Expressions cannot be evaluated, only input can be entered".to_string());
+        }
+
+        let text = arguments["expression"].as_str().unwrap_or_default();
+        let numbers: Result<Vec<i64>, _> = text
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|number| !number.is_empty())
+            .map(str::parse)
+            .collect();
+
+        let inputs = match numbers {
+            Ok(numbers) if self.input != Some(InputKind::Character) => numbers,
+            _ if self.input == Some(InputKind::Number) => return Err(format!("`{}` is not a number", text.trim())),
+            _ => text.chars().map(|character| character as i64).collect(),
+        };
+        if inputs.is_empty() {
+            return Err("No input was entered".to_string());
+        }
+
+        let count = inputs.len();
+        self.vm.input_all(inputs);
+        self.input = None;
+
+        let plural = if count == 1 { "" } else { "s" };
+        Ok(json!({ "result": format!("Queued {count} input{plural}"), "variablesReference": 0 }))
+    }
+}
+
+fn not_loaded() -> String {
+    "No program has been launched".to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::mpsc;
+
+    /// Collects each message sent to the editor
+    struct Messages(mpsc::Sender<Value>);
+
+    impl Write for Messages {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            // Each message is written at once, so the body follows the first blank line
+            let text = std::str::from_utf8(buf).unwrap();
+            let (_, body) = text.split_once("\r\n\r\n").unwrap();
+            self.0.send(serde_json::from_str(body).unwrap()).unwrap();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_session() {
+        let path = std::env::temp_dir().join(format!("lmp-dap-test-{}.lmc", std::process::id()));
+        std::fs::write(&path, "        INP\n        STA value\n\n        OUT\n        HLT\nvalue   DAT\n").unwrap();
+
+        let (requests, receiver) = mpsc::channel();
+        let (sender, events) = mpsc::channel();
+        let session = std::thread::spawn(move || Session::new(Messages(sender), None).run(receiver));
+
+        let mut seq = 0;
+        let mut request = |command: &str, arguments: Value| {
+            seq += 1;
+            let request = json!({ "seq": seq, "type": "request", "command": command, "arguments": arguments });
+            requests.send(request).unwrap();
+        };
+        // Receive messages up to and including the one matching `until`
+        let expect = |until: &dyn Fn(&Value) -> bool| {
+            let mut received = Vec::new();
+            loop {
+                let message = events.recv_timeout(std::time::Duration::from_secs(5)).unwrap();
+                let done = until(&message);
+                received.push(message);
+                if done {
+                    return received;
+                }
+            }
+        };
+        let response = |command: &'static str| move |message: &Value| message["command"] == command;
+        let event = |event: &'static str| move |message: &Value| message["event"] == event;
+
+        request("initialize", json!({ "adapterID": "lmp" }));
+        expect(&response("initialize"));
+        request("launch", json!({ "program": path }));
+        expect(&event("initialized"));
+
+        // Line 3 is blank, so the breakpoint moves to the `OUT` on line 4
+        request("setBreakpoints", json!({ "source": { "path": path }, "breakpoints": [{ "line": 3 }] }));
+        let breakpoints = expect(&response("setBreakpoints"));
+        assert_eq!(breakpoints[0]["body"]["breakpoints"][0], json!({ "verified": true, "line": 4 }));
+
+        request("configurationDone", Value::Null);
+        let waiting = expect(&|message| message["body"]["category"] == "console");
+        assert!(waiting.last().unwrap()["body"]["output"].as_str().unwrap().starts_with("Waiting for input"));
+
+        request("evaluate", json!({ "expression": "42", "context": "repl" }));
+        let stopped = expect(&event("stopped"));
+        assert_eq!(stopped.last().unwrap()["body"]["reason"], "breakpoint");
+
+        request("stackTrace", json!({ "threadId": THREAD }));
+        let trace = expect(&response("stackTrace"));
+        let frame = &trace.last().unwrap()["body"]["stackFrames"][0];
+        assert_eq!(frame["line"], 4);
+        assert_eq!(frame["name"], "2: OUT");
+
+        request("variables", json!({ "variablesReference": MEMORY, "start": 4, "count": 1 }));
+        let memory = expect(&response("variables"));
+        assert_eq!(memory.last().unwrap()["body"]["variables"][0]["value"], "42");
+
+        // Counts past the end of memory are cut short instead of overflowing
+        request("variables", json!({ "variablesReference": MEMORY, "start": 500, "count": u64::MAX }));
+        let memory = expect(&response("variables"));
+        assert_eq!(memory.last().unwrap()["body"]["variables"].as_array().unwrap().len(), MEMORY_SIZE - 500);
+
+        request("continue", json!({ "threadId": THREAD }));
+        let output = expect(&event("terminated"));
+        assert!(output.iter().any(|message| message["body"]["category"] == "stdout" && message["body"]["output"] == "42\n"));
+
+        request("disconnect", Value::Null);
+        session.join().unwrap().unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+}
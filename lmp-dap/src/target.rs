@@ -0,0 +1,95 @@
+//! The program being debugged, and mapping between its memory and the source it came from
+
+use lmp_common::MEMORY_SIZE;
+use lmp_lang::parser::assemble_sources;
+use lmp_lang::{Program, SourceId, SourceMap, SourceOrigin};
+use serde_json::{Value, json};
+use std::path::Path;
+
+/// An assembled program along with its sources
+pub struct Target {
+    sources: SourceMap,
+    program: Program,
+    /// Index into the program's cells of the cell at each address
+    cells: Vec<Option<usize>>,
+}
+
+impl Target {
+    /// Assemble a program from a file
+    pub fn assemble(path: &Path) -> Result<Self, String> {
+        let mut sources = SourceMap::new();
+        let root = sources
+            .add_file(path)
+            .map_err(|error| format!("Could not read {}: {error}", path.display()))?;
+        let program = assemble_sources(&mut sources, root).map_err(|errors| {
+            let errors: Vec<_> = errors.iter().map(|error| error.report(&sources)).collect();
+            format!("Could not assemble the program.\n{}", errors.join("\n"))
+        })?;
+
+        let mut cells = vec![None; MEMORY_SIZE];
+        for (index, cell) in program.cells.iter().enumerate() {
+            cells[cell.address] = Some(index);
+        }
+
+        Ok(Self { sources, program, cells })
+    }
+
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    /// File and line (starting from 1) of the line that emitted the cell at an address
+    pub fn location(&self, address: usize) -> Option<(SourceId, usize)> {
+        let cell = &self.program.cells[(*self.cells.get(address)?)?];
+        let (id, offset) = self.sources.lookup(cell.span.start)?;
+
+        Some((id, self.sources.file(id).line_col(offset).0))
+    }
+
+    /// DAP `Source` describing a file. Files from the standard library are not on disk, so they
+    /// are given a reference with which the editor can ask for their text
+    pub fn source(&self, id: SourceId) -> Value {
+        let file = self.sources.file(id);
+        match &file.origin {
+            SourceOrigin::File(path) => json!({
+                "name": path.file_name().map(|name| name.to_string_lossy()),
+                "path": path,
+            }),
+            origin => json!({
+                "name": origin.to_string(),
+                "sourceReference": self.sources.offset(id) + 1,
+            }),
+        }
+    }
+
+    /// Text of a file given the reference from [`Target::source`]
+    pub fn source_text(&self, reference: usize) -> Option<&str> {
+        let (id, offset) = self.sources.lookup(reference.checked_sub(1)?)?;
+        (offset == 0).then(|| self.sources.file(id).text.as_str())
+    }
+
+    /// Addresses of the instructions emitted by a line of a file. If the line has none, the
+    /// nearest following line that does is used instead, and returned along with its addresses
+    pub fn breakpoint(&self, path: &Path, line: usize) -> Option<(usize, Vec<usize>)> {
+        let path = path.canonicalize().ok()?;
+        let id = self.sources.find(&SourceOrigin::File(path))?;
+
+        let lines: Vec<(usize, usize)> = self
+            .program
+            .code()
+            .filter_map(|address| match self.location(address) {
+                Some((file, code_line)) if file == id && code_line >= line => Some((code_line, address)),
+                _ => None,
+            })
+            .collect();
+
+        let nearest = lines.iter().map(|&(code_line, _)| code_line).min()?;
+        let addresses = lines
+            .into_iter()
+            .filter(|&(code_line, _)| code_line == nearest)
+            .map(|(_, address)| address)
+            .collect();
+
+        Some((nearest, addresses))
+    }
+}
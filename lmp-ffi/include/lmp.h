@@ -0,0 +1,135 @@
+/* C interface to the Little Man Plus virtual machine, generated by cbindgen from lmp-ffi */
+
+#ifndef LMP_H
+#define LMP_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+// What happened when the VM was stepped or run
+typedef enum LmpStatus {
+  // An instruction was executed
+  LMP_STATUS_ADVANCED,
+  // A value was output, and can be taken with [`lmp_vm_poll_output`]
+  LMP_STATUS_OUTPUT,
+  // The program needs input, given with [`lmp_vm_push_input`]
+  LMP_STATUS_INPUT_REQUIRED,
+  // The program is waiting for a device
+  LMP_STATUS_WAITING,
+  // An interrupt handler was entered
+  LMP_STATUS_INTERRUPTED,
+  LMP_STATUS_HALTED,
+  // The program faulted, see [`lmp_vm_last_error`]
+  LMP_STATUS_FAULT,
+  // [`lmp_vm_run`] ran for the maximum number of cycles
+  LMP_STATUS_CYCLE_LIMIT,
+  // The function could not be carried out, see [`lmp_vm_last_error`]
+  LMP_STATUS_ERROR,
+} LmpStatus;
+
+// Opaque handle to a virtual machine
+typedef struct LmpVm LmpVm;
+
+// A value output by the program
+typedef struct LmpOutput {
+  // The value, or the code point of the character
+  int64_t value;
+  // Whether it was output by `OTC`
+  bool is_character;
+} LmpOutput;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Create a VM with the default configuration and no program loaded. Returns null if it could not
+// be created
+struct LmpVm *lmp_vm_create(void);
+
+// Free a VM
+//
+// # Safety
+// `vm` must be null or a handle returned by [`lmp_vm_create`] that has not been destroyed
+void lmp_vm_destroy(struct LmpVm *vm);
+
+// Assemble a NUL-terminated program and load it, resetting the VM. Returns `false` if the
+// program has errors
+//
+// # Safety
+// `vm` must be a valid handle, and `source` must be a valid C string
+bool lmp_vm_load_source(struct LmpVm *vm, const char *source);
+
+// Load `length` cells of an assembled memory image starting from address 0, resetting the VM.
+// Returns `false` if the image does not fit into memory
+//
+// # Safety
+// `vm` must be a valid handle, and `image` must point to `length` values
+bool lmp_vm_load_image(struct LmpVm *vm, const int64_t *image, size_t length);
+
+// Execute one instruction
+//
+// # Safety
+// `vm` must be a valid handle
+enum LmpStatus lmp_vm_step(struct LmpVm *vm);
+
+// Run until the program halts, faults, needs input, or has run for `max_cycles` cycles. Outputs
+// are queued to be taken with [`lmp_vm_poll_output`]
+//
+// # Safety
+// `vm` must be a valid handle
+enum LmpStatus lmp_vm_run(struct LmpVm *vm, uint64_t max_cycles);
+
+// Read a cell of memory into `value`. Returns `false` if the address is out of range
+//
+// # Safety
+// `vm` must be a valid handle, and `value` must be valid for writes
+bool lmp_vm_read_memory(struct LmpVm *vm, size_t address, int64_t *value);
+
+// Overwrite a cell of memory, bypassing devices and protection. Returns `false` if the address
+// is out of range
+//
+// # Safety
+// `vm` must be a valid handle
+bool lmp_vm_write_memory(struct LmpVm *vm, size_t address, int64_t value);
+
+// Queue a value to be read by `INP`, or the code point of a character to be read by `INC`
+//
+// # Safety
+// `vm` must be a valid handle
+void lmp_vm_push_input(struct LmpVm *vm, int64_t value);
+
+// Take the oldest output into `output`. Returns `false` if there is none
+//
+// # Safety
+// `vm` must be a valid handle, and `output` must be valid for writes
+bool lmp_vm_poll_output(struct LmpVm *vm, struct LmpOutput *output);
+
+// Description of the last error or fault, or null if there has been none. The string is owned
+// by the VM and stays valid until the next call that fails, loads a program, or destroys it
+//
+// # Safety
+// `vm` must be a valid handle
+const char *lmp_vm_last_error(const struct LmpVm *vm);
+
+// # Safety
+// `vm` must be a valid handle
+int64_t lmp_vm_accumulator(const struct LmpVm *vm);
+
+// # Safety
+// `vm` must be a valid handle
+size_t lmp_vm_program_counter(const struct LmpVm *vm);
+
+// # Safety
+// `vm` must be a valid handle
+int64_t lmp_vm_cycles(const struct LmpVm *vm);
+
+// # Safety
+// `vm` must be a valid handle
+bool lmp_vm_halted(const struct LmpVm *vm);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* LMP_H */
@@ -0,0 +1,365 @@
+//! C ABI for the virtual machine, built as a shared and a static library
+//!
+//! The header in `include/lmp.h` is generated with cbindgen, and checked to be up to date by the
+//! tests. Every function takes the handle returned by [`lmp_vm_create`], and functions that can
+//! fail return [`LmpStatus::Error`] or `false` and describe the failure in [`lmp_vm_last_error`].
+//! Panics are caught rather than unwinding into C, and are reported the same way.
+use lmp_common::MEMORY_SIZE;
+use lmp_common::assembly::Instruction;
+use lmp_vm::{VirtualMachine, VirtualMachineStep};
+use std::collections::VecDeque;
+use std::any::Any;
+use std::ffi::{CStr, CString, c_char};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+/// Opaque handle to a virtual machine
+pub struct LmpVm {
+    vm: VirtualMachine,
+    /// Outputs not yet taken with [`lmp_vm_poll_output`]
+    outputs: VecDeque<LmpOutput>,
+    /// Description of the last error or fault
+    error: Option<CString>,
+}
+
+impl LmpVm {
+    fn set_error(&mut self, error: impl Into<String>) {
+        // Interior NULs would end the message early, so replace them
+        let error = error.into().replace('\0', " ");
+        self.error = Some(CString::new(error).expect("NULs have been removed"));
+    }
+
+    fn step(&mut self) -> LmpStatus {
+        match self.vm.step() {
+            VirtualMachineStep::Output(value) => {
+                self.outputs.push_back(LmpOutput { value, is_character: false });
+                LmpStatus::Output
+            }
+            VirtualMachineStep::OutputCharacter(character) => {
+                self.outputs.push_back(LmpOutput { value: character as i64, is_character: true });
+                LmpStatus::Output
+            }
+            VirtualMachineStep::InputRequired(_) => LmpStatus::InputRequired,
+            VirtualMachineStep::Waiting => LmpStatus::Waiting,
+            VirtualMachineStep::Interrupted(_) => LmpStatus::Interrupted,
+            VirtualMachineStep::Halted => LmpStatus::Halted,
+            VirtualMachineStep::Fault(fault) => {
+                self.set_error(fault.to_string());
+                LmpStatus::Fault
+            }
+            _ => LmpStatus::Advanced,
+        }
+    }
+}
+
+/// What happened when the VM was stepped or run
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LmpStatus {
+    /// An instruction was executed
+    Advanced,
+    /// A value was output, and can be taken with [`lmp_vm_poll_output`]
+    Output,
+    /// The program needs input, given with [`lmp_vm_push_input`]
+    InputRequired,
+    /// The program is waiting for a device
+    Waiting,
+    /// An interrupt handler was entered
+    Interrupted,
+    Halted,
+    /// The program faulted, see [`lmp_vm_last_error`]
+    Fault,
+    /// [`lmp_vm_run`] ran for the maximum number of cycles
+    CycleLimit,
+    /// The function could not be carried out, see [`lmp_vm_last_error`]
+    Error,
+}
+
+/// A value output by the program
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LmpOutput {
+    /// The value, or the code point of the character
+    pub value: i64,
+    /// Whether it was output by `OTC`
+    pub is_character: bool,
+}
+
+/// Create a VM with the default configuration and no program loaded. Returns null if it could not
+/// be created
+#[unsafe(no_mangle)]
+pub extern "C" fn lmp_vm_create() -> *mut LmpVm {
+    guard(ptr::null_mut(), || {
+        Box::into_raw(Box::new(LmpVm {
+            vm: VirtualMachine::new(),
+            outputs: VecDeque::new(),
+            error: None,
+        }))
+    })
+}
+
+/// Free a VM
+///
+/// # Safety
+/// `vm` must be null or a handle returned by [`lmp_vm_create`] that has not been destroyed
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lmp_vm_destroy(vm: *mut LmpVm) {
+    if !vm.is_null() {
+        guard((), || drop(unsafe { Box::from_raw(vm) }));
+    }
+}
+
+/// Assemble a NUL-terminated program and load it, resetting the VM. Returns `false` if the
+/// program has errors
+///
+/// # Safety
+/// `vm` must be a valid handle, and `source` must be a valid C string
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lmp_vm_load_source(vm: *mut LmpVm, source: *const c_char) -> bool {
+    unsafe {
+        with_vm(vm, false, |vm| {
+            if source.is_null() {
+                vm.set_error("source is null");
+                return false;
+            }
+
+            let Ok(source) = CStr::from_ptr(source).to_str() else {
+                vm.set_error("source is not valid UTF-8");
+                return false;
+            };
+
+            match vm.vm.compile(source) {
+                Ok(()) => {
+                    vm.outputs.clear();
+                    vm.error = None;
+                    true
+                }
+                Err(error) => {
+                    vm.set_error(error.to_string());
+                    false
+                }
+            }
+        })
+    }
+}
+
+/// Load `length` cells of an assembled memory image starting from address 0, resetting the VM.
+/// Returns `false` if the image does not fit into memory
+///
+/// # Safety
+/// `vm` must be a valid handle, and `image` must point to `length` values
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lmp_vm_load_image(vm: *mut LmpVm, image: *const i64, length: usize) -> bool {
+    unsafe {
+        with_vm(vm, false, |vm| {
+            if length > MEMORY_SIZE {
+                vm.set_error(format!("image of {length} cells does not fit into memory ({MEMORY_SIZE} cells)"));
+                return false;
+            }
+
+            let image = match image.is_null() {
+                true if length > 0 => {
+                    vm.set_error("image is null");
+                    return false;
+                }
+                true => &[][..],
+                false => std::slice::from_raw_parts(image, length),
+            };
+
+            // The image does not say which cells are instructions, so none are treated as code
+            vm.vm.load(image.iter().enumerate().map(|(address, &data)| (address, Instruction::DAT(data))));
+            vm.outputs.clear();
+            vm.error = None;
+            true
+        })
+    }
+}
+
+/// Execute one instruction
+///
+/// # Safety
+/// `vm` must be a valid handle
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lmp_vm_step(vm: *mut LmpVm) -> LmpStatus {
+    unsafe { with_vm(vm, LmpStatus::Error, LmpVm::step) }
+}
+
+/// Run until the program halts, faults, needs input, or has run for `max_cycles` cycles. Outputs
+/// are queued to be taken with [`lmp_vm_poll_output`]
+///
+/// # Safety
+/// `vm` must be a valid handle
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lmp_vm_run(vm: *mut LmpVm, max_cycles: u64) -> LmpStatus {
+    unsafe {
+        with_vm(vm, LmpStatus::Error, |vm| {
+            for _ in 0..max_cycles {
+                let status = vm.step();
+                if matches!(status, LmpStatus::Halted | LmpStatus::Fault | LmpStatus::InputRequired) {
+                    return status;
+                }
+            }
+
+            LmpStatus::CycleLimit
+        })
+    }
+}
+
+/// Read a cell of memory into `value`. Returns `false` if the address is out of range
+///
+/// # Safety
+/// `vm` must be a valid handle, and `value` must be valid for writes
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lmp_vm_read_memory(vm: *mut LmpVm, address: usize, value: *mut i64) -> bool {
+    unsafe {
+        with_vm(vm, false, |vm| {
+            let Some(cell) = vm.vm.memory().get(address) else {
+                vm.set_error(format!("address {address} is out of range"));
+                return false;
+            };
+
+            if !value.is_null() {
+                value.write(cell.data);
+            }
+            true
+        })
+    }
+}
+
+/// Overwrite a cell of memory, bypassing devices and protection. Returns `false` if the address
+/// is out of range
+///
+/// # Safety
+/// `vm` must be a valid handle
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lmp_vm_write_memory(vm: *mut LmpVm, address: usize, value: i64) -> bool {
+    unsafe {
+        with_vm(vm, false, |vm| {
+            if address >= MEMORY_SIZE {
+                vm.set_error(format!("address {address} is out of range"));
+                return false;
+            }
+
+            vm.vm.set_memory(address, value);
+            true
+        })
+    }
+}
+
+/// Queue a value to be read by `INP`, or the code point of a character to be read by `INC`
+///
+/// # Safety
+/// `vm` must be a valid handle
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lmp_vm_push_input(vm: *mut LmpVm, value: i64) {
+    unsafe { with_vm(vm, (), |vm| vm.vm.input(value)) }
+}
+
+/// Take the oldest output into `output`. Returns `false` if there is none
+///
+/// # Safety
+/// `vm` must be a valid handle, and `output` must be valid for writes
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lmp_vm_poll_output(vm: *mut LmpVm, output: *mut LmpOutput) -> bool {
+    unsafe {
+        with_vm(vm, false, |vm| {
+            if output.is_null() {
+                return false;
+            }
+
+            match vm.outputs.pop_front() {
+                Some(next) => {
+                    output.write(next);
+                    true
+                }
+                None => false,
+            }
+        })
+    }
+}
+
+/// Description of the last error or fault, or null if there has been none. The string is owned
+/// by the VM and stays valid until the next call that fails, loads a program, or destroys it
+///
+/// # Safety
+/// `vm` must be a valid handle
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lmp_vm_last_error(vm: *const LmpVm) -> *const c_char {
+    match unsafe { vm.as_ref() }.and_then(|vm| vm.error.as_ref()) {
+        Some(error) => error.as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+/// # Safety
+/// `vm` must be a valid handle
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lmp_vm_accumulator(vm: *const LmpVm) -> i64 {
+    guard(0, || unsafe { vm.as_ref() }.map_or(0, |vm| vm.vm.accumulator()))
+}
+
+/// # Safety
+/// `vm` must be a valid handle
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lmp_vm_program_counter(vm: *const LmpVm) -> usize {
+    guard(0, || unsafe { vm.as_ref() }.map_or(0, |vm| vm.vm.program_counter()))
+}
+
+/// # Safety
+/// `vm` must be a valid handle
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lmp_vm_cycles(vm: *const LmpVm) -> i64 {
+    guard(0, || unsafe { vm.as_ref() }.map_or(0, |vm| vm.vm.cycles()))
+}
+
+/// # Safety
+/// `vm` must be a valid handle
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lmp_vm_halted(vm: *const LmpVm) -> bool {
+    guard(true, || unsafe { vm.as_ref() }.is_none_or(|vm| vm.vm.halted()))
+}
+
+/// Call `f`, returning `failed` if it panics so that the panic does not unwind into C
+fn guard<T>(failed: T, f: impl FnOnce() -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(failed)
+}
+
+/// Call `f` with the VM behind a handle, returning `failed` if the handle is null or `f` panics.
+/// Panics are described in [`lmp_vm_last_error`]
+///
+/// # Safety
+/// `vm` must be null or a valid handle
+unsafe fn with_vm<T>(vm: *mut LmpVm, failed: T, f: impl FnOnce(&mut LmpVm) -> T) -> T {
+    let Some(vm) = (unsafe { vm.as_mut() }) else { return failed };
+    match panic::catch_unwind(AssertUnwindSafe(|| f(&mut *vm))) {
+        Ok(result) => result,
+        Err(payload) => {
+            vm.set_error(format!("internal error: {}", panic_message(payload.as_ref())));
+            failed
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    match (payload.downcast_ref::<&str>(), payload.downcast_ref::<String>()) {
+        (Some(message), _) => message,
+        (_, Some(message)) => message,
+        _ => "panic",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_panic() {
+        let vm = lmp_vm_create();
+        let status = unsafe { with_vm(vm, LmpStatus::Error, |_| panic!("out of bounds")) };
+        assert_eq!(status, LmpStatus::Error);
+
+        let error = unsafe { CStr::from_ptr(lmp_vm_last_error(vm)) };
+        assert_eq!(error.to_str(), Ok("internal error: out of bounds"));
+        unsafe { lmp_vm_destroy(vm) };
+    }
+}
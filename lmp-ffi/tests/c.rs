@@ -0,0 +1,34 @@
+//! Builds `tests/test.c` against the static library and runs it
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+#[test]
+fn test_c_program() {
+    let crate_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    // Test executables are in `target/<profile>/deps`, next to where the library is built
+    let exe = env::current_exe().unwrap();
+    let profile_dir = exe.parent().and_then(Path::parent).unwrap();
+    let library = profile_dir.join("liblmp_ffi.a");
+    let program: PathBuf = profile_dir.join(format!("lmp-ffi-test-{}", std::process::id()));
+
+    let compiler = env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let compiled = Command::new(&compiler)
+        .arg(crate_dir.join("tests/test.c"))
+        .arg("-I")
+        .arg(crate_dir.join("include"))
+        .arg(&library)
+        .args(["-lpthread", "-ldl", "-lm", "-o"])
+        .arg(&program)
+        .status();
+    let Ok(compiled) = compiled else {
+        eprintln!("skipping, as the C compiler `{compiler}` could not be run");
+        return;
+    };
+    assert!(compiled.success(), "could not compile tests/test.c");
+
+    let output = Command::new(&program).output().unwrap();
+    let _ = std::fs::remove_file(&program);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "ok\n");
+}
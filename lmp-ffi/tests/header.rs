@@ -0,0 +1,29 @@
+//! Checks that `include/lmp.h` matches what cbindgen generates from the crate. Run with
+//! `LMP_UPDATE_HEADER=1` to regenerate it
+use std::env;
+use std::fs;
+use std::path::Path;
+
+#[test]
+fn test_header() {
+    let crate_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let config = cbindgen::Config::from_file(crate_dir.join("cbindgen.toml")).unwrap();
+    let mut generated = Vec::new();
+    cbindgen::Builder::new()
+        .with_crate(crate_dir)
+        .with_config(config)
+        .generate()
+        .expect("could not generate the C header")
+        .write(&mut generated);
+    let generated = String::from_utf8(generated).unwrap();
+
+    let path = crate_dir.join("include/lmp.h");
+    if env::var_os("LMP_UPDATE_HEADER").is_some() {
+        fs::write(&path, &generated).unwrap();
+    }
+    let checked_in = fs::read_to_string(&path).unwrap();
+    assert!(
+        checked_in == generated,
+        "include/lmp.h is out of date, run the tests with LMP_UPDATE_HEADER=1 to update it"
+    );
+}
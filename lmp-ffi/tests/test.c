@@ -0,0 +1,75 @@
+/* Exercises the C interface, built and run by tests/c.rs */
+#include <stdio.h>
+#include <string.h>
+
+#include "lmp.h"
+
+#define CHECK(condition)                                                      \
+    do {                                                                      \
+        if (!(condition)) {                                                   \
+            fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__, __LINE__,  \
+                    #condition);                                              \
+            return 1;                                                         \
+        }                                                                     \
+    } while (0)
+
+static const char *COUNTDOWN =
+    "        INP\n"
+    "loop    OUT\n"
+    "        SUB one\n"
+    "        BRP loop\n"
+    "        HLT\n"
+    "one     DAT 1\n";
+
+int main(void) {
+    LmpVm *vm = lmp_vm_create();
+    LmpOutput output;
+    int64_t value;
+
+    /* Assembler errors are reported through the error string */
+    CHECK(!lmp_vm_load_source(vm, "LDA missing"));
+    CHECK(strstr(lmp_vm_last_error(vm), "undefined symbol") != NULL);
+
+    /* Count down from 2, stopping first to ask for input */
+    CHECK(lmp_vm_load_source(vm, COUNTDOWN));
+    CHECK(lmp_vm_run(vm, 1000) == LMP_STATUS_INPUT_REQUIRED);
+    lmp_vm_push_input(vm, 2);
+    CHECK(lmp_vm_run(vm, 1000) == LMP_STATUS_HALTED);
+    for (int64_t expected = 2; expected >= 0; expected--) {
+        CHECK(lmp_vm_poll_output(vm, &output));
+        CHECK(output.value == expected && !output.is_character);
+    }
+    CHECK(!lmp_vm_poll_output(vm, &output));
+    CHECK(lmp_vm_halted(vm));
+
+    /* Memory can be read and patched, here to count down by 2 */
+    CHECK(lmp_vm_read_memory(vm, 5, &value) && value == 1);
+    CHECK(!lmp_vm_read_memory(vm, 100000, &value));
+    CHECK(lmp_vm_load_source(vm, COUNTDOWN));
+    CHECK(lmp_vm_write_memory(vm, 5, 2));
+    lmp_vm_push_input(vm, 4);
+    CHECK(lmp_vm_run(vm, 1000) == LMP_STATUS_HALTED);
+    int count = 0;
+    while (lmp_vm_poll_output(vm, &output)) {
+        count++;
+    }
+    CHECK(count == 3);
+
+    /* Images are loaded as is, and faults are reported through the error string */
+    int64_t image[] = {902, 15003, 1, 0}; /* OUT, DIV 3, HLT, DAT 0 */
+    CHECK(lmp_vm_load_image(vm, image, 4));
+    CHECK(lmp_vm_step(vm) == LMP_STATUS_OUTPUT);
+    CHECK(lmp_vm_step(vm) == LMP_STATUS_FAULT);
+    CHECK(strstr(lmp_vm_last_error(vm), "Division by zero") != NULL);
+    CHECK(lmp_vm_program_counter(vm) == 1);
+
+    /* Addresses taken from the accumulator fault rather than aborting */
+    CHECK(lmp_vm_load_source(vm, "INP\nLDR\nHLT\n"));
+    lmp_vm_push_input(vm, -1);
+    CHECK(lmp_vm_run(vm, 1000) == LMP_STATUS_FAULT);
+    CHECK(strstr(lmp_vm_last_error(vm), "invalid address -1") != NULL);
+
+    lmp_vm_destroy(vm);
+    printf("ok\n");
+    return 0;
+}
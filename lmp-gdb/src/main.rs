@@ -0,0 +1,92 @@
+/*
+    littlemanplus — a Rust-based Little Man Computer simulator
+    Copyright (C) 2025 azyrite
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+mod packet;
+mod stub;
+
+use clap::Parser;
+use lmp_lang::SourceMap;
+use lmp_lang::parser::assemble_sources;
+use lmp_vm::VirtualMachine;
+use packet::Connection;
+use std::io::{self, BufReader};
+use std::net::{Ipv4Addr, TcpListener};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use stub::Stub;
+
+/// A gdbserver-style stub that lets GDB debug an LMC program over TCP
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Args {
+    /// Program to debug
+    program: PathBuf,
+
+    /// Port to wait for GDB on. Only connections from this machine are accepted
+    #[arg(short, long, default_value_t = 1234)]
+    port: u16,
+
+    /// Values to queue as input, separated by commas. Further input is read from stdin
+    #[arg(short, long, value_name = "VALUES", value_delimiter = ',', allow_negative_numbers = true)]
+    input: Vec<i64>,
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+
+    let mut vm = match load(&args.program) {
+        Ok(vm) => vm,
+        Err(error) => {
+            eprintln!("{error}");
+            return ExitCode::FAILURE;
+        }
+    };
+    vm.input_all(args.input);
+
+    let result = TcpListener::bind((Ipv4Addr::LOCALHOST, args.port)).and_then(|listener| {
+        eprintln!("Waiting for GDB on {}, connect with `target remote {0}`", listener.local_addr()?);
+        let (stream, address) = listener.accept()?;
+        eprintln!("GDB connected from {address}");
+
+        let mut connection = Connection::new(stream)?;
+        Stub::new(vm, BufReader::new(io::stdin())).serve(&mut connection)
+    });
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("lmp-gdb: {error}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Assemble a program from a file into a new VM
+fn load(path: &Path) -> Result<VirtualMachine, String> {
+    let mut sources = SourceMap::new();
+    let root = sources
+        .add_file(path)
+        .map_err(|error| format!("Could not read {}: {error}", path.display()))?;
+    let program = assemble_sources(&mut sources, root).map_err(|errors| {
+        let errors: Vec<_> = errors.iter().map(|error| error.report(&sources)).collect();
+        format!("Could not assemble the program.\n{}", errors.join("\n"))
+    })?;
+
+    let mut vm = VirtualMachine::new();
+    vm.load(program.cells.iter().map(|cell| (cell.address, cell.instruction)));
+    Ok(vm)
+}
@@ -0,0 +1,125 @@
+//! Framing of GDB remote serial protocol packets, `$data#checksum`
+
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::TcpStream;
+
+/// Sent by GDB to interrupt the program while it is running
+const INTERRUPT: u8 = 0x03;
+
+/// Something received from GDB
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Received {
+    Packet(String),
+    Interrupt,
+}
+
+pub struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    /// Whether packets are acknowledged with `+`, which GDB can turn off with `QStartNoAckMode`
+    pub ack: bool,
+}
+
+impl Connection {
+    pub fn new(stream: TcpStream) -> io::Result<Self> {
+        // Packets are small and each waits for a reply, so send them straight away
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+            ack: true,
+        })
+    }
+
+    /// Wait for the next packet or interrupt. Returns `None` once GDB has disconnected
+    pub fn receive(&mut self) -> io::Result<Option<Received>> {
+        loop {
+            let mut byte = [0];
+            if self.reader.read(&mut byte)? == 0 {
+                return Ok(None);
+            }
+
+            match byte[0] {
+                INTERRUPT => return Ok(Some(Received::Interrupt)),
+                b'$' => {}
+                // Acknowledgements, and anything outside of a packet
+                _ => continue,
+            }
+
+            let mut data = Vec::new();
+            if self.reader.read_until(b'#', &mut data)? == 0 || data.pop() != Some(b'#') {
+                return Ok(None);
+            }
+            let mut checksum = [0; 2];
+            self.reader.read_exact(&mut checksum)?;
+
+            let valid = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|checksum| u8::from_str_radix(checksum, 16).ok())
+                .is_some_and(|checksum| checksum == sum(&data));
+            if self.ack {
+                // Ask for the packet again if it was corrupted
+                self.writer.write_all(if valid { b"+" } else { b"-" })?;
+            }
+            if valid {
+                return Ok(Some(Received::Packet(String::from_utf8_lossy(&data).into_owned())));
+            }
+        }
+    }
+
+    pub fn send(&mut self, data: &str) -> io::Result<()> {
+        let mut escaped = Vec::with_capacity(data.len());
+        for &byte in data.as_bytes() {
+            match byte {
+                b'$' | b'#' | b'}' | b'*' => escaped.extend([b'}', byte ^ 0x20]),
+                _ => escaped.push(byte),
+            }
+        }
+
+        let mut packet = vec![b'$'];
+        packet.extend_from_slice(&escaped);
+        packet.extend(format!("#{:02x}", sum(&escaped)).bytes());
+        self.writer.write_all(&packet)?;
+        self.writer.flush()
+    }
+
+    /// Whether GDB has asked to interrupt the running program, without waiting for it to
+    pub fn interrupted(&mut self) -> io::Result<bool> {
+        self.reader.get_ref().set_nonblocking(true)?;
+        let available = match self.reader.fill_buf() {
+            Ok(buffer) => Ok(buffer.first().copied()),
+            Err(error) if error.kind() == ErrorKind::WouldBlock => Ok(None),
+            Err(error) => Err(error),
+        };
+        self.reader.get_ref().set_nonblocking(false)?;
+
+        match available? {
+            Some(INTERRUPT) => {
+                self.reader.consume(1);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+}
+
+fn sum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, &byte| sum.wrapping_add(byte))
+}
+
+/// Encode bytes as pairs of hex digits
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Decode pairs of hex digits into bytes
+pub fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..text.len())
+        .step_by(2)
+        .map(|start| u8::from_str_radix(text.get(start..start + 2)?, 16).ok())
+        .collect()
+}
@@ -0,0 +1,413 @@
+//! Handling of GDB's packets, running the VM in response
+
+use crate::packet::{Connection, Received, hex, unhex};
+use lmp_common::MEMORY_SIZE;
+use lmp_vm::{InputKind, VirtualMachine, VirtualMachineFault, VirtualMachineStep};
+use std::collections::HashSet;
+use std::io::{self, BufRead, Write};
+
+/// Description of the registers, sent to GDB with `qXfer:features:read`
+const TARGET: &str = include_str!("target.xml");
+/// Size of a memory cell in the address space seen by GDB
+const CELL: usize = 8;
+/// Registers in the order of the target description
+const REGISTERS: usize = 6;
+/// The program is run in batches of this many steps, checking for an interrupt in between
+const BATCH: usize = 10_000;
+
+// Signals reported when the program stops
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGFPE: u8 = 8;
+const SIGSEGV: u8 = 11;
+
+pub struct Stub<I> {
+    vm: VirtualMachine,
+    /// Addresses of the cells with software breakpoints
+    breakpoints: HashSet<usize>,
+    /// Lines of input typed for the program when it runs out of queued input
+    input: I,
+}
+
+impl<I: BufRead> Stub<I> {
+    pub fn new(vm: VirtualMachine, input: I) -> Self {
+        Self {
+            vm,
+            breakpoints: HashSet::new(),
+            input,
+        }
+    }
+
+    /// Answer packets until GDB detaches, kills the program or disconnects
+    pub fn serve(&mut self, connection: &mut Connection) -> io::Result<()> {
+        while let Some(received) = connection.receive()? {
+            let packet = match received {
+                Received::Packet(packet) => packet,
+                // The program is not running, so report that it is stopped
+                Received::Interrupt => {
+                    connection.send(&self.status())?;
+                    continue;
+                }
+            };
+
+            let reply = match packet.as_bytes().first() {
+                Some(b'c') => self.resume(connection, false)?,
+                Some(b's') => self.resume(connection, true)?,
+                Some(b'D') => return connection.send("OK"),
+                Some(b'k') => return Ok(()),
+                _ if packet == "vKill" || packet.starts_with("vKill;") => {
+                    connection.send("OK")?;
+                    return Ok(());
+                }
+                _ if packet == "QStartNoAckMode" => {
+                    connection.send("OK")?;
+                    connection.ack = false;
+                    continue;
+                }
+                _ => self.query(&packet).unwrap_or_else(|| "E01".to_string()),
+            };
+            connection.send(&reply)?;
+        }
+
+        Ok(())
+    }
+
+    /// Reply to a packet that does not run the program, or `None` if it is invalid
+    fn query(&mut self, packet: &str) -> Option<String> {
+        let Some((command, arguments)) = packet.split_at_checked(1) else { return Some(String::new()) };
+        let reply = match command {
+            "?" => self.status(),
+            "g" => (0..REGISTERS)
+                .map(|register| self.register(register).map(|value| hex(&value.to_le_bytes())))
+                .collect::<Option<_>>()?,
+            "G" => {
+                let bytes = unhex(arguments)?;
+                let values: Vec<_> = bytes.chunks_exact(8).map(|value| i64::from_le_bytes(value.try_into().unwrap())).collect();
+                // Only the writable registers are changed, as GDB writes back every register
+                for (register, &value) in values.iter().enumerate().take(4) {
+                    self.set_register(register, value)?;
+                }
+                "OK".to_string()
+            }
+            "p" => hex(&self.register(usize::from_str_radix(arguments, 16).ok()?)?.to_le_bytes()),
+            "P" => {
+                let (register, value) = arguments.split_once('=')?;
+                let value = i64::from_le_bytes(unhex(value)?.try_into().ok()?);
+                self.set_register(usize::from_str_radix(register, 16).ok()?, value)?;
+                "OK".to_string()
+            }
+            "m" => {
+                let (address, length) = address_length(arguments)?;
+                self.read_memory(address, length)?
+            }
+            "M" => {
+                let (range, data) = arguments.split_once(':')?;
+                let (address, length) = address_length(range)?;
+                let data = unhex(data)?;
+                (data.len() == length).then_some(())?;
+                self.write_memory(address, &data)?;
+                "OK".to_string()
+            }
+            "Z" | "z" => {
+                // Software and hardware breakpoints behave the same, as the stub checks for both
+                let mut parts = arguments.splitn(3, ',');
+                let kind = parts.next()?;
+                if kind != "0" && kind != "1" {
+                    return Some(String::new());
+                }
+                let address = usize::from_str_radix(parts.next()?, 16).ok()? / CELL;
+                if command == "Z" {
+                    self.breakpoints.insert(address);
+                } else {
+                    self.breakpoints.remove(&address);
+                }
+                "OK".to_string()
+            }
+            "H" | "T" => "OK".to_string(),
+            _ => return Some(general(packet)),
+        };
+
+        Some(reply)
+    }
+
+    /// Stop reply describing the state of the program
+    fn status(&self) -> String {
+        match self.vm.halted() {
+            true => "W00".to_string(),
+            false => format!("S{SIGTRAP:02x}"),
+        }
+    }
+
+    /// Value of a register by its number in the target description, or `None` if there is no such
+    /// register
+    fn register(&self, register: usize) -> Option<i64> {
+        Some(match register {
+            0 => self.vm.accumulator(),
+            1 => self.vm.index(),
+            2 => (self.vm.stack_pointer() * CELL) as i64,
+            3 => (self.vm.program_counter() * CELL) as i64,
+            4 => self.vm.flags().carry as i64 | (self.vm.flags().overflow as i64) << 1,
+            5 => self.vm.cycles(),
+            _ => return None,
+        })
+    }
+
+    /// Change a register, or `None` if it is read-only or the value is invalid
+    fn set_register(&mut self, register: usize, value: i64) -> Option<()> {
+        match register {
+            0 => self.vm.set_accumulator(value),
+            1 => self.vm.set_index(value),
+            2 => {
+                let stack = &self.vm.config().stack;
+                let address = usize::try_from(value).ok()? / CELL;
+                (stack.start..=stack.end).contains(&address).then_some(())?;
+                self.vm.set_stack_pointer(address);
+            }
+            3 => self.vm.set_program_counter(usize::try_from(value).ok()? / CELL),
+            _ => return None,
+        }
+
+        Some(())
+    }
+
+    /// Bytes of memory from an address, stopping at the end of memory
+    fn read_memory(&self, address: usize, length: usize) -> Option<String> {
+        let end = address.saturating_add(length).min(MEMORY_SIZE * CELL);
+        (address < end || length == 0).then_some(())?;
+
+        let bytes: Vec<u8> = (address..end)
+            .map(|byte| self.vm.memory()[byte / CELL].data.to_le_bytes()[byte % CELL])
+            .collect();
+        Some(hex(&bytes))
+    }
+
+    fn write_memory(&mut self, address: usize, data: &[u8]) -> Option<()> {
+        (address.checked_add(data.len())? <= MEMORY_SIZE * CELL).then_some(())?;
+
+        // Cells may be partly written, so each is read, changed and written back
+        for (byte, &value) in (address..).zip(data) {
+            let cell = byte / CELL;
+            let mut bytes = self.vm.memory()[cell].data.to_le_bytes();
+            bytes[byte % CELL] = value;
+            self.vm.set_memory(cell, i64::from_le_bytes(bytes));
+        }
+
+        Some(())
+    }
+
+    /// Run the program until it stops, returning the stop reply. Output is forwarded to GDB's
+    /// console as it happens
+    fn resume(&mut self, connection: &mut Connection, step: bool) -> io::Result<String> {
+        loop {
+            for _ in 0..BATCH {
+                match self.vm.step() {
+                    VirtualMachineStep::Output(value) => connection.send(&format!("O{}", hex(format!("{value}\n").as_bytes())))?,
+                    VirtualMachineStep::OutputCharacter(character) => {
+                        connection.send(&format!("O{}", hex(character.to_string().as_bytes())))?
+                    }
+                    VirtualMachineStep::InputRequired(kind) => {
+                        if !self.read_input(kind)? {
+                            connection.send(&format!("O{}", hex(b"The program needs input but none is left\n")))?;
+                            return Ok(format!("S{SIGTRAP:02x}"));
+                        }
+                        continue;
+                    }
+                    VirtualMachineStep::Halted => return Ok("W00".to_string()),
+                    VirtualMachineStep::Fault(fault) => {
+                        connection.send(&format!("O{}", hex(format!("{fault}\n").as_bytes())))?;
+                        return Ok(format!("S{:02x}", signal(&fault)));
+                    }
+                    _ => {}
+                }
+
+                if step {
+                    return Ok(format!("S{SIGTRAP:02x}"));
+                } else if self.breakpoints.contains(&self.vm.program_counter()) {
+                    return Ok(format!("T{SIGTRAP:02x}swbreak:;"));
+                }
+            }
+
+            if connection.interrupted()? {
+                return Ok(format!("S{SIGINT:02x}"));
+            }
+        }
+    }
+
+    /// Read a line of input for the program, returning `false` if there is none left
+    fn read_input(&mut self, kind: InputKind) -> io::Result<bool> {
+        loop {
+            let prompt = match kind {
+                InputKind::Number => "Input a number: ",
+                InputKind::Character => "Input a character: ",
+            };
+            eprint!("{prompt}");
+            io::stderr().flush()?;
+
+            let mut line = String::new();
+            if self.input.read_line(&mut line)? == 0 {
+                return Ok(false);
+            }
+
+            let line = line.trim_end_matches(['\r', '\n']);
+            match kind {
+                InputKind::Number => match line.trim().parse() {
+                    Ok(value) => self.vm.input(value),
+                    Err(_) => continue,
+                },
+                // An empty line is a newline
+                InputKind::Character => match line.chars().next() {
+                    Some(_) => self.vm.input_all(line.chars().map(|character| character as i64)),
+                    None => self.vm.input('\n' as i64),
+                },
+            }
+
+            return Ok(true);
+        }
+    }
+}
+
+/// Reply to a general query or `v` packet, which is empty if it is not supported
+fn general(packet: &str) -> String {
+    if packet.starts_with("qSupported") {
+        "PacketSize=4000;qXfer:features:read+;QStartNoAckMode+;swbreak+".to_string()
+    } else if let Some(annex) = packet.strip_prefix("qXfer:features:read:") {
+        let Some(("target.xml", range)) = annex.split_once(':') else { return "E00".to_string() };
+        let Some((offset, length)) = address_length(range) else { return "E01".to_string() };
+
+        let start = offset.min(TARGET.len());
+        let end = offset.saturating_add(length).min(TARGET.len());
+        let more = if end < TARGET.len() { 'm' } else { 'l' };
+        format!("{more}{}", &TARGET[start..end])
+    } else {
+        match packet {
+            "qAttached" => "1",
+            "qC" => "QC1",
+            "qfThreadInfo" => "m1",
+            "qsThreadInfo" => "l",
+            "qSymbol::" => "OK",
+            _ => "",
+        }
+        .to_string()
+    }
+}
+
+/// Parse `address,length` in hex
+fn address_length(text: &str) -> Option<(usize, usize)> {
+    let (address, length) = text.split_once(',')?;
+    Some((usize::from_str_radix(address, 16).ok()?, usize::from_str_radix(length, 16).ok()?))
+}
+
+/// The signal GDB is told stopped the program when it faults
+fn signal(fault: &VirtualMachineFault) -> u8 {
+    use VirtualMachineFault::*;
+    match fault {
+        DivisionByZero { .. } => SIGFPE,
+        StackOverflow { .. }
+        | StackUnderflow { .. }
+        | InvalidAddress { .. }
+        | InvalidReturn { .. }
+        | InvalidHandler { .. }
+        | WriteProtected { .. } => SIGSEGV,
+        _ => SIGILL,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{BufReader, Read};
+    use std::net::{TcpListener, TcpStream};
+
+    /// Send a packet as GDB would, returning the replies up to the next that is not console output
+    fn request(stream: &mut TcpStream, packet: &str) -> Vec<String> {
+        let sum = packet.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        write!(stream, "${packet}#{sum:02x}").unwrap();
+
+        let mut reader = BufReader::new(stream);
+        let mut replies = Vec::new();
+        loop {
+            let mut reply = Vec::new();
+            reader.read_until(b'$', &mut reply).unwrap();
+            reply.clear();
+            reader.read_until(b'#', &mut reply).unwrap();
+            reply.pop();
+            reader.read_exact(&mut [0; 2]).unwrap();
+
+            let reply = String::from_utf8(reply).unwrap();
+            let output = reply.starts_with('O') && reply != "OK";
+            replies.push(reply);
+            if !output {
+                return replies;
+            }
+        }
+    }
+
+    #[test]
+    fn test_stub() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let program = lmp_lang::parser::assemble("LDA #7\nSTA value\nOUT\nHLT\nvalue DAT\n").unwrap();
+            let mut vm = VirtualMachine::new();
+            vm.load(program.cells.iter().map(|cell| (cell.address, cell.instruction)));
+
+            let (stream, _) = listener.accept().unwrap();
+            Stub::new(vm, io::empty()).serve(&mut Connection::new(stream).unwrap())
+        });
+        let mut gdb = TcpStream::connect(address).unwrap();
+        gdb.set_nodelay(true).unwrap();
+
+        assert!(request(&mut gdb, "qSupported:swbreak+")[0].contains("qXfer:features:read+"));
+        assert_eq!(request(&mut gdb, "QStartNoAckMode"), ["OK"]);
+        let target = request(&mut gdb, "qXfer:features:read:target.xml:0,fff").remove(0);
+        assert!(target.starts_with("l<?xml") && target.contains(r#"<reg name="pc" bitsize="64" type="code_ptr"/>"#));
+
+        // Break at the `OUT` in cell 2, which is at address 16
+        assert_eq!(request(&mut gdb, "Z0,10,1"), ["OK"]);
+        assert_eq!(request(&mut gdb, "c"), ["T05swbreak:;"]);
+        let registers = request(&mut gdb, "g").remove(0);
+        assert_eq!(&registers[..16], "0700000000000000");
+        assert_eq!(&registers[48..64], "1000000000000000");
+        assert_eq!(request(&mut gdb, "p3"), ["1000000000000000"]);
+        assert_eq!(request(&mut gdb, "p6"), ["E01"]);
+        assert_eq!(request(&mut gdb, "m20,8"), ["0700000000000000"]);
+
+        assert_eq!(request(&mut gdb, "P0=0500000000000000"), ["OK"]);
+        assert_eq!(request(&mut gdb, "M20,2:0901"), ["OK"]);
+        assert_eq!(request(&mut gdb, "m20,8"), ["0901000000000000"]);
+        assert_eq!(request(&mut gdb, "P5=0000000000000000"), ["E01"]);
+
+        // Output is sent to GDB's console, "5\n" in hex
+        assert_eq!(request(&mut gdb, "s"), ["O350a", "S05"]);
+        assert_eq!(request(&mut gdb, "c"), ["W00"]);
+        assert_eq!(request(&mut gdb, "D"), ["OK"]);
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_interrupt() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let program = lmp_lang::parser::assemble("loop BRA loop\n").unwrap();
+            let mut vm = VirtualMachine::new();
+            vm.load(program.cells.iter().map(|cell| (cell.address, cell.instruction)));
+
+            let (stream, _) = listener.accept().unwrap();
+            Stub::new(vm, io::empty()).serve(&mut Connection::new(stream).unwrap())
+        });
+        let mut gdb = TcpStream::connect(address).unwrap();
+
+        // The program never stops by itself, so `c` only returns once it is interrupted
+        write!(gdb, "$c#63").unwrap();
+        gdb.write_all(&[0x03]).unwrap();
+        let mut reply = [0; 8];
+        gdb.read_exact(&mut reply).unwrap();
+        assert_eq!(&reply, b"+$S02#b5");
+
+        // Killing the program has no reply, and ends the session
+        write!(gdb, "$k#6b").unwrap();
+        server.join().unwrap().unwrap();
+    }
+}
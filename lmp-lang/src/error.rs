@@ -0,0 +1,134 @@
+use crate::source::line_col;
+use crate::SourceMap;
+use lmp_common::MEMORY_SIZE;
+use std::ops::Range;
+use thiserror::Error;
+
+/// Byte range into the [`SourceMap`] of the assembled program
+pub type Span = Range<usize>;
+
+/// An error encountered while assembling a program
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum AssemblerError {
+    #[error("syntax error: {message}")]
+    Syntax { message: String, span: Span },
+    #[error("undefined symbol `{name}`")]
+    UndefinedSymbol { name: String, span: Span },
+    /// A label used to lay out memory, as in `BLK` or `ORG`, that is only defined after the directive
+    #[error("label `{name}` must be defined before this directive")]
+    DefinedLater { name: String, span: Span },
+    #[error("symbol `{name}` is defined more than once")]
+    DuplicateSymbol { name: String, span: Span },
+    #[error("constant `{name}` is defined in terms of itself")]
+    CircularDefinition { name: String, span: Span },
+    #[error("value {value} is out of range, expected a value from {min} to {max}")]
+    OutOfRange {
+        value: i64,
+        min: i64,
+        max: i64,
+        span: Span,
+    },
+    #[error("program does not fit into memory ({} cells)", MEMORY_SIZE)]
+    MemoryFull { span: Span },
+    #[error("cell at address {address} overlaps with a previously placed cell")]
+    Overlap {
+        address: usize,
+        span: Span,
+        /// Location of the line that previously placed a cell at the address
+        previous: Span,
+    },
+    #[error("division by zero in expression")]
+    DivisionByZero { span: Span },
+    #[error("expression overflowed")]
+    Overflow { span: Span },
+    #[error("undefined macro `{name}`")]
+    UndefinedMacro { name: String, span: Span },
+    #[error("macro `{name}` expects {expected} arguments but {found} were given")]
+    MacroArguments {
+        name: String,
+        expected: usize,
+        found: usize,
+        span: Span,
+    },
+    #[error("macro `{name}` expands into itself")]
+    RecursiveMacro { name: String, span: Span },
+    #[error("macro `{name}` is missing a matching ENDM")]
+    UnterminatedMacro { name: String, span: Span },
+    #[error("ENDM without a matching MACRO")]
+    UnexpectedEndm { span: Span },
+    #[error("macros cannot be defined inside other macros")]
+    NestedMacro { span: Span },
+    #[error("could not include `{path}`: {message}")]
+    Include {
+        path: String,
+        message: String,
+        span: Span,
+    },
+    #[error("`{path}` includes itself")]
+    IncludeCycle { path: String, span: Span },
+    /// An error inside the body of a macro, raised when expanding it at `call_site`
+    #[error("{error} (in expansion of macro `{name}`)")]
+    InMacro {
+        name: String,
+        call_site: Span,
+        error: Box<AssemblerError>,
+    },
+}
+
+impl AssemblerError {
+    /// Location in the source the error originates from
+    ///
+    /// For errors inside a macro body this is the location within the body, see
+    /// [`AssemblerError::InMacro`] for the call site
+    pub fn span(&self) -> &Span {
+        use AssemblerError::*;
+        match self {
+            Syntax { span, .. }
+            | UndefinedSymbol { span, .. }
+            | DefinedLater { span, .. }
+            | DuplicateSymbol { span, .. }
+            | CircularDefinition { span, .. }
+            | OutOfRange { span, .. }
+            | MemoryFull { span }
+            | Overlap { span, .. }
+            | DivisionByZero { span }
+            | Overflow { span }
+            | UndefinedMacro { span, .. }
+            | MacroArguments { span, .. }
+            | RecursiveMacro { span, .. }
+            | UnterminatedMacro { span, .. }
+            | UnexpectedEndm { span }
+            | NestedMacro { span }
+            | Include { span, .. }
+            | IncludeCycle { span, .. } => span,
+            InMacro { error, .. } => error.span(),
+        }
+    }
+
+    /// Line and column (both starting from 1) of the start of the error in `source`, for
+    /// programs assembled from a single source
+    pub fn line_col(&self, source: &str) -> (usize, usize) {
+        line_col(source, self.span().start)
+    }
+
+    /// Human-readable description of the error and where it occurred, including the call sites
+    /// of any macros the error is inside of
+    pub fn report(&self, sources: &SourceMap) -> String {
+        let mut error = self;
+        let mut call_sites = Vec::new();
+        while let AssemblerError::InMacro { name, call_site, error: inner } = error {
+            call_sites.push((name, call_site));
+            error = inner;
+        }
+
+        let mut report = format!("{}: {error}", sources.describe(error.span()));
+        for (name, call_site) in call_sites.into_iter().rev() {
+            report += &format!(
+                "\n  in expansion of macro `{name}` at {}",
+                sources.describe(call_site)
+            );
+        }
+
+        report
+    }
+}
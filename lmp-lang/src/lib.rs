@@ -1,3 +1,13 @@
+pub mod library;
+pub mod lint;
 pub mod parser;
+mod error;
+mod program;
+mod source;
+mod symbols;
 
 pub use chumsky::Parser;
+pub use error::*;
+pub use program::*;
+pub use source::*;
+pub use symbols::*;
@@ -0,0 +1,16 @@
+//! Standard library of routines bundled with the assembler, included with `INCLUDE <name>`
+//!
+//! The routines are macros, so they are assembled in place wherever they are used. All of them
+//! overwrite the accumulator.
+
+/// Files in the standard library along with their contents
+pub const FILES: &[(&str, &str)] = &[
+    ("math.lmc", include_str!("../std/math.lmc")),
+    ("shift.lmc", include_str!("../std/shift.lmc")),
+    ("print.lmc", include_str!("../std/print.lmc")),
+];
+
+/// Look up a file in the standard library by name, returning its name and contents
+pub fn get(name: &str) -> Option<(&'static str, &'static str)> {
+    FILES.iter().find(|(file, _)| *file == name).copied()
+}
@@ -0,0 +1,555 @@
+//! Checks for mistakes in programs that assemble, but probably do not do what was intended
+
+use crate::{Cell, Program, SourceMap, Span, SymbolIndex, SymbolKind};
+use lmp_common::MEMORY_SIZE;
+use lmp_common::assembly::Instruction;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// A check made by [`lint`]. Each has a code that stays the same between versions, along with a
+/// name, either of which can be used to configure it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Lint {
+    /// Instructions that can never be executed
+    UnreachableCode,
+    /// No `HLT` can be reached from the start of the program
+    NoHalt,
+    /// Execution continuing from an instruction into a `DAT` cell
+    FallIntoData,
+    /// A label that is defined but never used
+    UnusedLabel,
+    /// A branch or call to a `DAT` cell
+    BranchIntoData,
+    /// `STA` or `STX` overwriting an instruction
+    StoreIntoCode,
+    /// Reading a `DAT` cell that is never given a value, either in the program or by storing to it
+    UninitialisedRead,
+    /// `LDR` when the accumulator holds something that is not an address
+    ImplausibleLdr,
+}
+
+impl Lint {
+    pub const ALL: [Lint; 8] = [
+        Lint::UnreachableCode,
+        Lint::NoHalt,
+        Lint::FallIntoData,
+        Lint::UnusedLabel,
+        Lint::BranchIntoData,
+        Lint::StoreIntoCode,
+        Lint::UninitialisedRead,
+        Lint::ImplausibleLdr,
+    ];
+
+    /// Stable code of the lint, e.g. `L001`
+    pub fn code(self) -> &'static str {
+        match self {
+            Lint::UnreachableCode => "L001",
+            Lint::NoHalt => "L002",
+            Lint::FallIntoData => "L003",
+            Lint::UnusedLabel => "L004",
+            Lint::BranchIntoData => "L005",
+            Lint::StoreIntoCode => "L006",
+            Lint::UninitialisedRead => "L007",
+            Lint::ImplausibleLdr => "L008",
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Lint::UnreachableCode => "unreachable-code",
+            Lint::NoHalt => "no-halt",
+            Lint::FallIntoData => "fall-into-data",
+            Lint::UnusedLabel => "unused-label",
+            Lint::BranchIntoData => "branch-into-data",
+            Lint::StoreIntoCode => "store-into-code",
+            Lint::UninitialisedRead => "uninitialised-read",
+            Lint::ImplausibleLdr => "implausible-ldr",
+        }
+    }
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("unknown lint `{0}`")]
+pub struct UnknownLint(pub String);
+
+impl FromStr for Lint {
+    type Err = UnknownLint;
+
+    /// Parse the code or name of a lint, ignoring case
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Lint::ALL
+            .into_iter()
+            .find(|lint| lint.code().eq_ignore_ascii_case(s) || lint.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| UnknownLint(s.to_string()))
+    }
+}
+
+/// How a lint is reported
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Severity {
+    /// Not checked at all
+    Allow,
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Allow => write!(f, "allow"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// Severity of each lint. Lints are warnings unless configured otherwise
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LintConfig {
+    severities: HashMap<Lint, Severity>,
+}
+
+impl LintConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&mut self, lint: Lint, severity: Severity) {
+        self.severities.insert(lint, severity);
+    }
+
+    pub fn severity(&self, lint: Lint) -> Severity {
+        self.severities.get(&lint).copied().unwrap_or(Severity::Warning)
+    }
+}
+
+/// A problem found by a lint
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub lint: Lint,
+    pub severity: Severity,
+    pub message: String,
+    pub span: Span,
+}
+
+impl Diagnostic {
+    /// Human-readable description of the problem and where it is, e.g.
+    /// `line 3, column 1: warning[L004]: label `x` is never used (unused-label)`
+    pub fn report(&self, sources: &SourceMap) -> String {
+        format!(
+            "{}: {}[{}]: {} ({})",
+            sources.describe(&self.span),
+            self.severity,
+            self.lint.code(),
+            self.message,
+            self.lint
+        )
+    }
+}
+
+/// Check an assembled program for likely mistakes, in order of where they are in the source.
+/// `symbols` are the symbols of the file being checked, see [`crate::parser::index`], and only
+/// its labels are checked for being used
+///
+/// Execution is followed from address 0 and from any instruction whose address is stored with
+/// `DAT` or loaded with `LDA #`, which are assumed to be reached through pointers or interrupts
+pub fn lint(program: &Program, symbols: &SymbolIndex, config: &LintConfig) -> Vec<Diagnostic> {
+    let mut linter = Linter::new(program, symbols, config);
+    linter.reachability();
+    linter.unused_labels(symbols);
+    linter.accesses();
+    linter.ldr();
+
+    let mut diagnostics = linter.diagnostics;
+    diagnostics.sort_by_key(|diagnostic| (diagnostic.span.start, diagnostic.lint));
+    diagnostics
+}
+
+struct Linter<'a> {
+    cells: BTreeMap<usize, &'a Cell>,
+    /// Name of the label at each address, for messages
+    labels: HashMap<usize, &'a str>,
+    /// Instructions execution starts from, see [`lint`]
+    entries: Vec<usize>,
+    /// Addresses execution can start from other than by continuing from the previous cell
+    targets: HashSet<usize>,
+    /// Addresses of the instructions that can be executed
+    reachable: HashSet<usize>,
+    /// Cells stored to directly
+    written: HashSet<usize>,
+    /// The lowest address that could be written by storing through a pointer or with an index
+    written_from: Option<usize>,
+    config: &'a LintConfig,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Linter<'a> {
+    fn new(program: &'a Program, symbols: &'a SymbolIndex, config: &'a LintConfig) -> Self {
+        let cells: BTreeMap<_, _> = program.cells.iter().map(|cell| (cell.address, cell)).collect();
+
+        // Every cell placed by a line shares its span, so a label is at the first of them
+        let labels = symbols
+            .definitions
+            .iter()
+            .filter(|definition| definition.kind == SymbolKind::Label)
+            .filter_map(|definition| {
+                let cell = program.cells.iter().find(|cell| cell.span == definition.line)?;
+                Some((cell.address, definition.name.as_str()))
+            })
+            .collect();
+
+        let is_code = |address: usize| cells.get(&address).is_some_and(|cell| !is_data(cell));
+        let stored = program.cells.iter().filter_map(|cell| match cell.instruction {
+            Instruction::DAT(value) | Instruction::LDAI(value) => direct(value),
+            _ => None,
+        });
+        let entries: Vec<_> = [0].into_iter().chain(stored).filter(|&address| is_code(address)).collect();
+        let branches = program.cells.iter().filter_map(|cell| flow(cell.instruction).0.and_then(direct));
+        let targets = entries.iter().copied().chain(branches).filter(|&address| is_code(address)).collect();
+
+        let code = program.cells.iter().filter(|cell| !is_data(cell));
+        let written = code.clone().filter_map(|cell| write(cell.instruction)).collect();
+        let written_from = code
+            .filter_map(|cell| match cell.instruction {
+                Instruction::STR(_) => Some(0),
+                Instruction::STA(address) | Instruction::STX(address) if direct(address).is_none() => Some(0),
+                Instruction::STAX(address) => Some(direct(address).unwrap_or(0)),
+                _ => None,
+            })
+            .min();
+
+        Self {
+            cells,
+            labels,
+            entries,
+            targets,
+            reachable: HashSet::new(),
+            written,
+            written_from,
+            config,
+            diagnostics: Vec::new(),
+        }
+    }
+
+    fn report(&mut self, lint: Lint, span: &Span, message: String) {
+        let severity = self.config.severity(lint);
+        if severity != Severity::Allow {
+            self.diagnostics.push(Diagnostic {
+                lint,
+                severity,
+                message,
+                span: span.clone(),
+            });
+        }
+    }
+
+    fn is_code(&self, address: usize) -> bool {
+        self.cells.get(&address).is_some_and(|cell| !is_data(cell))
+    }
+
+    fn is_data(&self, address: usize) -> bool {
+        self.cells.get(&address).is_some_and(|cell| is_data(cell))
+    }
+
+    /// Whether a cell always holds the value it was given in the program
+    fn is_constant(&self, address: usize) -> bool {
+        !self.written.contains(&address) && self.written_from.is_none_or(|from| address < from)
+    }
+
+    /// Instructions in order of address
+    fn code(&self) -> Vec<&'a Cell> {
+        self.cells.values().copied().filter(|cell| !is_data(cell)).collect()
+    }
+
+    /// The label at an address, or the address itself if there is none
+    fn describe(&self, address: usize) -> String {
+        match self.labels.get(&address) {
+            Some(label) => format!("`{label}`"),
+            None => format!("address {address}"),
+        }
+    }
+
+    /// Follow execution from each entry point
+    fn reachability(&mut self) {
+        let mut pending = self.entries.clone();
+        while let Some(address) = pending.pop() {
+            let Some(&cell) = self.cells.get(&address) else { continue };
+            if is_data(cell) || !self.reachable.insert(address) {
+                continue;
+            }
+
+            let (target, continues) = flow(cell.instruction);
+            pending.extend(target.and_then(direct).filter(|&target| self.is_code(target)));
+            if continues && self.is_data(address + 1) {
+                let message = format!(
+                    "execution continues from `{}` into data at {}",
+                    cell.instruction,
+                    self.describe(address + 1)
+                );
+                self.report(Lint::FallIntoData, &cell.span, message);
+            } else if continues {
+                pending.push(address + 1);
+            }
+        }
+
+        let code = self.code();
+        let halts = self.reachable.iter().any(|address| self.cells[address].instruction == Instruction::HLT);
+        if let Some(first) = code.first()
+            && !halts
+        {
+            let span = self.cells.get(&0).unwrap_or(first).span.clone();
+            self.report(Lint::NoHalt, &span, "no `HLT` can be reached, so the program never stops".to_string());
+        }
+
+        // Report each run of unreachable instructions once
+        let mut previous = None;
+        for cell in code {
+            let unreachable = !self.reachable.contains(&cell.address);
+            let continued = previous.is_some_and(|previous| previous + 1 == cell.address);
+            if unreachable && !continued {
+                let location = self.describe(cell.address);
+                let message = format!("`{}` at {location} can never be executed", cell.instruction);
+                self.report(Lint::UnreachableCode, &cell.span, message);
+            }
+            previous = unreachable.then_some(cell.address);
+        }
+    }
+
+    fn unused_labels(&mut self, symbols: &SymbolIndex) {
+        for definition in &symbols.definitions {
+            if definition.kind == SymbolKind::Label && symbols.references(&definition.name).next().is_none() {
+                self.report(Lint::UnusedLabel, &definition.span, format!("label `{}` is never used", definition.name));
+            }
+        }
+    }
+
+    /// Branches, loads and stores to cells of the wrong kind
+    fn accesses(&mut self) {
+        for cell in self.code() {
+            let instruction = cell.instruction;
+            if let Some(target) = flow(instruction).0.and_then(direct)
+                && self.is_data(target)
+            {
+                let message = format!("`{instruction}` branches into data at {}", self.describe(target));
+                self.report(Lint::BranchIntoData, &cell.span, message);
+            }
+
+            if let Some(target) = write(instruction)
+                && self.is_code(target)
+            {
+                let message = format!("`{instruction}` overwrites the instruction at {}", self.describe(target));
+                self.report(Lint::StoreIntoCode, &cell.span, message);
+            }
+
+            if let Some(target) = read(instruction)
+                && self.cells.get(&target).is_some_and(|target| is_data(target) && !target.initialised)
+                && self.is_constant(target)
+            {
+                let message = format!("`{instruction}` reads {}, which is never given a value", self.describe(target));
+                self.report(Lint::UninitialisedRead, &cell.span, message);
+            }
+        }
+    }
+
+    /// `LDR` after something that leaves a value in the accumulator which is not an address
+    fn ldr(&mut self) {
+        for cell in self.code() {
+            if cell.instruction != Instruction::LDR || !self.reachable.contains(&cell.address) {
+                continue;
+            }
+
+            if let Some(reason) = self.ldr_source(cell.address) {
+                self.report(Lint::ImplausibleLdr, &cell.span, format!("`LDR` uses {reason} as an address"));
+            }
+        }
+    }
+
+    /// What the accumulator holds at `LDR` if it is not an address, found by looking back through the
+    /// instructions that always run just before it for what set the accumulator
+    fn ldr_source(&self, mut address: usize) -> Option<String> {
+        loop {
+            if self.targets.contains(&address) || address == 0 {
+                return None;
+            }
+            address -= 1;
+            let previous = self.cells.get(&address).filter(|cell| !is_data(cell))?;
+            if !flow(previous.instruction).1 {
+                return None;
+            }
+
+            match previous.instruction {
+                Instruction::LDA(source) => {
+                    let source = self.cells.get(&direct(source)?)?;
+                    return match source.instruction {
+                        Instruction::DAT(value) if self.is_constant(source.address) && !plausible(value) => {
+                            Some(format!("{value}, loaded from {}", self.describe(source.address)))
+                        }
+                        Instruction::DAT(_) => None,
+                        _ => Some(format!("the instruction at {}", self.describe(source.address))),
+                    };
+                }
+                Instruction::INC => return Some("a character read from input".to_string()),
+                instruction if keeps_accumulator(instruction) => {}
+                _ => return None,
+            }
+        }
+    }
+}
+
+fn is_data(cell: &Cell) -> bool {
+    matches!(cell.instruction, Instruction::DAT(_))
+}
+
+/// An operand as an address, unless it is a pointer to one
+fn direct(operand: i64) -> Option<usize> {
+    usize::try_from(operand).ok().filter(|&address| address < MEMORY_SIZE)
+}
+
+/// Whether `LDR` can load from an address in the accumulator, which may also be a pointer
+fn plausible(address: i64) -> bool {
+    (0..2 * MEMORY_SIZE as i64).contains(&address)
+}
+
+/// Where execution can go after an instruction: the operand of a branch or call, and whether it
+/// can continue on to the next cell
+fn flow(instruction: Instruction<i64>) -> (Option<i64>, bool) {
+    use Instruction::*;
+    match instruction {
+        HLT | RET | RTI | DAT(_) => (None, false),
+        BRA(target) => (Some(target), false),
+        BRZ(target) | BRP(target) | BRN(target) | BNZ(target) | BRC(target) | BRV(target) | CAL(target) => {
+            (Some(target), true)
+        }
+        _ => (None, true),
+    }
+}
+
+/// The cell an instruction reads from directly
+fn read(instruction: Instruction<i64>) -> Option<usize> {
+    use Instruction::*;
+    match instruction {
+        ADD(address) | SUB(address) | LDA(address) | LDX(address) | BWA(address) | BWO(address)
+        | BWX(address) | MUL(address) | DIV(address) | MOD(address) | SHL(address) | SHR(address)
+        | SAR(address) => direct(address),
+        _ => None,
+    }
+}
+
+/// The cell an instruction writes to directly
+fn write(instruction: Instruction<i64>) -> Option<usize> {
+    match instruction {
+        Instruction::STA(address) | Instruction::STX(address) => direct(address),
+        _ => None,
+    }
+}
+
+/// Whether an instruction that continues on to the next cell leaves the accumulator as it was
+fn keeps_accumulator(instruction: Instruction<i64>) -> bool {
+    use Instruction::*;
+    matches!(
+        instruction,
+        STA(_) | STR(_) | STX(_) | STAX(_) | LDX(_) | TAX | OUT | OTC | OUTP(_) | PSH | EI | DI
+            | BRZ(_) | BRP(_) | BRN(_) | BNZ(_) | BRC(_) | BRV(_)
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::{assemble, index};
+    use crate::SourceOrigin;
+    use indoc::indoc;
+
+    fn check(source: &str) -> Vec<(Lint, String)> {
+        let program = assemble(source).unwrap();
+        let mut sources = SourceMap::new();
+        let id = sources.add(SourceOrigin::Input, source);
+        let symbols = index(&sources, id).unwrap();
+
+        lint(&program, &symbols, &LintConfig::new())
+            .into_iter()
+            .map(|diagnostic| (diagnostic.lint, diagnostic.report(&sources)))
+            .collect()
+    }
+
+    #[test]
+    fn test_lint() {
+        let clean = indoc! {"
+                    LDA #table
+                    ADD count
+                    LDR
+                    OUT
+                    CAL done
+                    STA count
+            loop    INP
+                    BRZ done
+                    BRA loop
+            done    HLT
+            table   DAT 5
+            count   DAT 1
+        "};
+        assert_eq!(check(clean), []);
+
+        let messy = indoc! {"
+                    LDA ptr
+                    LDR
+                    STA start
+                    LDA empty
+                    BRZ value
+            unused  OUT
+                    BRA 0
+                    HLT
+            value   DAT 12
+            empty   DAT
+            ptr     DAT 5000
+            start   INP
+        "};
+        let lints: Vec<_> = check(messy).into_iter().map(|(lint, _)| lint).collect();
+        assert_eq!(
+            lints,
+            [
+                Lint::NoHalt,
+                Lint::ImplausibleLdr,
+                Lint::StoreIntoCode,
+                Lint::UninitialisedRead,
+                Lint::BranchIntoData,
+                Lint::UnusedLabel,
+                Lint::UnreachableCode,
+                Lint::UnreachableCode,
+            ]
+        );
+        assert_eq!(
+            check(messy)[4].1,
+            "line 5, column 9: warning[L005]: `BRZ 8` branches into data at `value` (branch-into-data)"
+        );
+
+        let falls = check("LDA #1\nOUT\nDAT 5\n");
+        assert_eq!(falls.iter().map(|(lint, _)| *lint).collect::<Vec<_>>(), [Lint::NoHalt, Lint::FallIntoData]);
+    }
+
+    #[test]
+    fn test_lint_config() {
+        assert_eq!("L004".parse(), Ok(Lint::UnusedLabel));
+        assert_eq!("Unused-Label".parse(), Ok(Lint::UnusedLabel));
+        assert_eq!("L999".parse::<Lint>(), Err(UnknownLint("L999".to_string())));
+
+        let program = assemble("HLT\nx DAT 1\n").unwrap();
+        let symbols = SymbolIndex::default();
+        let mut config = LintConfig::new();
+        assert!(lint(&program, &symbols, &config).is_empty());
+
+        let program = assemble("LDA #1\nOUT\n").unwrap();
+        config.set(Lint::NoHalt, Severity::Error);
+        assert_eq!(lint(&program, &symbols, &config)[0].severity, Severity::Error);
+        config.set(Lint::NoHalt, Severity::Allow);
+        assert!(lint(&program, &symbols, &config).is_empty());
+    }
+}
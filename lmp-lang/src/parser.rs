@@ -1,194 +1,967 @@
 //! Assembly compiler
 
+mod format;
+mod include;
+mod index;
+mod macros;
+
+pub use format::format;
+pub use index::index;
+
+use crate::{AssemblerError, Cell, Program, SourceId, SourceMap, SourceOrigin, Span};
+use chumsky::input::MapExtra;
+use chumsky::inspector::SimpleState;
 use chumsky::prelude::*;
 use lmp_common::assembly::Instruction;
 use lmp_common::MEMORY_SIZE;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+use std::rc::Rc;
 
-/// Data attached to each instruction in the **unprocessed** AST
-#[derive(Debug, Clone)]
-enum NodeInstructionData<'a> {
-    Pointer(&'a str),
-    Label(&'a str),
-    Num(i64),
+/// The state is the offset of the file being parsed in the [`SourceMap`]
+type Extra<'a> = extra::Full<Rich<'a, char>, SimpleState<usize>, ()>;
+
+/// Largest operand that can be encoded into an instruction
+const MAX_OPERAND: i64 = 999;
+
+/// Words that cannot be used as labels, as they would be mistaken for an opcode or directive
+const RESERVED: &[&str] = &[
+    "ADD", "SUB", "STA", "LDA", "BRA", "BRZ", "BRP", "BRN", "BNZ", "BRC", "BRV", "BWN", "BWA",
+    "BWO", "BWX", "MUL", "DIV", "MOD", "SHL", "SHR", "SAR", "CAL", "RET", "PSH", "POP", "STR",
+    "LDX", "STX", "TAX", "TXA", "LDR", "INP", "OUT", "INC", "OTC", "EI", "DI", "RTI", "HLT",
+    "DAT", "EQU", "ORG", "BLK", "MACRO", "ENDM", "INCLUDE",
+];
+
+/// Binary operators permitted in assemble-time expressions
+#[derive(Debug, Clone, Copy)]
+enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
 }
 
-impl<'a> From<&'a str> for NodeInstructionData<'a> {
-    fn from(s: &'a str) -> Self {
-        if s.starts_with('@') {
-            Self::Pointer(&s[1..])
-        } else {
-            Self::Label(s)
-        }
-    }
+/// Name of a label or constant
+///
+/// Labels defined inside a macro body are given the scope of the expansion they were produced by,
+/// so that each expansion gets its own copy. Everything else lives in the global scope (`0`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Ident<'a> {
+    name: &'a str,
+    scope: usize,
 }
 
-impl From<i64> for NodeInstructionData<'_> {
-    fn from(n: i64) -> Self {
-        Self::Num(n)
+impl<'a> From<&'a str> for Ident<'a> {
+    fn from(name: &'a str) -> Self {
+        Self { name, scope: 0 }
     }
 }
 
+/// Expression evaluated at assemble time, e.g. `table+3` or `2*SIZE`
+#[derive(Debug, Clone)]
+enum Expr<'a> {
+    Num(i64),
+    /// Label or constant, along with where it was referenced
+    Symbol(Ident<'a>, Span),
+    Neg(Box<Expr<'a>>),
+    Binary(Box<Expr<'a>>, BinaryOp, Box<Expr<'a>>),
+}
+
+/// Data attached to each instruction in the **unprocessed** AST
+#[derive(Debug, Clone)]
+struct NodeInstructionData<'a> {
+    /// Whether the operand is a pointer to the cell at the evaluated address (`@label`)
+    pointer: bool,
+    expr: Expr<'a>,
+    span: Span,
+}
+
 type NodeInstruction<'a> = Instruction<NodeInstructionData<'a>>;
 
+/// A single item in a `DAT` directive
+#[derive(Debug, Clone)]
+enum DataItem<'a> {
+    /// A value occupying one cell
+    Value(NodeInstructionData<'a>),
+    /// A string, stored as one character code per cell
+    String(String),
+    /// A cell left as zero, from `DAT` without a value
+    Zero,
+}
+
+/// What a line in the program contains
+#[derive(Debug)]
+enum NodeKind<'a> {
+    /// An instruction occupying a cell in memory
+    Instruction(NodeInstruction<'a>),
+    /// Values stored into consecutive cells (`DAT 1, 2, "abc"`)
+    Data(Vec<DataItem<'a>>),
+    /// A block of zeroed cells of the given length (`BLK 10`)
+    Block(Expr<'a>),
+    /// Move the location of the following cells to the given address (`ORG 100`)
+    Origin(Expr<'a>),
+    /// A named constant (`EQU` or `.set`), which does not occupy memory
+    Constant(Expr<'a>),
+    /// Start of a macro definition (`MACRO name a, b`), the body follows until [`NodeKind::MacroEnd`]
+    MacroStart {
+        name: &'a str,
+        name_span: Span,
+        params: Vec<&'a str>,
+    },
+    /// End of a macro definition (`ENDM`)
+    MacroEnd,
+    /// Invocation of a macro, which is replaced by the body of the macro before labels are resolved
+    Call {
+        name: &'a str,
+        name_span: Span,
+        args: Vec<NodeInstructionData<'a>>,
+    },
+    /// Another file to assemble in place of this line (`INCLUDE "file.lmc"`), or a file from the
+    /// standard library (`INCLUDE <math.lmc>`)
+    Include { path: String, library: bool },
+}
+
+/// Macro expansion that a node was produced by
+#[derive(Debug)]
+struct Expansion<'a> {
+    name: &'a str,
+    call_site: Span,
+    /// Expansion that the call site itself was produced by, for nested macros
+    parent: Option<Rc<Expansion<'a>>>,
+}
+
 /// Node in the AST
 #[derive(Debug)]
 struct Node<'a> {
-    pub label: Option<&'a str>,
-    pub instruction: NodeInstruction<'a>
+    pub label: Option<Ident<'a>>,
+    /// Location of the label, if there is one
+    pub label_span: Option<Span>,
+    pub kind: NodeKind<'a>,
+    pub span: Span,
+    pub expansion: Option<Rc<Expansion<'a>>>,
+}
+
+impl Node<'_> {
+    /// Attach the macro expansions this node was produced by to an error
+    fn error(&self, mut error: AssemblerError) -> AssemblerError {
+        let mut expansion = self.expansion.as_ref();
+        while let Some(current) = expansion {
+            error = AssemblerError::InMacro {
+                name: current.name.to_string(),
+                call_site: current.call_site.clone(),
+                error: Box::new(error),
+            };
+            expansion = current.parent.as_ref();
+        }
+
+        error
+    }
+}
+
+/// Span of the output of a parser in the [`SourceMap`]
+fn span<'a>(e: &mut MapExtra<'a, '_, &'a str, Extra<'a>>) -> Span {
+    let offset = e.state().0;
+    let span = e.span();
+
+    span.start + offset..span.end + offset
+}
+
+fn num<'a>() -> impl Parser<'a, &'a str, i64, Extra<'a>> + Clone {
+    text::int(10).try_map(|s: &str, span| {
+        s.parse()
+            .map_err(|_| Rich::custom(span, format!("number {s} is too large")))
+    })
 }
 
-fn num<'a>() -> impl Parser<'a, &'a str, i64> {
-    text::int(10).map(|s: &str| s.parse().unwrap())
+fn label<'a>() -> impl Parser<'a, &'a str, &'a str, Extra<'a>> + Clone {
+    // Reject opcodes and directives (e.g., ADD), this prevents label from "eating" the opcode
+    // when no label is provided
+    text::ascii::ident().filter(|s: &&str| !RESERVED.contains(s))
 }
 
-fn label<'a>() -> impl Parser<'a, &'a str, &'a str> {
-    // HACK: Use rewind?
-    // If all uppercase then reject as it is probably an opcode (e.g., ADD),
-    // this prevents label from "eating" the opcode when no label is provided
-    opt_whitespace().ignore_then(text::ascii::ident().filter(|s: &&str| {
-        !s.is_empty() && !s.chars().all(|c| c.is_ascii_uppercase())
-    }))
+/// Whether `name` can be used as a label, constant or macro name
+pub fn is_label(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !RESERVED.contains(&name)
+}
+
+/// A label along with its location
+fn spanned_label<'a>() -> impl Parser<'a, &'a str, (&'a str, Span), Extra<'a>> + Clone {
+    label().map_with(|name, e| (name, span(e)))
 }
 
 /// at least one whitespace excl. newlines
-fn whitespace<'a>() -> impl Parser<'a, &'a str, ()> {
+fn whitespace<'a>() -> impl Parser<'a, &'a str, (), Extra<'a>> + Clone {
     text::inline_whitespace().at_least(1)
 }
 
 /// whitespace (optional) excl. newlines
-fn opt_whitespace<'a>() -> impl Parser<'a, &'a str, ()> {
+fn opt_whitespace<'a>() -> impl Parser<'a, &'a str, (), Extra<'a>> + Clone {
     text::inline_whitespace().at_least(0)
 }
 
+/// Comment running until the end of the line
+fn comment<'a>() -> impl Parser<'a, &'a str, (), Extra<'a>> + Clone {
+    just("//")
+        .then(any().and_is(text::newline().not()).repeated())
+        .ignored()
+}
+
+/// Assemble-time expression supporting `+ - * / %`, unary minus and parentheses
+fn expr<'a>() -> impl Parser<'a, &'a str, Expr<'a>, Extra<'a>> + Clone {
+    recursive(|expr| {
+        let atom = choice((
+            num().map(Expr::Num),
+            character().map(|c| Expr::Num(c as i64)),
+            text::ascii::ident().map_with(|name: &str, e| Expr::Symbol(name.into(), span(e))),
+            expr.delimited_by(
+                just('(').then(opt_whitespace()),
+                opt_whitespace().then(just(')')),
+            ),
+        ));
+
+        let unary = just('-')
+            .then(opt_whitespace())
+            .repeated()
+            .foldr(atom, |_, expr| Expr::Neg(Box::new(expr)));
+
+        let binary = |l, (op, r)| Expr::Binary(Box::new(l), op, Box::new(r));
+
+        let product = unary.clone().foldl(
+            choice((
+                just('*').to(BinaryOp::Mul),
+                just('/').to(BinaryOp::Div),
+                just('%').to(BinaryOp::Rem),
+            ))
+            .padded_by(opt_whitespace())
+            .then(unary)
+            .repeated(),
+            binary,
+        );
+
+        product.clone().foldl(
+            choice((just('+').to(BinaryOp::Add), just('-').to(BinaryOp::Sub)))
+                .padded_by(opt_whitespace())
+                .then(product)
+                .repeated(),
+            binary,
+        )
+    })
+}
+
 /// Input that goes after an instruction
-fn instruction_input<'a>() -> impl Parser<'a, &'a str, NodeInstructionData<'a>> {
-    // Based off text::ascii::ident but with @ symbols permitted as the first char
-    // for pointers
-    let text_input = regex("[a-zA-Z_@][a-zA-Z0-9_]*");
-    num().map(|n| n.into()).or(text_input.map(|i: &str| i.into()))
-}
-
-fn instruction<'a>() -> impl Parser<'a, &'a str, NodeInstruction<'a>> {
-    // Allow any indent
-    text::inline_whitespace().ignore_then(choice((
-        just("INP").to(Instruction::INP),
-        just("OUT").to(Instruction::OUT),
-        just("HLT").to(Instruction::HLT),
-        just("BWN").to(Instruction::BWN),
-        just("LDR").to(Instruction::LDR),
-        just("DAT").ignore_then(
+fn instruction_input<'a>() -> impl Parser<'a, &'a str, NodeInstructionData<'a>, Extra<'a>> + Clone {
+    // `@` marks a pointer to the cell at the address the expression evaluates to
+    just('@')
+        .or_not()
+        .then(expr())
+        .map_with(|(pointer, expr), e| NodeInstructionData {
+            pointer: pointer.is_some(),
+            expr,
+            span: span(e),
+        })
+}
+
+/// Escape sequence in a string or character literal: `\n`, `\t`, `\0`, `\\`, `\"` or `\'`
+fn escape<'a>() -> impl Parser<'a, &'a str, char, Extra<'a>> + Clone {
+    just('\\').ignore_then(choice((
+        just('n').to('\n'),
+        just('t').to('\t'),
+        just('0').to('\0'),
+        just('\\'),
+        just('"'),
+        just('\''),
+    )))
+}
+
+/// String literal, which may contain escape sequences
+fn string<'a>() -> impl Parser<'a, &'a str, String, Extra<'a>> + Clone {
+    none_of("\\\"\n")
+        .or(escape())
+        .repeated()
+        .collect::<String>()
+        .delimited_by(just('"'), just('"'))
+}
+
+/// Character literal, e.g. `'A'`, which evaluates to its code point
+fn character<'a>() -> impl Parser<'a, &'a str, char, Extra<'a>> + Clone {
+    none_of("\\'\n")
+        .or(escape())
+        .delimited_by(just('\''), just('\''))
+}
+
+/// Data directive storing a comma separated list of values and strings, defaulting to `0`
+fn data<'a>() -> impl Parser<'a, &'a str, Vec<DataItem<'a>>, Extra<'a>> {
+    let item = choice((
+        string().map(DataItem::String),
+        instruction_input().map(DataItem::Value),
+    ));
+
+    just("DAT")
+        .ignore_then(
             whitespace()
-                .ignore_then(num())
-                .or_not()
-                .map(|data| Instruction::DAT(data.unwrap_or_default().into())),
-        ),
-        just("ADD")
-            .ignore_then(whitespace().ignore_then(instruction_input()))
-            .map(|data| Instruction::ADD(data)),
-        just("SUB")
+                .ignore_then(
+                    item.separated_by(just(',').padded_by(opt_whitespace()))
+                        .at_least(1)
+                        .collect::<Vec<_>>(),
+                )
+                .or_not(),
+        )
+        .map(|items| items.unwrap_or_else(|| vec![DataItem::Zero]))
+}
+
+fn indexed_input<'a>() -> impl Parser<'a, &'a str, NodeInstructionData<'a>, Extra<'a>> + Clone {
+    // `,X` adds the index register to the address
+    instruction_input().then_ignore(just(',').padded_by(opt_whitespace()).then(just('X')))
+}
+
+fn port_input<'a>() -> impl Parser<'a, &'a str, NodeInstructionData<'a>, Extra<'a>> + Clone {
+    expr().map_with(|expr, e| NodeInstructionData {
+        pointer: false,
+        expr,
+        span: span(e),
+    })
+}
+
+fn immediate_input<'a>() -> impl Parser<'a, &'a str, NodeInstructionData<'a>, Extra<'a>> + Clone {
+    // `#` marks the value itself rather than an address
+    just('#')
+        .ignore_then(expr())
+        .map_with(|expr, e| NodeInstructionData {
+            pointer: false,
+            expr,
+            span: span(e),
+        })
+}
+
+/// A whole word, so that e.g. a macro called `INCR` is not read as `INC`
+fn keyword<'a>(word: &'static str) -> impl Parser<'a, &'a str, (), Extra<'a>> + Clone {
+    text::ascii::ident().filter(move |s: &&str| *s == word).ignored()
+}
+
+fn instruction<'a>() -> impl Parser<'a, &'a str, NodeInstruction<'a>, Extra<'a>> {
+    // Split up as `choice` only supports so many parsers
+    // Before the fixed instructions, which would otherwise match `INP` and `OUT` on their own
+    let ports = choice((
+        just("INP")
+            .ignore_then(whitespace().ignore_then(port_input()))
+            .map(Instruction::INPP),
+        just("OUT")
+            .ignore_then(whitespace().ignore_then(port_input()))
+            .map(Instruction::OUTP),
+    ));
+
+    let fixed = choice((
+        keyword("INP").to(Instruction::INP),
+        keyword("OUT").to(Instruction::OUT),
+        keyword("INC").to(Instruction::INC),
+        keyword("OTC").to(Instruction::OTC),
+        keyword("HLT").to(Instruction::HLT),
+        keyword("BWN").to(Instruction::BWN),
+        keyword("LDR").to(Instruction::LDR),
+        keyword("RET").to(Instruction::RET),
+        keyword("PSH").to(Instruction::PSH),
+        keyword("POP").to(Instruction::POP),
+        keyword("TAX").to(Instruction::TAX),
+        keyword("TXA").to(Instruction::TXA),
+        keyword("EI").to(Instruction::EI),
+        keyword("DI").to(Instruction::DI),
+        keyword("RTI").to(Instruction::RTI),
+    ));
+
+    let memory = choice((
+        just("ADD").ignore_then(whitespace().ignore_then(choice((
+            immediate_input().map(Instruction::ADDI),
+            indexed_input().map(Instruction::ADDX),
+            instruction_input().map(Instruction::ADD),
+        )))),
+        just("SUB").ignore_then(whitespace().ignore_then(choice((
+            immediate_input().map(Instruction::SUBI),
+            indexed_input().map(Instruction::SUBX),
+            instruction_input().map(Instruction::SUB),
+        )))),
+        just("STA").ignore_then(whitespace().ignore_then(choice((
+            indexed_input().map(Instruction::STAX),
+            instruction_input().map(Instruction::STA),
+        )))),
+        just("LDA").ignore_then(whitespace().ignore_then(choice((
+            immediate_input().map(Instruction::LDAI),
+            indexed_input().map(Instruction::LDAX),
+            instruction_input().map(Instruction::LDA),
+        )))),
+        just("STR")
             .ignore_then(whitespace().ignore_then(instruction_input()))
-            .map(|data| Instruction::SUB(data)),
-        just("STA")
+            .map(Instruction::STR),
+        just("LDX")
             .ignore_then(whitespace().ignore_then(instruction_input()))
-            .map(|data| Instruction::STA(data)),
-        just("LDA")
+            .map(Instruction::LDX),
+        just("STX")
             .ignore_then(whitespace().ignore_then(instruction_input()))
-            .map(|data| Instruction::LDA(data)),
+            .map(Instruction::STX),
+    ));
+
+    let branch = choice((
         just("BRA")
             .ignore_then(whitespace().ignore_then(instruction_input()))
-            .map(|data| Instruction::BRA(data)),
+            .map(Instruction::BRA),
         just("BRZ")
             .ignore_then(whitespace().ignore_then(instruction_input()))
-            .map(|data| Instruction::BRZ(data)),
+            .map(Instruction::BRZ),
         just("BRP")
             .ignore_then(whitespace().ignore_then(instruction_input()))
-            .map(|data| Instruction::BRP(data)),
-        just("BWA")
+            .map(Instruction::BRP),
+        just("BRN")
             .ignore_then(whitespace().ignore_then(instruction_input()))
-            .map(|data| Instruction::BWA(data)),
-        just("BWO")
+            .map(Instruction::BRN),
+        just("BNZ")
             .ignore_then(whitespace().ignore_then(instruction_input()))
-            .map(|data| Instruction::BWO(data)),
-        just("BWX")
+            .map(Instruction::BNZ),
+        just("BRC")
             .ignore_then(whitespace().ignore_then(instruction_input()))
-            .map(|data| Instruction::BWX(data)),
-    )))
+            .map(Instruction::BRC),
+        just("BRV")
+            .ignore_then(whitespace().ignore_then(instruction_input()))
+            .map(Instruction::BRV),
+        just("CAL")
+            .ignore_then(whitespace().ignore_then(instruction_input()))
+            .map(Instruction::CAL),
+    ));
+
+    let arithmetic = choice((
+        just("BWA").ignore_then(whitespace().ignore_then(choice((
+            immediate_input().map(Instruction::BWAI),
+            instruction_input().map(Instruction::BWA),
+        )))),
+        just("BWO").ignore_then(whitespace().ignore_then(choice((
+            immediate_input().map(Instruction::BWOI),
+            instruction_input().map(Instruction::BWO),
+        )))),
+        just("BWX").ignore_then(whitespace().ignore_then(choice((
+            immediate_input().map(Instruction::BWXI),
+            instruction_input().map(Instruction::BWX),
+        )))),
+        just("MUL").ignore_then(whitespace().ignore_then(choice((
+            immediate_input().map(Instruction::MULI),
+            instruction_input().map(Instruction::MUL),
+        )))),
+        just("DIV").ignore_then(whitespace().ignore_then(choice((
+            immediate_input().map(Instruction::DIVI),
+            instruction_input().map(Instruction::DIV),
+        )))),
+        just("MOD").ignore_then(whitespace().ignore_then(choice((
+            immediate_input().map(Instruction::MODI),
+            instruction_input().map(Instruction::MOD),
+        )))),
+        just("SHL").ignore_then(whitespace().ignore_then(choice((
+            immediate_input().map(Instruction::SHLI),
+            instruction_input().map(Instruction::SHL),
+        )))),
+        just("SHR").ignore_then(whitespace().ignore_then(choice((
+            immediate_input().map(Instruction::SHRI),
+            instruction_input().map(Instruction::SHR),
+        )))),
+        just("SAR").ignore_then(whitespace().ignore_then(choice((
+            immediate_input().map(Instruction::SARI),
+            instruction_input().map(Instruction::SAR),
+        )))),
+    ));
+
+    choice((ports, fixed, memory, branch, arithmetic))
 }
 
-fn labeled_line<'a>() -> impl Parser<'a, &'a str, Node<'a>> {
-    // FIXME: allow empty lines with trailing whitespace
-    let maybe_label = label()
+/// A single line of the program, which may be empty
+fn line<'a>() -> impl Parser<'a, &'a str, Option<Node<'a>>, Extra<'a>> {
+    // name EQU expr
+    let equ = spanned_label()
         .then_ignore(whitespace())
-        .map(Some)
-        .or_not()
-        .map(|opt| opt.flatten());
+        .then_ignore(just("EQU"))
+        .then_ignore(whitespace())
+        .then(expr())
+        .map(|(name, expr)| (Some(name), NodeKind::Constant(expr)));
+
+    // .set name, expr
+    let set = just(".set")
+        .ignore_then(whitespace())
+        .ignore_then(spanned_label())
+        .then_ignore(just(',').padded_by(opt_whitespace()))
+        .then(expr())
+        .map(|(name, expr)| (Some(name), NodeKind::Constant(expr)));
+
+    // MACRO name a, b
+    let macro_start = just("MACRO")
+        .ignore_then(whitespace())
+        .ignore_then(spanned_label())
+        .then(
+            whitespace()
+                .ignore_then(
+                    label()
+                        .separated_by(just(',').padded_by(opt_whitespace()))
+                        .at_least(1)
+                        .collect::<Vec<_>>(),
+                )
+                .or_not(),
+        )
+        .map(|((name, name_span), params)| {
+            (None, NodeKind::MacroStart { name, name_span, params: params.unwrap_or_default() })
+        });
 
-    maybe_label
-        .then(instruction())
-        .map(|(label, instruction)| Node {
-            label,
-            instruction
+    let macro_end = just("ENDM").map(|_| (None, NodeKind::MacroEnd));
+
+    // INCLUDE "file.lmc" or INCLUDE <math.lmc>
+    let include = just("INCLUDE")
+        .ignore_then(whitespace())
+        .ignore_then(choice((
+            string().map(|path| NodeKind::Include { path, library: false }),
+            none_of(">\n")
+                .repeated()
+                .at_least(1)
+                .to_slice()
+                .delimited_by(just('<'), just('>'))
+                .map(|name: &str| NodeKind::Include {
+                    path: name.to_string(),
+                    library: true,
+                }),
+        )))
+        .map(|include| (None, include));
+
+    // name a, b
+    let call = label()
+        .map_with(|name, e| (name, span(e)))
+        .then(
+            whitespace()
+                .ignore_then(
+                    instruction_input()
+                        .separated_by(just(',').padded_by(opt_whitespace()))
+                        .at_least(1)
+                        .collect::<Vec<_>>(),
+                )
+                .or_not(),
+        )
+        .map(|((name, name_span), args)| NodeKind::Call {
+            name,
+            name_span,
+            args: args.unwrap_or_default(),
+        });
+
+    let maybe_label = spanned_label().then_ignore(whitespace()).or_not();
+
+    let statement = choice((
+        data().map(NodeKind::Data),
+        just("BLK")
+            .ignore_then(whitespace())
+            .ignore_then(expr())
+            .map(NodeKind::Block),
+        just("ORG")
+            .ignore_then(whitespace())
+            .ignore_then(expr())
+            .map(NodeKind::Origin),
+        instruction().map(NodeKind::Instruction),
+    ));
+
+    let labeled_statement = maybe_label.then(statement);
+
+    // Calls without a label must take up the whole line, otherwise a label followed by a call
+    // would be read as a call with the second macro as an argument
+    let end_of_line = opt_whitespace()
+        .then(choice((text::newline(), end(), comment())))
+        .rewind();
+    let labeled_call = choice((
+        call.clone().then_ignore(end_of_line).map(|call| (None, call)),
+        spanned_label().then_ignore(whitespace()).then(call).map(|(label, call)| (Some(label), call)),
+    ));
+
+    choice((equ, set, macro_start, macro_end, include, labeled_statement, labeled_call))
+        .map_with(|(label, kind), e| {
+            let (label, label_span) = label.unzip();
+            Node {
+                label: label.map(Ident::from),
+                label_span,
+                kind,
+                span: span(e),
+                expansion: None,
+            }
         })
-        .then_ignore(text::inline_whitespace())
+        .or_not()
+        .padded_by(opt_whitespace())
+        .then_ignore(comment().or_not())
 }
 
-fn parse<'a>() -> impl Parser<'a, &'a str, Vec<Node<'a>>> {
-    labeled_line()
-        .separated_by(text::newline().repeated().at_least(1))
-        .allow_trailing()
-        .allow_leading()
+fn parse<'a>() -> impl Parser<'a, &'a str, Vec<Node<'a>>, Extra<'a>> {
+    line()
+        .separated_by(text::newline())
         .collect::<Vec<_>>()
+        .map(|lines| lines.into_iter().flatten().collect())
         .then_ignore(end())
 }
 
-fn resolve_labels(ast: Vec<Node>) -> Result<Vec<Instruction<i64>>, ()> {
-    let mut labels = HashMap::new();
+/// What a symbol in the program refers to
+enum Definition<'a, 'b> {
+    /// Address of an instruction
+    Label(i64),
+    /// Constant defined by an expression, along with the location of its definition
+    Constant(&'b Expr<'a>, &'b Span),
+}
+
+/// Labels and constants defined in the program
+struct Symbols<'a, 'b> {
+    definitions: HashMap<Ident<'a>, Definition<'a, 'b>>,
+    /// Labels that are defined somewhere in the program, but not yet while laying out memory
+    later: HashSet<Ident<'a>>,
+}
+
+impl<'a, 'b> Symbols<'a, 'b> {
+    /// Evaluate an expression, reporting arithmetic errors at `span`
+    fn eval(&self, expr: &Expr<'a>, span: &Span) -> Result<i64, AssemblerError> {
+        self.eval_inner(expr, span, &mut Vec::new())
+    }
+
+    /// Evaluate an expression, keeping track of the constants currently being evaluated in
+    /// `resolving` so that circular definitions are caught
+    fn eval_inner(
+        &self,
+        expr: &Expr<'a>,
+        span: &Span,
+        resolving: &mut Vec<Ident<'a>>,
+    ) -> Result<i64, AssemblerError> {
+        match expr {
+            Expr::Num(n) => Ok(*n),
+            Expr::Symbol(ident, symbol_span) => match self.definitions.get(ident) {
+                Some(Definition::Label(addr)) => Ok(*addr),
+                Some(Definition::Constant(expr, definition_span)) => {
+                    if resolving.contains(ident) {
+                        return Err(AssemblerError::CircularDefinition {
+                            name: ident.name.to_string(),
+                            span: (*definition_span).clone(),
+                        });
+                    }
+
+                    resolving.push(*ident);
+                    let value = self.eval_inner(expr, definition_span, resolving);
+                    resolving.pop();
+                    value
+                }
+                None if self.later.contains(ident) => Err(AssemblerError::DefinedLater {
+                    name: ident.name.to_string(),
+                    span: symbol_span.clone(),
+                }),
+                None => Err(AssemblerError::UndefinedSymbol {
+                    name: ident.name.to_string(),
+                    span: symbol_span.clone(),
+                }),
+            },
+            Expr::Neg(expr) => self
+                .eval_inner(expr, span, resolving)?
+                .checked_neg()
+                .ok_or(AssemblerError::Overflow { span: span.clone() }),
+            Expr::Binary(l, op, r) => {
+                let l = self.eval_inner(l, span, resolving)?;
+                let r = self.eval_inner(r, span, resolving)?;
+
+                if matches!(op, BinaryOp::Div | BinaryOp::Rem) && r == 0 {
+                    return Err(AssemblerError::DivisionByZero { span: span.clone() });
+                }
+
+                match op {
+                    BinaryOp::Add => l.checked_add(r),
+                    BinaryOp::Sub => l.checked_sub(r),
+                    BinaryOp::Mul => l.checked_mul(r),
+                    BinaryOp::Div => l.checked_div(r),
+                    BinaryOp::Rem => l.checked_rem(r),
+                }
+                .ok_or(AssemblerError::Overflow { span: span.clone() })
+            }
+        }
+    }
+
+    /// Evaluate an operand that refers to a memory address, checking it can be encoded
+    fn address(&self, data: &NodeInstructionData<'a>) -> Result<i64, AssemblerError> {
+        let addr = self.eval(&data.expr, &data.span)?;
+
+        if !(0..MEMORY_SIZE as i64).contains(&addr) {
+            return Err(AssemblerError::OutOfRange {
+                value: addr,
+                min: 0,
+                max: MEMORY_SIZE as i64 - 1,
+                span: data.span.clone(),
+            });
+        }
+
+        if !data.pointer {
+            return Ok(addr);
+        }
+
+        // Pointers are encoded as MEMORY_SIZE + LOCATION
+        let ptr = addr + MEMORY_SIZE as i64;
+        if ptr > MAX_OPERAND {
+            return Err(AssemblerError::OutOfRange {
+                value: addr,
+                min: 0,
+                max: MAX_OPERAND - MEMORY_SIZE as i64,
+                span: data.span.clone(),
+            });
+        }
+
+        Ok(ptr)
+    }
+
+    /// Evaluate an immediate operand, checking it can be encoded
+    fn immediate(&self, data: &NodeInstructionData<'a>) -> Result<i64, AssemblerError> {
+        let value = self.eval(&data.expr, &data.span)?;
+
+        if !(0..=MAX_OPERAND).contains(&value) {
+            return Err(AssemblerError::OutOfRange {
+                value,
+                min: 0,
+                max: MAX_OPERAND,
+                span: data.span.clone(),
+            });
+        }
+
+        Ok(value)
+    }
+
+    /// Evaluate the data stored by a `DAT` instruction
+    fn data(&self, data: &NodeInstructionData<'a>) -> Result<i64, AssemblerError> {
+        let value = self.eval(&data.expr, &data.span)?;
+
+        if data.pointer {
+            value
+                .checked_add(MEMORY_SIZE as i64)
+                .ok_or(AssemblerError::Overflow { span: data.span.clone() })
+        } else {
+            Ok(value)
+        }
+    }
+}
+
+/// Evaluate a directive operand which must be known while laying out memory, i.e., one that
+/// only refers to constants and labels defined before it
+fn eval_layout(symbols: &Symbols, expr: &Expr, span: &Span) -> Result<usize, AssemblerError> {
+    let value = symbols.eval(expr, span)?;
+
+    if !(0..=MEMORY_SIZE as i64).contains(&value) {
+        return Err(AssemblerError::OutOfRange {
+            value,
+            min: 0,
+            max: MEMORY_SIZE as i64,
+            span: span.clone(),
+        });
+    }
+
+    Ok(value as usize)
+}
+
+fn resolve_labels(ast: Vec<Node>) -> Result<Program, Vec<AssemblerError>> {
+    let mut symbols = Symbols { definitions: HashMap::new(), later: HashSet::new() };
+    let mut errors = Vec::new();
+
+    // FIRST PASS: grab constants, so they can be used to lay out memory, and note which labels exist
+    for node in &ast {
+        if let (Some(label), false) = (node.label, matches!(node.kind, NodeKind::Constant(_))) {
+            symbols.later.insert(label);
+        }
+
+        if let (Some(label), NodeKind::Constant(expr)) = (node.label, &node.kind)
+            && symbols
+                .definitions
+                .insert(label, Definition::Constant(expr, &node.span))
+                .is_some()
+        {
+            errors.push(node.error(AssemblerError::DuplicateSymbol {
+                name: label.name.to_string(),
+                span: node.span.clone(),
+            }));
+        }
+    }
+
+    // SECOND PASS: lay out memory and grab labels
+    let mut addr = 0;
+    let mut addresses = Vec::with_capacity(ast.len());
+    // Number of cells each node occupies, or `None` if it could not be worked out
+    let mut sizes = Vec::with_capacity(ast.len());
+    for node in &ast {
+        let size = match &node.kind {
+            NodeKind::Instruction(_) => Ok(1),
+            NodeKind::Data(items) => Ok(items
+                .iter()
+                .map(|item| match item {
+                    DataItem::Value(_) | DataItem::Zero => 1,
+                    DataItem::String(string) => string.chars().count(),
+                })
+                .sum()),
+            NodeKind::Block(expr) => eval_layout(&symbols, expr, &node.span),
+            NodeKind::Origin(expr) => eval_layout(&symbols, expr, &node.span).map(|origin| {
+                addr = origin;
+                0
+            }),
+            NodeKind::Constant(_) => Ok(0),
+            NodeKind::MacroStart { .. } | NodeKind::MacroEnd | NodeKind::Call { .. } => {
+                unreachable!("macros should have been expanded before resolving labels")
+            }
+            NodeKind::Include { .. } => {
+                unreachable!("includes should have been spliced in before resolving labels")
+            }
+        };
 
-    // FIRST PASS: grab labels
-    for (addr, expr) in ast.iter().enumerate() {
-        if let Some(label) = expr.label {
-            labels.insert(label, addr);
+        if let Some(label) = node.label
+            && !matches!(node.kind, NodeKind::Constant(_))
+            && symbols
+                .definitions
+                .insert(label, Definition::Label(addr as i64))
+                .is_some()
+        {
+            errors.push(node.error(AssemblerError::DuplicateSymbol {
+                name: label.name.to_string(),
+                span: node.span.clone(),
+            }));
+        }
+
+        addresses.push(addr);
+        match size {
+            Ok(size) => {
+                addr += size;
+                sizes.push(Some(size));
+            }
+            Err(error) => {
+                errors.push(node.error(error));
+                sizes.push(None);
+            }
         }
     }
 
-    // SECOND PASS: validate and insert mem address
-    Ok(ast.into_iter().map(|node| {
-        use Instruction::*;
-        macro_rules! label_to_addr {
-            ($($member:ident),*) => {
-                match node.instruction {
-                $(
-                    $member(NodeInstructionData::Num(n)) => {
-                        $member(n)
-                    },
-                    $member(NodeInstructionData::Label(l)) => {
-                        let addr = *labels.get(l).expect(&format!("invalid label {l}"));
-                        $member(addr as i64)
-                    },
-                    $member(NodeInstructionData::Pointer(p)) => {
-                        let addr = *labels.get(p).expect(&format!("invalid pointer label {p}")) + MEMORY_SIZE;
-                        $member(addr as i64)
+    // THIRD PASS: evaluate operands and place cells into memory
+    let mut cells: BTreeMap<usize, Cell> = BTreeMap::new();
+    for ((node, addr), size) in ast.iter().zip(addresses).zip(sizes) {
+        let instructions = match &node.kind {
+            NodeKind::Instruction(instruction) => {
+                use Instruction::*;
+                macro_rules! label_to_addr {
+                    ($($member:ident),*; $($immediate:ident),*) => {
+                        match instruction {
+                        $(
+                            $member(data) => symbols.address(data).map($member),
+                        )*
+                        $(
+                            $immediate(data) => symbols.immediate(data).map($immediate),
+                        )*
+                            DAT(data) => symbols.data(data).map(DAT),
+                            BWN => Ok(BWN),
+                            LDR => Ok(LDR),
+                            INP => Ok(INP),
+                            OUT => Ok(OUT),
+                            INC => Ok(INC),
+                            OTC => Ok(OTC),
+                            RET => Ok(RET),
+                            PSH => Ok(PSH),
+                            POP => Ok(POP),
+                            TAX => Ok(TAX),
+                            TXA => Ok(TXA),
+                            EI => Ok(EI),
+                            DI => Ok(DI),
+                            RTI => Ok(RTI),
+                            HLT => Ok(HLT),
+                        }
                     }
-                )*
-                    DAT(NodeInstructionData::Num(n)) => DAT(n),
-                    BWN => BWN,
-                    INP => INP,
-                    OUT => OUT,
-                    HLT => HLT,
-                    _ => panic!("invalid instruction {:?}", node.instruction),
                 }
+
+                // NOTE: DO NOT INCLUDE STATIC INSTRUCTIONS or DAT!! Add them in the macro above
+                label_to_addr!(
+                    ADD, SUB, STA, STR, LDA, BRA, BRZ, BRP, BRN, BNZ, BRC, BRV, CAL, LDX, STX, BWA, BWO,
+                    BWX, MUL, DIV, MOD, SHL, SHR, SAR, ADDX, SUBX, STAX, LDAX;
+                    ADDI, SUBI, LDAI, BWAI, BWOI, BWXI, MULI, DIVI, MODI, SHLI, SHRI, SARI,
+                    INPP, OUTP
+                )
+                .map(|i| vec![(i, true)])
+            }
+            NodeKind::Data(items) => items
+                .iter()
+                .try_fold(Vec::new(), |mut values, item| {
+                    match item {
+                        DataItem::Value(data) => values.push((Instruction::DAT(symbols.data(data)?), true)),
+                        DataItem::String(string) => {
+                            values.extend(string.chars().map(|c| (Instruction::DAT(c as i64), true)))
+                        }
+                        DataItem::Zero => values.push((Instruction::DAT(0), false)),
+                    }
+                    Ok(values)
+                }),
+            // Sized while laying out memory, as labels defined later could give a different size now
+            NodeKind::Block(_) => match size {
+                Some(size) => Ok(vec![(Instruction::DAT(0), false); size]),
+                // Already reported while laying out memory
+                None => continue,
+            },
+            _ => continue,
+        };
+
+        let instructions = match instructions {
+            Ok(instructions) => instructions,
+            Err(error) => {
+                errors.push(node.error(error));
+                continue;
+            }
+        };
+
+        if addr + instructions.len() > MEMORY_SIZE {
+            errors.push(node.error(AssemblerError::MemoryFull { span: node.span.clone() }));
+            continue;
+        }
+
+        for (offset, (instruction, initialised)) in instructions.into_iter().enumerate() {
+            let address = addr + offset;
+            let cell = Cell {
+                address,
+                instruction,
+                span: node.span.clone(),
+                initialised,
+            };
+
+            if let Some(previous) = cells.insert(address, cell) {
+                errors.push(node.error(AssemblerError::Overlap {
+                    address,
+                    span: node.span.clone(),
+                    previous: previous.span,
+                }));
             }
         }
+    }
 
-        // NOTE: DO NOT INCLUDE STATIC INSTRUCTIONS or DAT!! Add them in the macro above
-        label_to_addr!(ADD, SUB, STA, LDA, BRA, BRZ, BRP, BWA, BWO, BWX)
-    }).collect())
+    if errors.is_empty() {
+        Ok(Program {
+            cells: cells.into_values().collect(),
+        })
+    } else {
+        Err(errors)
+    }
+}
+
+/// Assemble a program, resolving relative includes from the current directory
+pub fn assemble<S: AsRef<str>>(input: S) -> Result<Program, Vec<AssemblerError>> {
+    let mut sources = SourceMap::new();
+    let root = sources.add(SourceOrigin::Input, input.as_ref());
+
+    assemble_sources(&mut sources, root)
 }
 
-pub fn assemble<S: AsRef<str>>(input: S) -> Result<Vec<Instruction<i64>>, ()> {
-    let parser = parse();
-    let ast = parser.parse(input.as_ref()).into_result().map_err(|_| ())?;
+/// Assemble the program in the file `root`, adding any files it includes to the source map.
+/// Errors refer to locations in the source map
+pub fn assemble_sources(
+    sources: &mut SourceMap,
+    root: SourceId,
+) -> Result<Program, Vec<AssemblerError>> {
+    let mut errors = Vec::new();
+    let mut includes = include::Includes::new();
+    include::load(sources, root, &mut Vec::new(), &mut includes, &mut errors);
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let mut ast = Vec::new();
+    include::splice(sources, root, &includes, &mut Default::default(), &mut ast)?;
+    let ast = macros::expand(ast)?;
     resolve_labels(ast)
 }
 
@@ -215,4 +988,554 @@ mod test {
         let parsed = assemble(test_doc).unwrap();
         println!("{:#?}", parsed);
     }
+
+    #[test]
+    fn test_expressions() {
+        let test_doc = indoc! {"
+        SIZE    EQU 3
+        .set    STRIDE, SIZE * 2
+        start   LDA table+2
+                STA @table + 1
+                HLT
+        table   DAT 2*SIZE
+                DAT -(STRIDE - 1) % 4
+                DAT end-start
+        end     DAT
+        "};
+        let parsed = assemble(test_doc).unwrap();
+        assert_eq!(
+            parsed.instructions().collect::<Vec<_>>(),
+            vec![
+                Instruction::LDA(5),
+                Instruction::STA(4 + MEMORY_SIZE as i64),
+                Instruction::HLT,
+                Instruction::DAT(6),
+                Instruction::DAT(-1),
+                Instruction::DAT(6),
+                Instruction::DAT(0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_expression_errors() {
+        let test_doc = indoc! {"
+        A       EQU B
+        B       EQU A + 1
+                LDA missing
+                STA 1000
+                DAT 1 / (A - A)
+        "};
+        let errors = assemble(test_doc).unwrap_err();
+        assert!(matches!(&errors[0], AssemblerError::UndefinedSymbol { name, .. } if name == "missing"));
+        assert!(matches!(&errors[1], AssemblerError::OutOfRange { value: 1000, .. }));
+        assert!(matches!(&errors[2], AssemblerError::CircularDefinition { .. }));
+        assert_eq!(errors[0].line_col(test_doc), (3, 13));
+    }
+
+    #[test]
+    fn test_directives() {
+        let test_doc = indoc! {r#"
+                BRA start
+                ORG 10
+        table   DAT 1, 2, table
+        buffer  BLK 3
+        text    DAT "Hi\n", 0
+        start   LDA table
+                HLT
+        "#};
+        let parsed = assemble(test_doc).unwrap();
+        assert_eq!(
+            parsed.image().collect::<Vec<_>>(),
+            vec![
+                (0, 6000 + 20),
+                (10, 1),
+                (11, 2),
+                (12, 10),
+                (13, 0),
+                (14, 0),
+                (15, 0),
+                (16, 'H' as i64),
+                (17, 'i' as i64),
+                (18, '\n' as i64),
+                (19, 0),
+                (20, 5000 + 10),
+                (21, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_macros() {
+        let test_doc = indoc! {"
+                MACRO INCR var
+                LDA var
+                ADD one
+                STA var
+                ENDM
+
+                MACRO WAIT
+        loop    SUB one
+                BRP loop
+                ENDM
+
+        start   INCR count
+                INCR @ptr
+        here    WAIT
+                WAIT
+                BRA here
+        one     DAT 1
+        count   DAT
+        ptr     DAT count
+        "};
+        let parsed = assemble(test_doc).unwrap();
+        let ptr = 13 + MEMORY_SIZE as i64;
+        assert_eq!(
+            parsed.instructions().collect::<Vec<_>>(),
+            vec![
+                Instruction::LDA(12),
+                Instruction::ADD(11),
+                Instruction::STA(12),
+                Instruction::LDA(ptr),
+                Instruction::ADD(11),
+                Instruction::STA(ptr),
+                Instruction::SUB(11),
+                Instruction::BRP(6),
+                Instruction::SUB(11),
+                Instruction::BRP(8),
+                Instruction::BRA(6),
+                Instruction::DAT(1),
+                Instruction::DAT(0),
+                Instruction::DAT(12),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_index() {
+        let test_doc = indoc! {"
+                MACRO WAIT
+        loop    SUB one
+                BRP loop
+                ENDM
+
+        SIZE    EQU 2
+        start   LDA table + SIZE
+        here    WAIT
+                BRA start
+        one     DAT 1
+        table   BLK SIZE
+        "};
+        let mut sources = SourceMap::new();
+        let root = sources.add(SourceOrigin::Input, test_doc);
+        let index = index(&sources, root).unwrap();
+
+        let definitions: Vec<_> = index
+            .definitions
+            .iter()
+            .map(|definition| (definition.name.as_str(), definition.kind))
+            .collect();
+        assert_eq!(
+            definitions,
+            vec![
+                ("WAIT", crate::SymbolKind::Macro),
+                ("SIZE", crate::SymbolKind::Constant),
+                ("start", crate::SymbolKind::Label),
+                ("here", crate::SymbolKind::Label),
+                ("one", crate::SymbolKind::Label),
+                ("table", crate::SymbolKind::Label),
+            ]
+        );
+        // `loop` is local to the macro, so only its reference to `one` is kept
+        let references: Vec<_> = index.references.iter().map(|reference| reference.name.as_str()).collect();
+        assert_eq!(references, vec!["one", "table", "SIZE", "WAIT", "start", "SIZE"]);
+
+        let here = index.definition("here").unwrap();
+        assert_eq!(&test_doc[here.span.clone()], "here");
+        assert_eq!(index.name_at(here.span.end), Some("here"));
+        assert_eq!(index.references("SIZE").count(), 2);
+        assert_eq!(index.name_at(0), None);
+    }
+
+    #[test]
+    fn test_macro_errors() {
+        let test_doc = indoc! {"
+                MACRO LOAD value
+                LDA missing + value
+                ENDM
+
+                LOAD 1
+        "};
+        let mut sources = SourceMap::new();
+        let root = sources.add(SourceOrigin::Input, test_doc);
+        let errors = assemble_sources(&mut sources, root).unwrap_err();
+        assert!(matches!(
+            &errors[0],
+            AssemblerError::InMacro { error, .. } if matches!(**error, AssemblerError::UndefinedSymbol { .. })
+        ));
+        assert_eq!(
+            errors[0].report(&sources),
+            "line 2, column 5: undefined symbol `missing`\n  in expansion of macro `LOAD` at line 5, column 1"
+        );
+
+        let test_doc = indoc! {"
+                MACRO LOAD value
+                LDA value
+                ENDM
+
+                LOAD 1, 2
+                SAVE
+        "};
+        let errors = assemble(test_doc).unwrap_err();
+        assert!(matches!(&errors[0], AssemblerError::MacroArguments { expected: 1, found: 2, .. }));
+        assert!(matches!(&errors[1], AssemblerError::UndefinedMacro { name, .. } if name == "SAVE"));
+    }
+
+    #[test]
+    fn test_overlap() {
+        let test_doc = indoc! {"
+                LDA 5
+                HLT
+                ORG 1
+                DAT 7
+                ORG 510
+                BLK 3
+        "};
+        let errors = assemble(test_doc).unwrap_err();
+        assert!(matches!(&errors[0], AssemblerError::Overlap { address: 1, .. }));
+        assert!(matches!(&errors[1], AssemblerError::MemoryFull { .. }));
+
+        // The size of a block cannot depend on labels after it, even once they are defined
+        let test_doc = indoc! {"
+                BRA s
+                BLK e - s
+        s       HLT
+        e       HLT
+        "};
+        let errors = assemble(test_doc).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(&errors[0], AssemblerError::DefinedLater { name, .. } if name == "e"));
+    }
+
+    #[test]
+    fn test_immediate() {
+        let test_doc = indoc! {"
+        size    EQU 3
+                LDA #size * 2
+                ADD #1
+                SUB value
+                BWX #255
+                HLT
+        value   DAT 1
+        "};
+        let parsed = assemble(test_doc).unwrap();
+        let instructions = parsed.instructions().collect::<Vec<_>>();
+        assert_eq!(
+            instructions,
+            vec![
+                Instruction::LDAI(6),
+                Instruction::ADDI(1),
+                Instruction::SUB(5),
+                Instruction::BWXI(255),
+                Instruction::HLT,
+                Instruction::DAT(1),
+            ]
+        );
+        assert_eq!(i64::from(instructions[0]), 25006);
+        assert_eq!(Instruction::try_from(21001), Ok(Instruction::ADDI(1)));
+        assert_eq!(instructions[3].to_string(), "BWX #255");
+
+        let errors = assemble("ADD #1000").unwrap_err();
+        assert!(matches!(&errors[0], AssemblerError::OutOfRange { value: 1000, .. }));
+    }
+
+    #[test]
+    fn test_multiplicative() {
+        let test_doc = indoc! {"
+                MUL value
+                DIV #10
+                MOD @value
+                SHL #1
+                SHR value
+                SAR #63
+        value   DAT 2
+        "};
+        let parsed = assemble(test_doc).unwrap();
+        let instructions = parsed.instructions().collect::<Vec<_>>();
+        assert_eq!(
+            instructions,
+            vec![
+                Instruction::MUL(6),
+                Instruction::DIVI(10),
+                Instruction::MOD(6 + MEMORY_SIZE as i64),
+                Instruction::SHLI(1),
+                Instruction::SHR(6),
+                Instruction::SARI(63),
+                Instruction::DAT(2),
+            ]
+        );
+        for instruction in &instructions[..6] {
+            assert_eq!(Instruction::try_from(i64::from(*instruction)), Ok(*instruction));
+        }
+        assert_eq!(instructions[5].to_string(), "SAR #63");
+    }
+
+    #[test]
+    fn test_subroutines() {
+        let test_doc = indoc! {"
+                CAL double
+                HLT
+        double  PSH
+                POP
+                ADD #0
+                RET
+        "};
+        let parsed = assemble(test_doc).unwrap();
+        let instructions = parsed.instructions().collect::<Vec<_>>();
+        assert_eq!(
+            instructions,
+            vec![
+                Instruction::CAL(2),
+                Instruction::HLT,
+                Instruction::PSH,
+                Instruction::POP,
+                Instruction::ADDI(0),
+                Instruction::RET,
+            ]
+        );
+        assert_eq!(i64::from(instructions[0]), 40002);
+        assert_eq!(Instruction::try_from(903), Ok(Instruction::RET));
+    }
+
+    #[test]
+    fn test_indirect() {
+        let test_doc = indoc! {"
+                LDX count
+                LDA table, X
+                STA table+1,X
+                STR ptr
+                TXA
+                HLT
+        count   DAT 1
+        ptr     DAT table
+        table   DAT 4, 5, 6
+        "};
+        let parsed = assemble(test_doc).unwrap();
+        let instructions = parsed.instructions().collect::<Vec<_>>();
+        assert_eq!(
+            &instructions[..6],
+            &[
+                Instruction::LDX(6),
+                Instruction::LDAX(8),
+                Instruction::STAX(9),
+                Instruction::STR(7),
+                Instruction::TXA,
+                Instruction::HLT,
+            ]
+        );
+        assert_eq!(i64::from(instructions[1]), 55008);
+        assert_eq!(Instruction::try_from(4007), Ok(Instruction::STR(7)));
+        assert_eq!(instructions[2].to_string(), "STA 9,X");
+    }
+
+    #[test]
+    fn test_branches() {
+        let test_doc = indoc! {"
+        loop    BRN loop
+                BNZ loop
+                BRC loop
+                BRV loop
+        "};
+        let parsed = assemble(test_doc).unwrap();
+        let instructions = parsed.instructions().collect::<Vec<_>>();
+        assert_eq!(
+            instructions,
+            vec![
+                Instruction::BRN(0),
+                Instruction::BNZ(0),
+                Instruction::BRC(0),
+                Instruction::BRV(0),
+            ]
+        );
+        assert_eq!(
+            instructions.iter().map(|&i| i64::from(i)).collect::<Vec<_>>(),
+            vec![9000, 43000, 44000, 45000]
+        );
+    }
+
+    #[test]
+    fn test_characters() {
+        let test_doc = indoc! {"
+                INC
+                SUB #'a' - 'A'
+                OTC
+                LDA #'\\n'
+                DAT 'x', \"'\"
+        "};
+        let parsed = assemble(test_doc).unwrap();
+        let instructions = parsed.instructions().collect::<Vec<_>>();
+        assert_eq!(
+            instructions,
+            vec![
+                Instruction::INC,
+                Instruction::SUBI(32),
+                Instruction::OTC,
+                Instruction::LDAI(10),
+                Instruction::DAT(120),
+                Instruction::DAT(39),
+            ]
+        );
+        assert_eq!(i64::from(Instruction::<i64>::OTC), 922);
+    }
+
+    #[test]
+    fn test_interrupts() {
+        let test_doc = indoc! {"
+                     EI
+                     DI
+                     HLT
+                tick RTI
+                     ORG 473
+                     DAT tick
+        "};
+        let parsed = assemble(test_doc).unwrap();
+        let image = parsed.image().collect::<Vec<_>>();
+        assert_eq!(image, vec![(0, 906), (1, 907), (2, 1), (3, 908), (473, 3)]);
+        // Only cells emitted as instructions are code
+        assert_eq!(parsed.code().collect::<Vec<_>>(), vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_ports() {
+        let test_doc = indoc! {"
+                rng  EQU 1
+                     INP rng
+                     OUT rng + 1
+                     INP // from the user
+                     OUT
+                     OUT 0
+        "};
+        let parsed = assemble(test_doc).unwrap();
+        let instructions = parsed.instructions().collect::<Vec<_>>();
+        assert_eq!(
+            instructions,
+            vec![
+                Instruction::INPP(1),
+                Instruction::OUTP(2),
+                Instruction::INP,
+                Instruction::OUT,
+                Instruction::OUTP(0),
+            ]
+        );
+        assert_eq!(i64::from(Instruction::<i64>::INPP(3)), 46003);
+
+        let errors = assemble("INP 1000").unwrap_err();
+        assert!(matches!(&errors[0], AssemblerError::OutOfRange { value: 1000, .. }));
+    }
+
+    #[test]
+    fn test_include() {
+        let dir = std::env::temp_dir().join(format!("lmp-include-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        let write = |name: &str, text: &str| std::fs::write(dir.join(name), text).unwrap();
+        write("main.lmc", "LDA value\nHLT\nINCLUDE \"lib/data.lmc\"\nINCLUDE \"lib/data.lmc\"\n");
+        write("lib/data.lmc", "INCLUDE \"more.lmc\"\nvalue DAT 5\n");
+        write("lib/more.lmc", "other DAT 6\n");
+        write("lib/cycle.lmc", "INCLUDE \"../lib/cycle.lmc\"\n");
+
+        // Files are only included once, relative to the file including them
+        let mut sources = SourceMap::new();
+        let root = sources.add_file(dir.join("main.lmc")).unwrap();
+        let parsed = assemble_sources(&mut sources, root).unwrap();
+        assert_eq!(
+            parsed.instructions().collect::<Vec<_>>(),
+            vec![Instruction::LDA(3), Instruction::HLT, Instruction::DAT(6), Instruction::DAT(5)]
+        );
+
+        let mut sources = SourceMap::new();
+        let root = sources.add_file(dir.join("lib/cycle.lmc")).unwrap();
+        let errors = assemble_sources(&mut sources, root).unwrap_err();
+        assert!(matches!(&errors[0], AssemblerError::IncludeCycle { .. }));
+
+        let errors = assemble("INCLUDE \"missing.lmc\"").unwrap_err();
+        assert!(matches!(&errors[0], AssemblerError::Include { path, .. } if path == "missing.lmc"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_library() {
+        let test_doc = indoc! {"
+                INCLUDE <print.lmc>
+                INCLUDE <shift.lmc>
+                INP
+                STA value
+                SHIFTR value, one, value
+                PRINTNUM value
+                HLT
+        value   DAT
+        one     DAT 1
+        "};
+        assert!(assemble(test_doc).is_ok());
+
+        // Labels in the library are local to each expansion, so arguments never refer to them
+        let mut sources = SourceMap::new();
+        let root = sources.add(SourceOrigin::Input, "INCLUDE <math.lmc>\nMODULO loop, 3, 4\nBRA loop");
+        let errors = assemble_sources(&mut sources, root).unwrap_err();
+        assert_eq!(errors[0].report(&sources), "line 2, column 8: undefined symbol `loop`\n  in expansion of macro `MODULO` at line 2, column 1");
+
+        let errors = assemble("INCLUDE <missing.lmc>").unwrap_err();
+        assert!(matches!(&errors[0], AssemblerError::Include { path, .. } if path == "missing.lmc"));
+    }
+
+    #[test]
+    fn test_format() {
+        let test_doc = indoc! {"
+            // Count down from a number
+              INCLUDE <print.lmc>
+            start inp   // read it
+            loop   sta count
+               printnum count  // show it
+            lda count
+              SUB #1
+            brp loop
+
+
+            hlt
+            count dat  // left alone
+            table Dat 1,2 ,  3
+            lo    lda table , x
+            nl    DAT '/', \"a // b\"
+        "};
+        let expected = indoc! {"
+            // Count down from a number
+            INCLUDE <print.lmc>
+            start INP            // read it
+            loop  STA count
+                  printnum count // show it
+                  LDA count
+                  SUB #1
+                  BRP loop
+
+                  HLT
+            count DAT // left alone
+            table DAT 1, 2, 3
+            lo    LDA table,X
+            nl    DAT '/', \"a // b\"
+        "};
+        assert_eq!(format(test_doc).unwrap(), expected);
+        assert_eq!(format(expected).unwrap(), expected);
+
+        // Names spelt like opcodes are left alone
+        assert_eq!(format("add DAT 1\nLDA add\n").unwrap(), "add DAT 1\n    LDA add\n");
+
+        // Laid out programs are left as they are
+        for (_, text) in crate::library::FILES {
+            let formatted = format(text).unwrap();
+            assert_eq!(format(&formatted).unwrap(), formatted);
+        }
+
+        let errors = format("lda #1\nBRA )\n").unwrap_err();
+        assert_eq!(errors[0].line_col("lda #1\nBRA )\n").0, 2);
+    }
 }
@@ -0,0 +1,338 @@
+//! Formatting of programs into a canonical layout
+
+use super::*;
+use std::collections::HashSet;
+use std::ops::Range;
+
+/// Smallest width of the label column, so that short labels still leave code indented
+const MIN_LABEL_WIDTH: usize = 3;
+/// Instructions that can be indexed with `,X`
+const INDEXED: &[&str] = &["ADD", "SUB", "STA", "LDA"];
+
+/// A line split into the columns it is laid out in. Ranges are byte offsets into the line
+#[derive(Debug, Default)]
+struct Line {
+    label: Option<Range<usize>>,
+    mnemonic: Option<Range<usize>>,
+    /// The mnemonic in upper case, if it is an opcode or directive
+    keyword: Option<String>,
+    /// Each operand, split on commas
+    operands: Vec<Range<usize>>,
+    comment: Option<Range<usize>>,
+    /// Whether the first thing on the line is indented
+    indented: bool,
+}
+
+/// Lay out a program in columns, with labels first, then mnemonics, operands and comments
+/// aligned. Opcodes and directives are put into upper case, and comments and blank lines are kept
+/// (runs of blank lines become one).
+///
+/// Returns the syntax errors in the program if it cannot be parsed, in which case it is left as
+/// is. Other errors, such as undefined symbols, are not checked for.
+pub fn format(source: &str) -> Result<String, Vec<AssemblerError>> {
+    let names = Names::new(source);
+    let mut lines = Vec::new();
+    let mut start = 0;
+    for text in source.split_inclusive('\n') {
+        let end = start + text.len();
+        let text = text.strip_suffix('\n').unwrap_or(text);
+        let text = text.strip_suffix('\r').unwrap_or(text);
+        lines.push((start, text, split(text, &names)));
+        start = end;
+    }
+
+    // Changing case keeps every offset the same, so errors point into the original
+    let mut normalised = source.to_string();
+    for (start, text, line) in &lines {
+        if let (Some(mnemonic), Some(keyword)) = (&line.mnemonic, &line.keyword) {
+            normalised.replace_range(start + mnemonic.start..start + mnemonic.end, keyword);
+        }
+        if let Some(index) = index_register(text, line) {
+            normalised.replace_range(start + index.start..start + index.end, "X");
+        }
+    }
+
+    let mut sources = SourceMap::new();
+    let id = sources.add(SourceOrigin::Input, normalised);
+    include::parse_file(&sources, id)?;
+
+    let lines: Vec<_> = lines.into_iter().map(|(_, text, line)| (text, line)).collect();
+    Ok(layout(&lines))
+}
+
+/// Names defined by a program and the files it includes from the standard library
+#[derive(Debug, Default)]
+struct Names<'a> {
+    /// Labels, constants and macros, which are never put into upper case even if they are spelt
+    /// like an opcode in lower case
+    defined: HashSet<&'a str>,
+    macros: HashSet<&'a str>,
+}
+
+impl<'a> Names<'a> {
+    fn new(source: &'a str) -> Self {
+        let mut names = Self::default();
+        names.add(source, &mut HashSet::new());
+        names
+    }
+
+    fn add(&mut self, source: &'a str, included: &mut HashSet<&'static str>) {
+        let none = HashSet::new();
+        for text in source.lines() {
+            let code = &text[..split_comment(text).0];
+            let mut words = code.split_whitespace();
+            let (Some(first), second) = (words.next(), words.next()) else { continue };
+
+            match (keyword(first, &none).as_deref(), second) {
+                (Some("MACRO"), Some(name)) => {
+                    self.defined.insert(name);
+                    self.macros.insert(name);
+                }
+                (Some("INCLUDE"), Some(name)) => {
+                    let library = name.strip_prefix('<').and_then(|name| name.strip_suffix('>'));
+                    if let Some((name, text)) = library.and_then(crate::library::get)
+                        && included.insert(name)
+                    {
+                        self.add(text, included);
+                    }
+                }
+                (Some(".set"), Some(name)) => _ = self.defined.insert(name.trim_end_matches(',')),
+                (None, _) => _ = self.defined.insert(first),
+                // e.g. `add DAT 1`
+                (Some(_), Some(second)) if !RESERVED.contains(&first) && keyword(second, &none).is_some() => {
+                    self.defined.insert(first);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// The opcode or directive a word is, in its canonical case
+fn keyword(word: &str, defined: &HashSet<&str>) -> Option<String> {
+    // Words already in upper case cannot be names, as the parser does not allow it
+    if defined.contains(word) && !RESERVED.contains(&word) {
+        return None;
+    }
+
+    if word.eq_ignore_ascii_case(".set") {
+        return Some(".set".to_string());
+    }
+    let upper = word.to_ascii_uppercase();
+    RESERVED.contains(&upper.as_str()).then_some(upper)
+}
+
+/// Split a line into its columns
+fn split(text: &str, names: &Names) -> Line {
+    let (code_end, comment) = split_comment(text);
+    let code = &text[..code_end];
+    let words = words(code);
+    let mut line = Line {
+        comment,
+        indented: text.starts_with(char::is_whitespace),
+        ..Line::default()
+    };
+    let Some(first) = words.first() else { return line };
+
+    let first_keyword = keyword(&code[first.clone()], &names.defined);
+    let second_keyword = words.get(1).and_then(|second| keyword(&code[second.clone()], &names.defined));
+    let mnemonic = match (first_keyword.is_some(), second_keyword.is_some(), words.get(1)) {
+        (true, false, _) | (false, false, None) => 0,
+        (_, true, _) => 1,
+        // Either a macro called with arguments, or a label followed by a macro. Macros from other
+        // files are not known, in which case labels are assumed to start at the beginning of the
+        // line as they usually do
+        (false, false, Some(second)) => {
+            let call = names.macros.contains(&code[first.clone()])
+                || code[second.clone()].ends_with(',')
+                || (line.indented && !names.macros.contains(&code[second.clone()]));
+            if call { 0 } else { 1 }
+        }
+    };
+
+    if mnemonic == 1 {
+        line.label = Some(first.clone());
+    }
+    line.mnemonic = Some(words[mnemonic].clone());
+    line.keyword = [first_keyword, second_keyword].into_iter().nth(mnemonic).flatten();
+    line.operands = operands(code, words[mnemonic].end);
+    line
+}
+
+/// Ranges of the words in some code
+fn words(code: &str) -> Vec<Range<usize>> {
+    let mut words = Vec::new();
+    let mut start = None;
+    for (offset, c) in code.char_indices().chain([(code.len(), ' ')]) {
+        match (c.is_whitespace(), start) {
+            (true, Some(word)) => {
+                words.push(word..offset);
+                start = None;
+            }
+            (false, None) => start = Some(offset),
+            _ => {}
+        }
+    }
+
+    words
+}
+
+/// Where the code of a line ends, along with the comment after it. `//` inside a string or
+/// character literal does not start a comment
+fn split_comment(text: &str) -> (usize, Option<Range<usize>>) {
+    let mut quote = None;
+    let mut escaped = false;
+    for (offset, c) in text.char_indices() {
+        match quote {
+            Some(_) if escaped => escaped = false,
+            Some(_) if c == '\\' => escaped = true,
+            Some(open) if c == open => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if text[offset..].starts_with("//") => return (offset, Some(offset..text.trim_end().len())),
+            None => {}
+        }
+    }
+
+    (text.len(), None)
+}
+
+/// Ranges of the comma-separated operands after `start`, trimmed. Commas in literals and
+/// parentheses do not separate operands
+fn operands(code: &str, start: usize) -> Vec<Range<usize>> {
+    let mut operands = Vec::new();
+    let mut from = start;
+    let mut quote = None;
+    let mut escaped = false;
+    let mut depth = 0;
+    for (offset, c) in code[start..].char_indices().map(|(offset, c)| (start + offset, c)) {
+        match quote {
+            Some(_) if escaped => escaped = false,
+            Some(_) if c == '\\' => escaped = true,
+            Some(open) if c == open => quote = None,
+            Some(_) => {}
+            None => match c {
+                '"' | '\'' => quote = Some(c),
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                ',' if depth == 0 => {
+                    operands.push(from..offset);
+                    from = offset + 1;
+                }
+                _ => {}
+            },
+        }
+    }
+    operands.push(from..code.len());
+
+    let operands: Vec<_> = operands
+        .into_iter()
+        .map(|range| {
+            let operand = &code[range.clone()];
+            let start = range.start + (operand.len() - operand.trim_start().len());
+            start..range.start + operand.trim_end().len()
+        })
+        .collect();
+
+    // An operand list that is entirely empty is no operands at all
+    match operands.as_slice() {
+        [only] if only.is_empty() => Vec::new(),
+        _ => operands,
+    }
+}
+
+/// The `X` of an indexed operand, which may be written in lower case
+fn index_register(text: &str, line: &Line) -> Option<Range<usize>> {
+    let indexed = line.keyword.as_deref().is_some_and(|keyword| INDEXED.contains(&keyword));
+    match line.operands.as_slice() {
+        [_, index] if indexed && text[index.clone()].eq_ignore_ascii_case("x") => Some(index.clone()),
+        _ => None,
+    }
+}
+
+fn layout(lines: &[(&str, Line)]) -> String {
+    let label_width = lines
+        .iter()
+        .filter_map(|(_, line)| line.label.as_ref().map(Range::len))
+        .max()
+        .unwrap_or(0)
+        .max(MIN_LABEL_WIDTH);
+    // Directives and macro calls with longer names are followed by a single space instead
+    let mnemonic_width = lines
+        .iter()
+        .filter_map(|(_, line)| line.keyword.as_deref())
+        .filter(|keyword| keyword.len() <= 3)
+        .map(str::len)
+        .max()
+        .unwrap_or(3);
+
+    let codes: Vec<Option<String>> = lines
+        .iter()
+        .map(|(text, line)| {
+            let mnemonic = line.mnemonic.as_ref()?;
+            let mnemonic = line.keyword.as_deref().unwrap_or(&text[mnemonic.clone()]);
+
+            let operands = match index_register(text, line) {
+                Some(_) => format!("{},X", &text[line.operands[0].clone()]),
+                None => line.operands.iter().map(|operand| &text[operand.clone()]).collect::<Vec<_>>().join(", "),
+            };
+
+            let code = match (mnemonic, line.label.as_ref()) {
+                ("INCLUDE", _) => format!("{mnemonic} {operands}"),
+                (_, label) => {
+                    let label = label.map_or("", |label| &text[label.clone()]);
+                    format!("{label:label_width$} {mnemonic:mnemonic_width$} {operands}")
+                }
+            };
+            Some(code.trim_end().to_string())
+        })
+        .collect();
+
+    let mut output = String::new();
+    let mut blank = false;
+    let mut comment_column = None;
+    for (index, (text, line)) in lines.iter().enumerate() {
+        let Some(comment) = &line.comment else {
+            match &codes[index] {
+                Some(code) => push_line(&mut output, &mut blank, code),
+                None => {
+                    blank = !output.is_empty();
+                    comment_column = None;
+                }
+            }
+            continue;
+        };
+
+        let comment = &text[comment.clone()];
+        let Some(code) = &codes[index] else {
+            // Comments on their own line are indented along with code if they were indented
+            let indent = if line.indented { label_width + 1 } else { 0 };
+            push_line(&mut output, &mut blank, &format!("{:indent$}{comment}", ""));
+            continue;
+        };
+
+        // Trailing comments are aligned with those on the following lines, up to a blank line
+        let column = *comment_column.get_or_insert_with(|| {
+            (index..lines.len())
+                .map_while(|index| match (&codes[index], &lines[index].1.comment) {
+                    (None, None) => None,
+                    (Some(code), Some(_)) => Some(code.chars().count()),
+                    _ => Some(0),
+                })
+                .max()
+                .unwrap_or(0)
+        });
+        push_line(&mut output, &mut blank, &format!("{code:column$} {comment}"));
+    }
+
+    output
+}
+
+/// Add a line to the output, after a blank line if one came before it
+fn push_line(output: &mut String, blank: &mut bool, line: &str) {
+    if std::mem::take(blank) {
+        output.push('\n');
+    }
+    output.push_str(line);
+    output.push('\n');
+}
@@ -0,0 +1,146 @@
+//! Loading of files included with `INCLUDE`
+
+use super::*;
+use crate::library;
+use std::collections::HashSet;
+use std::fs;
+
+/// Parse a single file in the source map
+pub(super) fn parse_file(sources: &SourceMap, id: SourceId) -> Result<Vec<Node<'_>>, Vec<AssemblerError>> {
+    let offset = sources.offset(id);
+
+    parse()
+        .parse_with_state(sources.file(id).text.as_str(), &mut SimpleState(offset))
+        .into_result()
+        .map_err(|errors| {
+            errors
+                .into_iter()
+                .map(|error| {
+                    let span = error.span().into_range();
+                    AssemblerError::Syntax {
+                        message: error.to_string(),
+                        span: span.start + offset..span.end + offset,
+                    }
+                })
+                .collect()
+        })
+}
+
+/// Files included by a program, mapping the start of the span of each `INCLUDE` directive to the
+/// file it refers to
+pub(super) type Includes = HashMap<usize, SourceId>;
+
+/// Add every file included by the file `id` to the source map, recursively
+///
+/// `stack` holds the chain of files currently being loaded, so that a file including itself can be
+/// detected
+pub(super) fn load(
+    sources: &mut SourceMap,
+    id: SourceId,
+    stack: &mut Vec<SourceId>,
+    includes: &mut Includes,
+    errors: &mut Vec<AssemblerError>,
+) {
+    // Take the directives out of the AST first, as it borrows from the source map
+    let directives: Vec<_> = match parse_file(sources, id) {
+        Ok(ast) => ast
+            .into_iter()
+            .filter_map(|node| match node.kind {
+                NodeKind::Include { path, library } => Some((path, library, node.span)),
+                _ => None,
+            })
+            .collect(),
+        Err(mut parse_errors) => {
+            errors.append(&mut parse_errors);
+            return;
+        }
+    };
+
+    stack.push(id);
+    for (path, library, span) in directives {
+        let origin = match resolve(&sources.file(id).origin, &path, library) {
+            Ok(origin) => origin,
+            Err(message) => {
+                errors.push(AssemblerError::Include { path, message, span });
+                continue;
+            }
+        };
+
+        let included = match sources.find(&origin) {
+            Some(included) if stack.contains(&included) => {
+                errors.push(AssemblerError::IncludeCycle { path, span });
+                continue;
+            }
+            Some(included) => included,
+            None => {
+                let text = match read(&origin) {
+                    Ok(text) => text,
+                    Err(message) => {
+                        errors.push(AssemblerError::Include { path, message, span });
+                        continue;
+                    }
+                };
+
+                let included = sources.add(origin, text);
+                load(sources, included, stack, includes, errors);
+                included
+            }
+        };
+
+        includes.insert(span.start, included);
+    }
+    stack.pop();
+}
+
+/// Work out which file an `INCLUDE` directive in a file from `from` refers to
+fn resolve(from: &SourceOrigin, path: &str, library: bool) -> Result<SourceOrigin, String> {
+    // Files in the standard library can only include each other
+    if library || matches!(from, SourceOrigin::Library(_)) {
+        return library::get(path)
+            .map(|(name, _)| SourceOrigin::Library(name))
+            .ok_or_else(|| "no such file in the standard library".to_string());
+    }
+
+    let path = match from {
+        SourceOrigin::File(file) => file.parent().unwrap_or(Path::new("")).join(path),
+        _ => Path::new(path).to_path_buf(),
+    };
+
+    fs::canonicalize(path)
+        .map(SourceOrigin::File)
+        .map_err(|error| error.to_string())
+}
+
+fn read(origin: &SourceOrigin) -> Result<String, String> {
+    match origin {
+        SourceOrigin::File(path) => fs::read_to_string(path).map_err(|error| error.to_string()),
+        SourceOrigin::Library(name) => Ok(library::get(name).map(|(_, text)| text).unwrap_or_default().to_string()),
+        SourceOrigin::Input => unreachable!("input is never included"),
+    }
+}
+
+/// Parse the file `id` and the files it includes into a single AST. Each file is included in place
+/// of the first `INCLUDE` directive referring to it, later ones are ignored
+pub(super) fn splice<'a>(
+    sources: &'a SourceMap,
+    id: SourceId,
+    includes: &Includes,
+    included: &mut HashSet<SourceId>,
+    out: &mut Vec<Node<'a>>,
+) -> Result<(), Vec<AssemblerError>> {
+    included.insert(id);
+
+    for node in parse_file(sources, id)? {
+        if let NodeKind::Include { .. } = node.kind {
+            if let Some(&file) = includes.get(&node.span.start)
+                && !included.contains(&file)
+            {
+                splice(sources, file, includes, included, out)?;
+            }
+        } else {
+            out.push(node);
+        }
+    }
+
+    Ok(())
+}
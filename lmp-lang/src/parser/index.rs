@@ -0,0 +1,110 @@
+//! Indexing of the symbols in a file, for editor tooling
+
+use super::*;
+use crate::{SymbolDefinition, SymbolIndex, SymbolKind, SymbolReference};
+use std::collections::HashSet;
+
+/// Find the labels, constants and macros defined in the file `id` and every reference to them.
+/// Includes are not followed and macros are not expanded, and labels and parameters local to a
+/// macro body are left out
+pub fn index(sources: &SourceMap, id: SourceId) -> Result<SymbolIndex, Vec<AssemblerError>> {
+    let ast = include::parse_file(sources, id)?;
+    let macros: HashSet<&str> = ast
+        .iter()
+        .filter_map(|node| match node.kind {
+            NodeKind::MacroStart { name, .. } => Some(name),
+            _ => None,
+        })
+        .collect();
+
+    let mut index = SymbolIndex::default();
+    // Names local to the macro body currently being indexed, and the references made in it
+    let mut body: Option<(HashSet<&str>, Vec<SymbolReference>)> = None;
+    for mut node in ast {
+        macros::disambiguate(&mut node, &macros);
+
+        let mut references = Vec::new();
+        match &node.kind {
+            NodeKind::MacroStart { name, name_span, params } => {
+                index.definitions.push(SymbolDefinition {
+                    name: name.to_string(),
+                    kind: SymbolKind::Macro,
+                    span: name_span.clone(),
+                    line: node.span.clone(),
+                });
+                body = Some((params.iter().copied().collect(), Vec::new()));
+                continue;
+            }
+            NodeKind::MacroEnd => {
+                if let Some((locals, references)) = body.take() {
+                    let global = references.into_iter().filter(|reference| !locals.contains(reference.name.as_str()));
+                    index.references.extend(global);
+                }
+                continue;
+            }
+            NodeKind::Instruction(instruction) => {
+                instruction.clone().map(|data| symbol_references(&data.expr, &mut references));
+            }
+            NodeKind::Data(items) => {
+                for item in items {
+                    if let DataItem::Value(data) = item {
+                        symbol_references(&data.expr, &mut references);
+                    }
+                }
+            }
+            NodeKind::Block(expr) | NodeKind::Origin(expr) | NodeKind::Constant(expr) => {
+                symbol_references(expr, &mut references);
+            }
+            NodeKind::Call { name, name_span, args } => {
+                references.push(SymbolReference {
+                    name: name.to_string(),
+                    span: name_span.clone(),
+                });
+                for arg in args {
+                    symbol_references(&arg.expr, &mut references);
+                }
+            }
+            NodeKind::Include { .. } => {}
+        }
+
+        match &mut body {
+            Some((locals, body_references)) => {
+                locals.extend(node.label.map(|label| label.name));
+                body_references.append(&mut references);
+            }
+            None => {
+                if let (Some(label), Some(label_span)) = (node.label, &node.label_span) {
+                    let kind = match node.kind {
+                        NodeKind::Constant(_) => SymbolKind::Constant,
+                        _ => SymbolKind::Label,
+                    };
+                    index.definitions.push(SymbolDefinition {
+                        name: label.name.to_string(),
+                        kind,
+                        span: label_span.clone(),
+                        line: node.span.clone(),
+                    });
+                }
+                index.references.append(&mut references);
+            }
+        }
+    }
+
+    Ok(index)
+}
+
+/// Collect the symbols an expression refers to
+fn symbol_references(expr: &Expr, out: &mut Vec<SymbolReference>) {
+    match expr {
+        Expr::Num(_) => {}
+        Expr::Symbol(ident, span) => out.push(SymbolReference {
+            name: ident.name.to_string(),
+            span: span.clone(),
+        }),
+        Expr::Neg(expr) => symbol_references(expr, out),
+        Expr::Binary(l, _, r) => {
+            symbol_references(l, out);
+            symbol_references(r, out);
+        }
+    }
+}
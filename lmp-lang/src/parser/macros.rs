@@ -0,0 +1,282 @@
+//! Macro definitions and expansion
+
+use super::*;
+use std::collections::HashSet;
+
+/// A macro defined with `MACRO name a, b` ... `ENDM`
+struct Macro<'a> {
+    params: Vec<&'a str>,
+    body: Vec<Node<'a>>,
+}
+
+/// Collect the macro definitions out of the AST and replace every call with the body of the macro
+pub(super) fn expand(ast: Vec<Node>) -> Result<Vec<Node>, Vec<AssemblerError>> {
+    let mut errors = Vec::new();
+    let mut macros = HashMap::new();
+    let mut nodes = Vec::new();
+
+    let mut ast = ast.into_iter();
+    while let Some(node) = ast.next() {
+        match node.kind {
+            NodeKind::MacroStart { name, params, .. } => {
+                let mut body = Vec::new();
+                let mut terminated = false;
+
+                for node in ast.by_ref() {
+                    match node.kind {
+                        NodeKind::MacroEnd => {
+                            terminated = true;
+                            break;
+                        }
+                        NodeKind::MacroStart { .. } => {
+                            errors.push(AssemblerError::NestedMacro { span: node.span })
+                        }
+                        _ => body.push(node),
+                    }
+                }
+
+                if !terminated {
+                    errors.push(AssemblerError::UnterminatedMacro {
+                        name: name.to_string(),
+                        span: node.span.clone(),
+                    });
+                }
+
+                if macros.insert(name, Macro { params, body }).is_some() {
+                    errors.push(AssemblerError::DuplicateSymbol {
+                        name: name.to_string(),
+                        span: node.span,
+                    });
+                }
+            }
+            NodeKind::MacroEnd => errors.push(AssemblerError::UnexpectedEndm { span: node.span }),
+            _ => nodes.push(node),
+        }
+    }
+
+    let names: HashSet<&str> = macros.keys().copied().collect();
+    for node in nodes
+        .iter_mut()
+        .chain(macros.values_mut().flat_map(|definition| definition.body.iter_mut()))
+    {
+        disambiguate(node, &names);
+    }
+
+    let mut expander = Expander {
+        macros: &macros,
+        scopes: 0,
+        errors: &mut errors,
+    };
+    let mut expanded = Vec::with_capacity(nodes.len());
+    for node in nodes {
+        expander.expand(node, &mut expanded);
+    }
+
+    if errors.is_empty() {
+        Ok(expanded)
+    } else {
+        Err(errors)
+    }
+}
+
+/// A label followed by a macro without arguments (`here INCR`) is parsed as a call to the label
+/// with the macro as an argument, which of the two was meant can only be told once the names of
+/// the macros are known
+pub(super) fn disambiguate<'a>(node: &mut Node<'a>, macros: &HashSet<&'a str>) {
+    let NodeKind::Call { name, name_span, args } = &mut node.kind else {
+        return;
+    };
+
+    if node.label.is_some() || macros.contains(name) {
+        return;
+    }
+
+    if let [NodeInstructionData { pointer: false, expr: Expr::Symbol(ident, span), .. }] =
+        args.as_slice()
+        && macros.contains(ident.name)
+    {
+        node.label = Some(Ident::from(*name));
+        node.label_span = Some(name_span.clone());
+        *name = ident.name;
+        *name_span = span.clone();
+        args.clear();
+    }
+}
+
+struct Expander<'a, 'b> {
+    macros: &'b HashMap<&'a str, Macro<'a>>,
+    /// Number of scopes handed out to expansions so far
+    scopes: usize,
+    errors: &'b mut Vec<AssemblerError>,
+}
+
+impl<'a> Expander<'a, '_> {
+    /// Push `node` to `out`, replacing it with the body of the macro if it is a call
+    fn expand(&mut self, node: Node<'a>, out: &mut Vec<Node<'a>>) {
+        let NodeKind::Call { name, name_span, args } = &node.kind else {
+            out.push(node);
+            return;
+        };
+
+        let Some(definition) = self.macros.get(name) else {
+            self.errors.push(node.error(AssemblerError::UndefinedMacro {
+                name: name.to_string(),
+                span: name_span.clone(),
+            }));
+            return;
+        };
+
+        let mut parent = node.expansion.as_ref();
+        while let Some(expansion) = parent {
+            if expansion.name == *name {
+                self.errors.push(node.error(AssemblerError::RecursiveMacro {
+                    name: name.to_string(),
+                    span: node.span.clone(),
+                }));
+                return;
+            }
+            parent = expansion.parent.as_ref();
+        }
+
+        if definition.params.len() != args.len() {
+            self.errors.push(node.error(AssemblerError::MacroArguments {
+                name: name.to_string(),
+                expected: definition.params.len(),
+                found: args.len(),
+                span: node.span.clone(),
+            }));
+            return;
+        }
+
+        // The label of the call points at the first cell of the expansion
+        if let Some(label) = node.label {
+            out.push(Node {
+                label: Some(label),
+                label_span: node.label_span.clone(),
+                kind: NodeKind::Block(Expr::Num(0)),
+                span: node.span.clone(),
+                expansion: node.expansion.clone(),
+            });
+        }
+
+        self.scopes += 1;
+        let substitution = Substitution {
+            params: &definition.params,
+            args,
+            locals: definition
+                .body
+                .iter()
+                .filter_map(|node| node.label.map(|label| label.name))
+                .collect(),
+            scope: self.scopes,
+        };
+        let expansion = Rc::new(Expansion {
+            name,
+            call_site: node.span.clone(),
+            parent: node.expansion.clone(),
+        });
+
+        for body_node in &definition.body {
+            let expanded = Node {
+                label: body_node.label.map(|label| substitution.ident(label)),
+                label_span: body_node.label_span.clone(),
+                kind: substitution.kind(&body_node.kind),
+                span: body_node.span.clone(),
+                expansion: Some(expansion.clone()),
+            };
+
+            self.expand(expanded, out);
+        }
+    }
+}
+
+/// Replacements made to a macro body for a single expansion
+struct Substitution<'a, 'b> {
+    params: &'b [&'a str],
+    args: &'b [NodeInstructionData<'a>],
+    /// Labels defined inside the macro body
+    locals: HashSet<&'a str>,
+    scope: usize,
+}
+
+impl<'a> Substitution<'a, '_> {
+    fn ident(&self, ident: Ident<'a>) -> Ident<'a> {
+        if self.locals.contains(ident.name) {
+            Ident {
+                name: ident.name,
+                scope: self.scope,
+            }
+        } else {
+            ident
+        }
+    }
+
+    /// Substitute parameters in an expression, marking the result as a pointer if a pointer was
+    /// passed in for any of them
+    fn expr(&self, expr: &Expr<'a>, pointer: &mut bool) -> Expr<'a> {
+        match expr {
+            Expr::Num(n) => Expr::Num(*n),
+            Expr::Symbol(ident, span) => {
+                if let Some(index) = self.params.iter().position(|param| *param == ident.name) {
+                    let arg = &self.args[index];
+                    *pointer |= arg.pointer;
+                    arg.expr.clone()
+                } else {
+                    Expr::Symbol(self.ident(*ident), span.clone())
+                }
+            }
+            Expr::Neg(expr) => Expr::Neg(Box::new(self.expr(expr, pointer))),
+            Expr::Binary(l, op, r) => Expr::Binary(
+                Box::new(self.expr(l, pointer)),
+                *op,
+                Box::new(self.expr(r, pointer)),
+            ),
+        }
+    }
+
+    fn data(&self, data: &NodeInstructionData<'a>) -> NodeInstructionData<'a> {
+        let mut pointer = data.pointer;
+        let expr = self.expr(&data.expr, &mut pointer);
+
+        NodeInstructionData {
+            pointer,
+            expr,
+            span: data.span.clone(),
+        }
+    }
+
+    fn kind(&self, kind: &NodeKind<'a>) -> NodeKind<'a> {
+        // Pointers make no sense for directives, they are ignored
+        let expr = |expr| self.expr(expr, &mut false);
+
+        match kind {
+            NodeKind::Instruction(instruction) => {
+                NodeKind::Instruction(instruction.clone().map(|data| self.data(&data)))
+            }
+            NodeKind::Data(items) => NodeKind::Data(
+                items
+                    .iter()
+                    .map(|item| match item {
+                        DataItem::Value(data) => DataItem::Value(self.data(data)),
+                        DataItem::String(string) => DataItem::String(string.clone()),
+                        DataItem::Zero => DataItem::Zero,
+                    })
+                    .collect(),
+            ),
+            NodeKind::Block(size) => NodeKind::Block(expr(size)),
+            NodeKind::Origin(origin) => NodeKind::Origin(expr(origin)),
+            NodeKind::Constant(value) => NodeKind::Constant(expr(value)),
+            NodeKind::Call { name, name_span, args } => NodeKind::Call {
+                name,
+                name_span: name_span.clone(),
+                args: args.iter().map(|arg| self.data(arg)).collect(),
+            },
+            NodeKind::MacroStart { .. } | NodeKind::MacroEnd => {
+                unreachable!("macro definitions are removed from macro bodies")
+            }
+            NodeKind::Include { .. } => {
+                unreachable!("includes are spliced in before expanding macros")
+            }
+        }
+    }
+}
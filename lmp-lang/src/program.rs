@@ -0,0 +1,45 @@
+use crate::Span;
+use lmp_common::assembly::Instruction;
+
+/// A program that has been assembled and laid out in memory
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Program {
+    /// Cells emitted by the assembler, ordered by address. Addresses without a cell are left
+    /// untouched when the program is loaded
+    pub cells: Vec<Cell>,
+}
+
+/// A memory cell emitted by the assembler
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cell {
+    pub address: usize,
+    pub instruction: Instruction<i64>,
+    /// Location of the line in the source that emitted this cell
+    pub span: Span,
+    /// Whether the cell was given a value. Cells reserved with `BLK`, or `DAT` without a value,
+    /// are zero but not initialised
+    pub initialised: bool,
+}
+
+impl Program {
+    /// Encoded contents of each emitted cell along with its address
+    pub fn image(&self) -> impl Iterator<Item = (usize, i64)> + '_ {
+        self.cells
+            .iter()
+            .map(|cell| (cell.address, cell.instruction.into()))
+    }
+
+    /// Addresses of cells emitted as instructions rather than with `DAT`, the cells the virtual machine
+    /// treats as code once the program is loaded
+    pub fn code(&self) -> impl Iterator<Item = usize> + '_ {
+        self.cells
+            .iter()
+            .filter(|cell| !matches!(cell.instruction, Instruction::DAT(_)))
+            .map(|cell| cell.address)
+    }
+
+    /// Instructions in the program, in order of address
+    pub fn instructions(&self) -> impl Iterator<Item = Instruction<i64>> + '_ {
+        self.cells.iter().map(|cell| cell.instruction)
+    }
+}
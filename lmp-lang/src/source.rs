@@ -0,0 +1,138 @@
+use crate::Span;
+use std::path::{Path, PathBuf};
+use std::{fmt, fs, io};
+
+/// Index of a file in a [`SourceMap`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SourceId(usize);
+
+/// Where the text of a source file came from
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum SourceOrigin {
+    /// Text provided directly, e.g. typed into the interface. Relative includes are resolved from
+    /// the current directory
+    Input,
+    /// A file on disk. Relative includes are resolved from the directory containing it
+    File(PathBuf),
+    /// A file from the bundled standard library, see [`crate::library`]
+    Library(&'static str),
+}
+
+impl fmt::Display for SourceOrigin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SourceOrigin::Input => write!(f, "<input>"),
+            SourceOrigin::File(path) => write!(f, "{}", path.display()),
+            SourceOrigin::Library(name) => write!(f, "<{name}>"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SourceFile {
+    pub origin: SourceOrigin,
+    pub text: String,
+    /// Offset of the start of this file in the source map
+    offset: usize,
+}
+
+impl SourceFile {
+    /// Line and column (both starting from 1) of a byte offset into this file
+    pub fn line_col(&self, offset: usize) -> (usize, usize) {
+        line_col(&self.text, offset)
+    }
+}
+
+/// Line and column (both starting from 1) of a byte offset into `text`
+pub(crate) fn line_col(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset.min(text.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.chars().rev().take_while(|&c| c != '\n').count() + 1;
+
+    (line, column)
+}
+
+/// All the source files making up a program
+///
+/// Spans produced by the assembler are offsets into the source map rather than into individual
+/// files, so that any span can be traced back to the file it came from. The first file added
+/// starts at offset `0`, so spans in it are also plain offsets into its text.
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a file to the source map
+    pub fn add<S: Into<String>>(&mut self, origin: SourceOrigin, text: S) -> SourceId {
+        // Leave a gap of one between files so the end of a file is not the start of the next
+        let offset = self
+            .files
+            .last()
+            .map(|file| file.offset + file.text.len() + 1)
+            .unwrap_or(0);
+
+        self.files.push(SourceFile {
+            origin,
+            text: text.into(),
+            offset,
+        });
+
+        SourceId(self.files.len() - 1)
+    }
+
+    /// Read a file from disk and add it to the source map
+    pub fn add_file<P: AsRef<Path>>(&mut self, path: P) -> io::Result<SourceId> {
+        // Canonicalise so the file is recognised if it is included elsewhere
+        let path = fs::canonicalize(path)?;
+        let text = fs::read_to_string(&path)?;
+
+        Ok(self.add(SourceOrigin::File(path), text))
+    }
+
+    pub fn file(&self, id: SourceId) -> &SourceFile {
+        &self.files[id.0]
+    }
+
+    /// Offset of the start of a file in the source map
+    pub fn offset(&self, id: SourceId) -> usize {
+        self.files[id.0].offset
+    }
+
+    /// Find a file that has already been added from the given origin
+    pub fn find(&self, origin: &SourceOrigin) -> Option<SourceId> {
+        self.files
+            .iter()
+            .position(|file| &file.origin == origin)
+            .map(SourceId)
+    }
+
+    /// The file that an offset in the source map falls into, along with the offset into that file
+    pub fn lookup(&self, offset: usize) -> Option<(SourceId, usize)> {
+        let index = self
+            .files
+            .partition_point(|file| file.offset <= offset)
+            .checked_sub(1)?;
+
+        Some((SourceId(index), offset - self.files[index].offset))
+    }
+
+    /// Human-readable location of the start of a span, e.g. `line 3, column 5`. The file is
+    /// included if it is not [`SourceOrigin::Input`]
+    pub fn describe(&self, span: &Span) -> String {
+        let Some((id, offset)) = self.lookup(span.start) else {
+            return "unknown location".to_string();
+        };
+
+        let file = self.file(id);
+        let (line, column) = file.line_col(offset);
+        match file.origin {
+            SourceOrigin::Input => format!("line {line}, column {column}"),
+            _ => format!("{}, line {line}, column {column}", file.origin),
+        }
+    }
+}
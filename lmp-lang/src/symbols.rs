@@ -0,0 +1,63 @@
+use crate::Span;
+
+/// What a symbol names
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    /// The address of a cell in memory
+    Label,
+    /// A constant defined with `EQU` or `.set`
+    Constant,
+    /// A macro defined with `MACRO`
+    Macro,
+}
+
+/// Where a symbol is defined
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymbolDefinition {
+    pub name: String,
+    pub kind: SymbolKind,
+    /// Location of the name itself
+    pub span: Span,
+    /// Location of the whole line defining the symbol
+    pub line: Span,
+}
+
+/// A use of a symbol in an operand, or a call of a macro
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymbolReference {
+    pub name: String,
+    pub span: Span,
+}
+
+/// The symbols defined and referenced in a file, see [`crate::parser::index`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SymbolIndex {
+    /// Definitions in the order they appear
+    pub definitions: Vec<SymbolDefinition>,
+    /// References in the order they appear
+    pub references: Vec<SymbolReference>,
+}
+
+impl SymbolIndex {
+    /// The first definition of a symbol
+    pub fn definition(&self, name: &str) -> Option<&SymbolDefinition> {
+        self.definitions.iter().find(|definition| definition.name == name)
+    }
+
+    /// Every reference to a symbol
+    pub fn references<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a SymbolReference> {
+        self.references.iter().filter(move |reference| reference.name == name)
+    }
+
+    /// Name of the symbol defined or referenced at an offset, which may be just past the end of
+    /// the name
+    pub fn name_at(&self, offset: usize) -> Option<&str> {
+        let definitions = self.definitions.iter().map(|definition| (&definition.name, &definition.span));
+        let references = self.references.iter().map(|reference| (&reference.name, &reference.span));
+
+        definitions
+            .chain(references)
+            .find(|(_, span)| span.start <= offset && offset <= span.end)
+            .map(|(name, _)| name.as_str())
+    }
+}
@@ -0,0 +1,128 @@
+//! Documentation of mnemonics and directives, shown when hovering over and completing them
+
+use lmp_common::assembly::{Instruction, Mnemonic, MNEMONICS};
+use std::collections::BTreeMap;
+
+/// Directives, which are not part of the instruction set
+const DIRECTIVES: &[(&str, &str, &str)] = &[
+    ("EQU", "name EQU value", "Define a named constant, which does not occupy memory"),
+    (".set", ".set name, value", "Define a named constant, the same as `EQU`"),
+    ("ORG", "ORG address", "Place the following cells starting from the specified address"),
+    ("BLK", "BLK length", "Reserve a block of the specified number of cells, filled with `0`"),
+    ("MACRO", "MACRO name a, b", "Start the definition of a macro with parameters, ended by `ENDM`"),
+    ("ENDM", "ENDM", "End the definition of a macro"),
+    ("INCLUDE", "INCLUDE \"file.lmc\"", "Assemble another file in place of this line, or a file from the standard library with `INCLUDE <math.lmc>`"),
+];
+
+/// One way of writing an instruction, e.g. `ADD #value`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Form {
+    pub syntax: String,
+    pub description: String,
+}
+
+/// Forms of every mnemonic and directive, by name
+#[derive(Debug, Clone)]
+pub struct Docs {
+    mnemonics: BTreeMap<String, Vec<Form>>,
+}
+
+impl Docs {
+    pub fn new() -> Self {
+        let mut mnemonics: BTreeMap<String, Vec<Form>> = BTreeMap::new();
+        // The doc comments on `Instruction` are the single place instructions are documented
+        for &Mnemonic { name, opcode, doc } in MNEMONICS {
+            let description = description(doc);
+            let instruction = match Instruction::try_from(opcode) {
+                Ok(instruction) => instruction,
+                Err(()) if name == "DAT" => Instruction::DAT(0),
+                Err(()) => continue,
+            };
+
+            // Operands are named after how they are used
+            let placeholder = match name {
+                "INPP" | "OUTP" => "port",
+                "DAT" => "value",
+                _ if name.len() == 4 && name.ends_with('I') => "value",
+                _ => "address",
+            };
+            let syntax = instruction.map(|_| placeholder).to_string();
+            let mnemonic = syntax.split_whitespace().next().unwrap_or_default().to_string();
+            mnemonics.entry(mnemonic).or_default().push(Form { syntax, description });
+        }
+
+        for &(name, syntax, description) in DIRECTIVES {
+            mnemonics.entry(name.to_string()).or_default().push(Form {
+                syntax: syntax.to_string(),
+                description: description.to_string(),
+            });
+        }
+
+        Self { mnemonics }
+    }
+
+    /// Forms of a mnemonic or directive
+    pub fn get(&self, name: &str) -> Option<&[Form]> {
+        self.mnemonics.get(name).map(Vec::as_slice)
+    }
+
+    /// Every mnemonic and directive along with its forms, in alphabetical order
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &[Form])> {
+        self.mnemonics.iter().map(|(name, forms)| (name.as_str(), forms.as_slice()))
+    }
+
+    /// Markdown describing each form of a mnemonic
+    pub fn markdown(forms: &[Form]) -> String {
+        let forms: Vec<_> = forms
+            .iter()
+            .map(|form| format!("```lmc\n{}\n```\n{}", form.syntax, form.description))
+            .collect();
+        forms.join("\n\n")
+    }
+}
+
+/// Join the lines of a doc comment into paragraphs, replacing intra-doc links with their names
+fn description(lines: &[&str]) -> String {
+    let mut description = String::new();
+    for line in lines.iter().map(|line| line.trim()) {
+        if line.is_empty() {
+            description.push_str("\n\n");
+        } else {
+            if !description.is_empty() && !description.ends_with('\n') {
+                description.push(' ');
+            }
+            description.push_str(line);
+        }
+    }
+
+    description.replace("[`Instruction::", "`").replace("`]", "`")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_docs() {
+        let docs = Docs::new();
+
+        let add: Vec<_> = docs.get("ADD").unwrap().iter().map(|form| form.syntax.as_str()).collect();
+        assert_eq!(add, vec!["ADD address", "ADD address,X", "ADD #value"]);
+
+        let inp = docs.get("INP").unwrap();
+        assert_eq!(inp[1].syntax, "INP port");
+        assert!(inp[1].description.ends_with("Port 0 is the same as `INP`"));
+
+        let brp = &docs.get("BRP").unwrap()[0].description;
+        assert_eq!(
+            brp,
+            "Branch if positive, sets the program counter to the specified memory address if the accumulator is positive"
+        );
+        assert_eq!(docs.get("DAT").unwrap()[0].description, "Store a piece of data at a free memory address, usually associating it with a label.\n\nData defaults to `0`");
+
+        // Every word reserved by the assembler is documented
+        for name in ["LDR", "STR", "EI", "RTI", "HLT", "EQU", "INCLUDE"] {
+            assert!(docs.get(name).is_some(), "{name} is not documented");
+        }
+    }
+}
@@ -0,0 +1,154 @@
+//! Open documents and conversion between byte offsets and LSP positions
+
+use lmp_lang::parser::{self, assemble_sources};
+use lmp_lang::{AssemblerError, SourceMap, SourceOrigin, Span, SymbolIndex};
+use lsp_types::{Diagnostic, DiagnosticSeverity, Position, Range, Uri};
+use std::path::PathBuf;
+
+/// A file open in the editor
+#[derive(Debug, Clone)]
+pub struct Document {
+    pub text: String,
+    /// Byte offset of the start of each line
+    line_starts: Vec<usize>,
+    /// Symbols in the current text, if it could be parsed
+    pub index: Option<SymbolIndex>,
+    /// Symbols in the last text that could be parsed, used for completion while typing
+    pub last_index: Option<SymbolIndex>,
+}
+
+impl Document {
+    pub fn new(text: String) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(offset, _)| offset + 1))
+            .collect();
+
+        Self {
+            text,
+            line_starts,
+            index: None,
+            last_index: None,
+        }
+    }
+
+    /// Replace the text, keeping the last good index
+    pub fn update(&mut self, text: String) {
+        let last_index = self.index.take().or(self.last_index.take());
+        *self = Self::new(text);
+        self.last_index = last_index;
+    }
+
+    /// Position of a byte offset, with the character counted in UTF-16 code units as LSP expects
+    pub fn position(&self, offset: usize) -> Position {
+        let offset = offset.min(self.text.len());
+        let line = self.line_starts.partition_point(|&start| start <= offset) - 1;
+        let character = self.text[self.line_starts[line]..offset].encode_utf16().count();
+
+        Position::new(line as u32, character as u32)
+    }
+
+    /// Byte offset of a position, clamped to the end of its line
+    pub fn offset(&self, position: Position) -> usize {
+        let Some(&start) = self.line_starts.get(position.line as usize) else {
+            return self.text.len();
+        };
+        let end = self.line_starts.get(position.line as usize + 1).map_or(self.text.len(), |&end| end);
+
+        let mut units = 0;
+        for (offset, c) in self.text[start..end].char_indices() {
+            if units >= position.character as usize || c == '\n' {
+                return start + offset;
+            }
+            units += c.len_utf16();
+        }
+
+        end
+    }
+
+    pub fn range(&self, span: &Span) -> Range {
+        Range::new(self.position(span.start), self.position(span.end))
+    }
+
+    /// Assemble the document, re-indexing its symbols and returning the problems found
+    pub fn check(&mut self, uri: &Uri) -> Vec<Diagnostic> {
+        let mut sources = SourceMap::new();
+        let root = sources.add(origin(uri), self.text.as_str());
+
+        self.index = parser::index(&sources, root).ok();
+        match assemble_sources(&mut sources, root) {
+            Ok(_) => Vec::new(),
+            Err(errors) => errors.iter().map(|error| self.diagnostic(error, &sources)).collect(),
+        }
+    }
+
+    /// The root file is added first, so spans that fall within its text are offsets into it.
+    /// Errors in included files are shown at the innermost location that is in this document
+    fn diagnostic(&self, error: &AssemblerError, sources: &SourceMap) -> Diagnostic {
+        let mut spans = Vec::new();
+        let mut inner = error;
+        while let AssemblerError::InMacro { call_site, error, .. } = inner {
+            spans.push(call_site);
+            inner = error;
+        }
+        spans.push(inner.span());
+
+        let span = spans.into_iter().rev().find(|span| span.end <= self.text.len());
+        let (range, message) = match span {
+            Some(span) if std::ptr::eq(span, inner.span()) => (self.range(span), inner.to_string()),
+            Some(span) => (self.range(span), error.report(sources)),
+            None => (Range::default(), error.report(sources)),
+        };
+
+        Diagnostic {
+            range,
+            severity: Some(DiagnosticSeverity::ERROR),
+            source: Some("lmp".to_string()),
+            message,
+            ..Diagnostic::default()
+        }
+    }
+}
+
+/// Where a document came from, so includes are resolved relative to it when it is on disk
+fn origin(uri: &Uri) -> SourceOrigin {
+    if uri.scheme().is_some_and(|scheme| scheme.as_str() == "file")
+        && let Ok(path) = uri.path().as_estr().decode().into_string()
+    {
+        let path = PathBuf::from(path.as_ref());
+        return SourceOrigin::File(path.canonicalize().unwrap_or(path));
+    }
+
+    SourceOrigin::Input
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_positions() {
+        let document = Document::new("a\n  é𝄞 b\nend".to_string());
+
+        assert_eq!(document.position(0), Position::new(0, 0));
+        assert_eq!(document.position(2), Position::new(1, 0));
+        // `é` is two bytes and one UTF-16 unit, `𝄞` is four bytes and two units
+        assert_eq!(document.position(11), Position::new(1, 6));
+        assert_eq!(document.offset(Position::new(1, 6)), 11);
+        assert_eq!(document.offset(Position::new(1, 100)), 12);
+        assert_eq!(document.offset(Position::new(2, 3)), 16);
+        assert_eq!(document.offset(Position::new(9, 0)), 16);
+        assert_eq!(document.position(16), Position::new(2, 3));
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let mut document = Document::new("MACRO twice\n    ADD missing\nENDM\n\n    twice\n    HLT\n".to_string());
+        let uri = "untitled:test.lmc".parse().unwrap();
+
+        let diagnostics = document.check(&uri);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].range, Range::new(Position::new(1, 8), Position::new(1, 15)));
+        assert_eq!(diagnostics[0].message, "undefined symbol `missing`");
+        assert!(document.index.is_some());
+    }
+}
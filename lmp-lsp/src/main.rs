@@ -0,0 +1,40 @@
+/*
+    littlemanplus — a Rust-based Little Man Computer simulator
+    Copyright (C) 2025 azyrite
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+mod docs;
+mod document;
+mod server;
+
+use lsp_server::Connection;
+use std::process::ExitCode;
+
+/// Language server for LMC assembly, speaking LSP over stdin and stdout
+fn main() -> ExitCode {
+    let (connection, io_threads) = Connection::stdio();
+
+    let result = server::run(&connection);
+    drop(connection);
+    let result = result.and_then(|()| io_threads.join().map_err(Into::into));
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("lmp-lsp: {error}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,389 @@
+//! Handling of requests and notifications from the editor
+
+use crate::docs::Docs;
+use crate::document::Document;
+use lmp_lang::{SymbolDefinition, SymbolKind, parser};
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, Notification as _, PublishDiagnostics,
+};
+use lsp_types::request::{
+    Completion, DocumentSymbolRequest, GotoDefinition, HoverRequest, References, Rename, Request as _,
+};
+use lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionParams, CompletionResponse, DocumentSymbol, DocumentSymbolParams,
+    DocumentSymbolResponse, Documentation, GotoDefinitionParams, GotoDefinitionResponse, Hover, HoverContents,
+    HoverParams, HoverProviderCapability, Location, MarkupContent, MarkupKind, OneOf, PublishDiagnosticsParams,
+    ReferenceParams, RenameParams, ServerCapabilities, TextDocumentPositionParams, TextDocumentSyncCapability,
+    TextDocumentSyncKind, TextEdit, Uri, WorkspaceEdit,
+};
+use std::collections::HashMap;
+use std::error::Error;
+
+type Result<T, E = Box<dyn Error + Send + Sync>> = std::result::Result<T, E>;
+
+fn capabilities() -> ServerCapabilities {
+    ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        definition_provider: Some(OneOf::Left(true)),
+        references_provider: Some(OneOf::Left(true)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        completion_provider: Some(Default::default()),
+        rename_provider: Some(OneOf::Left(true)),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        ..ServerCapabilities::default()
+    }
+}
+
+/// Initialise the connection and serve requests until the editor shuts the server down
+pub fn run(connection: &Connection) -> Result<()> {
+    connection.initialize(serde_json::to_value(capabilities())?)?;
+
+    let mut server = Server::new();
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request)? {
+                    return Ok(());
+                }
+                connection.sender.send(server.request(request).into())?;
+            }
+            Message::Notification(notification) => {
+                if let Some(diagnostics) = server.notification(notification) {
+                    let notification = Notification::new(PublishDiagnostics::METHOD.to_string(), diagnostics);
+                    connection.sender.send(notification.into())?;
+                }
+            }
+            Message::Response(_) => {}
+        }
+    }
+
+    Ok(())
+}
+
+struct Server {
+    documents: HashMap<Uri, Document>,
+    docs: Docs,
+}
+
+impl Server {
+    fn new() -> Self {
+        Self {
+            documents: HashMap::new(),
+            docs: Docs::new(),
+        }
+    }
+
+    fn request(&self, request: Request) -> Response {
+        match request.method.as_str() {
+            GotoDefinition::METHOD => self.reply::<GotoDefinition>(request, Self::definition),
+            References::METHOD => self.reply::<References>(request, Self::references),
+            HoverRequest::METHOD => self.reply::<HoverRequest>(request, Self::hover),
+            Completion::METHOD => self.reply::<Completion>(request, Self::completion),
+            Rename::METHOD => self.reply::<Rename>(request, Self::rename),
+            DocumentSymbolRequest::METHOD => self.reply::<DocumentSymbolRequest>(request, Self::symbols),
+            method => Response::new_err(
+                request.id,
+                ErrorCode::MethodNotFound as i32,
+                format!("unsupported request `{method}`"),
+            ),
+        }
+    }
+
+    /// Respond to a request by passing its parameters to a handler
+    fn reply<R: lsp_types::request::Request>(
+        &self,
+        request: Request,
+        handler: fn(&Self, R::Params) -> Result<R::Result, String>,
+    ) -> Response {
+        let params = match serde_json::from_value(request.params) {
+            Ok(params) => params,
+            Err(error) => return Response::new_err(request.id, ErrorCode::InvalidParams as i32, error.to_string()),
+        };
+
+        match handler(self, params) {
+            Ok(result) => Response::new_ok(request.id, result),
+            Err(message) => Response::new_err(request.id, ErrorCode::RequestFailed as i32, message),
+        }
+    }
+
+    /// Update the open documents, returning the diagnostics to publish if a document changed
+    fn notification(&mut self, notification: Notification) -> Option<PublishDiagnosticsParams> {
+        let (uri, text, version) = match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params: lsp_types::DidOpenTextDocumentParams = serde_json::from_value(notification.params).ok()?;
+                let document = params.text_document;
+                self.documents.insert(document.uri.clone(), Document::new(String::new()));
+                (document.uri, document.text, document.version)
+            }
+            DidChangeTextDocument::METHOD => {
+                let params: lsp_types::DidChangeTextDocumentParams =
+                    serde_json::from_value(notification.params).ok()?;
+                // Documents are synchronised in full, so the last change is the whole text
+                let text = params.content_changes.into_iter().last()?.text;
+                (params.text_document.uri, text, params.text_document.version)
+            }
+            DidCloseTextDocument::METHOD => {
+                let params: lsp_types::DidCloseTextDocumentParams = serde_json::from_value(notification.params).ok()?;
+                self.documents.remove(&params.text_document.uri);
+                // Clear the diagnostics of the closed document
+                return Some(PublishDiagnosticsParams::new(params.text_document.uri, Vec::new(), None));
+            }
+            _ => return None,
+        };
+
+        let document = self.documents.entry(uri.clone()).or_insert_with(|| Document::new(String::new()));
+        document.update(text);
+        let diagnostics = document.check(&uri);
+
+        Some(PublishDiagnosticsParams::new(uri, diagnostics, Some(version)))
+    }
+
+    fn document(&self, uri: &Uri) -> Result<&Document, String> {
+        self.documents.get(uri).ok_or_else(|| format!("`{}` is not open", uri.as_str()))
+    }
+
+    /// Definition of the symbol at a position, along with the document it is in
+    fn symbol(&self, position: &TextDocumentPositionParams) -> Result<Option<(&Document, &SymbolDefinition)>, String> {
+        let document = self.document(&position.text_document.uri)?;
+        let Some(index) = &document.index else { return Ok(None) };
+
+        let definition = index
+            .name_at(document.offset(position.position))
+            .and_then(|name| index.definition(name));
+
+        Ok(definition.map(|definition| (document, definition)))
+    }
+
+    fn definition(&self, params: GotoDefinitionParams) -> Result<Option<GotoDefinitionResponse>, String> {
+        let position = params.text_document_position_params;
+        let Some((document, definition)) = self.symbol(&position)? else { return Ok(None) };
+
+        let location = Location::new(position.text_document.uri, document.range(&definition.span));
+        Ok(Some(GotoDefinitionResponse::Scalar(location)))
+    }
+
+    fn references(&self, params: ReferenceParams) -> Result<Option<Vec<Location>>, String> {
+        let position = params.text_document_position;
+        let Some((document, definition)) = self.symbol(&position)? else { return Ok(None) };
+        let Some(index) = &document.index else { return Ok(None) };
+
+        let uri = position.text_document.uri;
+        let declaration = params.context.include_declaration.then_some(&definition.span);
+        let references = index.references(&definition.name).map(|reference| &reference.span);
+        let locations = declaration
+            .into_iter()
+            .chain(references)
+            .map(|span| Location::new(uri.clone(), document.range(span)))
+            .collect();
+
+        Ok(Some(locations))
+    }
+
+    fn rename(&self, params: RenameParams) -> Result<Option<WorkspaceEdit>, String> {
+        let position = params.text_document_position;
+        let Some((document, definition)) = self.symbol(&position)? else { return Ok(None) };
+        let Some(index) = &document.index else { return Ok(None) };
+
+        let name = params.new_name;
+        if !parser::is_label(&name) {
+            return Err(format!("`{name}` is not a valid name"));
+        }
+        if name != definition.name && index.definition(&name).is_some() {
+            return Err(format!("`{name}` is already defined"));
+        }
+
+        let references = index.references(&definition.name).map(|reference| &reference.span);
+        let edits = std::iter::once(&definition.span)
+            .chain(references)
+            .map(|span| TextEdit::new(document.range(span), name.clone()))
+            .collect();
+
+        // `Uri` caches parts of itself lazily, which does not affect how it is hashed
+        #[allow(clippy::mutable_key_type)]
+        let changes = HashMap::from([(position.text_document.uri, edits)]);
+        Ok(Some(WorkspaceEdit::new(changes)))
+    }
+
+    fn hover(&self, params: HoverParams) -> Result<Option<Hover>, String> {
+        let position = params.text_document_position_params;
+        if let Some((document, definition)) = self.symbol(&position)? {
+            let line = document.text[definition.line.clone()].trim();
+            let kind = match definition.kind {
+                SymbolKind::Label => "label",
+                SymbolKind::Constant => "constant",
+                SymbolKind::Macro => "macro",
+            };
+
+            return Ok(Some(markdown(format!("```lmc\n{line}\n```\n{kind} `{}`", definition.name))));
+        }
+
+        // Otherwise the word under the cursor may be a mnemonic
+        let document = self.document(&position.text_document.uri)?;
+        let offset = document.offset(position.position);
+        let is_word = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '.';
+        let start = document.text[..offset].rfind(|c| !is_word(c)).map_or(0, |start| start + 1);
+        let end = document.text[offset..].find(|c| !is_word(c)).map_or(document.text.len(), |end| offset + end);
+
+        Ok(self.docs.get(&document.text[start..end]).map(|forms| markdown(Docs::markdown(forms))))
+    }
+
+    fn completion(&self, params: CompletionParams) -> Result<Option<CompletionResponse>, String> {
+        let document = self.document(&params.text_document_position.text_document.uri)?;
+
+        let mut items: Vec<_> = self
+            .docs
+            .iter()
+            .map(|(name, forms)| CompletionItem {
+                label: name.to_string(),
+                kind: Some(CompletionItemKind::KEYWORD),
+                detail: forms.first().map(|form| form.syntax.clone()),
+                documentation: Some(Documentation::MarkupContent(MarkupContent {
+                    kind: MarkupKind::Markdown,
+                    value: Docs::markdown(forms),
+                })),
+                ..CompletionItem::default()
+            })
+            .collect();
+
+        // Symbols from the last version that could be parsed, so they are offered mid-edit
+        if let Some(index) = document.index.as_ref().or(document.last_index.as_ref()) {
+            let symbols = index.definitions.iter().map(|definition| CompletionItem {
+                label: definition.name.clone(),
+                kind: Some(match definition.kind {
+                    SymbolKind::Label => CompletionItemKind::VARIABLE,
+                    SymbolKind::Constant => CompletionItemKind::CONSTANT,
+                    SymbolKind::Macro => CompletionItemKind::FUNCTION,
+                }),
+                ..CompletionItem::default()
+            });
+            items.extend(symbols);
+        }
+
+        Ok(Some(CompletionResponse::Array(items)))
+    }
+
+    fn symbols(&self, params: DocumentSymbolParams) -> Result<Option<DocumentSymbolResponse>, String> {
+        let document = self.document(&params.text_document.uri)?;
+        let Some(index) = &document.index else { return Ok(None) };
+
+        let symbols = index
+            .definitions
+            .iter()
+            .map(|definition| {
+                #[allow(deprecated)]
+                DocumentSymbol {
+                    name: definition.name.clone(),
+                    detail: None,
+                    kind: match definition.kind {
+                        SymbolKind::Label => lsp_types::SymbolKind::VARIABLE,
+                        SymbolKind::Constant => lsp_types::SymbolKind::CONSTANT,
+                        SymbolKind::Macro => lsp_types::SymbolKind::FUNCTION,
+                    },
+                    tags: None,
+                    deprecated: None,
+                    range: document.range(&definition.line),
+                    selection_range: document.range(&definition.span),
+                    children: None,
+                }
+            })
+            .collect();
+
+        Ok(Some(DocumentSymbolResponse::Nested(symbols)))
+    }
+}
+
+fn markdown(value: String) -> Hover {
+    Hover {
+        contents: HoverContents::Markup(MarkupContent {
+            kind: MarkupKind::Markdown,
+            value,
+        }),
+        range: None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use lsp_types::{Position, TextDocumentIdentifier, TextDocumentItem};
+    use serde_json::{Value, json};
+
+    const PROGRAM: &str = "count  EQU 3\n       LDA #count\nloop   SUB one\n       BRP loop\n       HLT\none    DAT 1\n";
+
+    /// Run a server in memory, opening `PROGRAM` and sending each request in turn
+    fn session(requests: Vec<(&str, Value)>) -> Vec<Value> {
+        let (server, client) = Connection::memory();
+        let thread = std::thread::spawn(move || run(&server).unwrap());
+
+        let uri: Uri = "file:///test.lmc".parse().unwrap();
+        client.sender.send(Request::new(0.into(), "initialize".to_string(), json!({ "capabilities": {} })).into()).unwrap();
+        client.receiver.recv().unwrap();
+        client.sender.send(Notification::new("initialized".to_string(), json!({})).into()).unwrap();
+        let open = lsp_types::DidOpenTextDocumentParams {
+            text_document: TextDocumentItem::new(uri, "lmc".to_string(), 1, PROGRAM.to_string()),
+        };
+        client.sender.send(Notification::new(DidOpenTextDocument::METHOD.to_string(), open).into()).unwrap();
+
+        let mut results = Vec::new();
+        for (id, (method, params)) in requests.into_iter().enumerate() {
+            client.sender.send(Request::new((id as i32 + 1).into(), method.to_string(), params).into()).unwrap();
+            loop {
+                match client.receiver.recv().unwrap() {
+                    Message::Response(response) => {
+                        results.push(response.response_result.unwrap());
+                        break;
+                    }
+                    Message::Notification(notification) => {
+                        let diagnostics: PublishDiagnosticsParams = serde_json::from_value(notification.params).unwrap();
+                        assert!(diagnostics.diagnostics.is_empty());
+                    }
+                    Message::Request(_) => unreachable!(),
+                }
+            }
+        }
+
+        client.sender.send(Request::new(100.into(), "shutdown".to_string(), json!(null)).into()).unwrap();
+        client.receiver.recv().unwrap();
+        client.sender.send(Notification::new("exit".to_string(), json!(null)).into()).unwrap();
+        thread.join().unwrap();
+
+        results
+    }
+
+    fn position(line: u32, character: u32) -> Value {
+        let document = TextDocumentIdentifier::new("file:///test.lmc".parse().unwrap());
+        json!(TextDocumentPositionParams::new(document, Position::new(line, character)))
+    }
+
+    #[test]
+    fn test_server() {
+        let mut rename = position(3, 12);
+        rename["newName"] = json!("again");
+        let mut references = position(2, 2);
+        references["context"] = json!({ "includeDeclaration": false });
+
+        let results = session(vec![
+            (GotoDefinition::METHOD, position(3, 12)),
+            (References::METHOD, references),
+            (Rename::METHOD, rename),
+            (HoverRequest::METHOD, position(3, 8)),
+        ]);
+
+        let definition: Location = serde_json::from_value(results[0].clone()).unwrap();
+        assert_eq!(definition.range.start, Position::new(2, 0));
+
+        let references: Vec<Location> = serde_json::from_value(results[1].clone()).unwrap();
+        assert_eq!(references.len(), 1);
+        assert_eq!(references[0].range.start, Position::new(3, 11));
+
+        let rename: WorkspaceEdit = serde_json::from_value(results[2].clone()).unwrap();
+        let edits = &rename.changes.unwrap()[&"file:///test.lmc".parse::<Uri>().unwrap()];
+        assert_eq!(edits.len(), 2);
+        assert!(edits.iter().all(|edit| edit.new_text == "again"));
+
+        let hover: Hover = serde_json::from_value(results[3].clone()).unwrap();
+        let HoverContents::Markup(hover) = hover.contents else { panic!("hover is not markdown") };
+        assert!(hover.value.starts_with("```lmc\nBRP address\n```\nBranch if positive"));
+    }
+}
@@ -0,0 +1,387 @@
+//! Python bindings for the assembler and virtual machine, built into the `lmp` extension module
+//! with `maturin build`
+use lmp_common::assembly::Instruction;
+use lmp_lang::{AssemblerError, SourceMap, SourceOrigin, parser};
+use lmp_vm::VirtualMachineStep;
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use std::fmt;
+
+create_exception!(lmp, AssemblyError, PyException, "The program could not be assembled. The errors are in its `diagnostics` attribute");
+
+/// An error found while assembling a program
+#[pyclass(frozen, get_all, module = "lmp")]
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    message: String,
+    /// File the error is in, or `None` if it is in the source passed in
+    file: Option<String>,
+    line: usize,
+    column: usize,
+    end_line: usize,
+    end_column: usize,
+    /// Human-readable description including the location and any macro expansions
+    report: String,
+}
+
+#[pymethods]
+impl Diagnostic {
+    fn __repr__(&self) -> String {
+        format!("<Diagnostic {}:{}: {}>", self.line, self.column, self.message)
+    }
+}
+
+impl Diagnostic {
+    fn new(error: &AssemblerError, sources: &SourceMap) -> Self {
+        let span = error.span();
+        let (file, (line, column), (end_line, end_column)) = match sources.lookup(span.start) {
+            Some((id, offset)) => {
+                let file = sources.file(id);
+                let end = offset + (span.end - span.start);
+                let name = match file.origin {
+                    SourceOrigin::Input => None,
+                    _ => Some(file.origin.to_string()),
+                };
+                (name, file.line_col(offset), file.line_col(end))
+            }
+            None => (None, (0, 0), (0, 0)),
+        };
+
+        Self {
+            message: error.to_string(),
+            file,
+            line,
+            column,
+            end_line,
+            end_column,
+            report: error.report(sources),
+        }
+    }
+}
+
+/// Assemble a program, returning the address and encoding of each cell it fills
+fn assemble_cells(source: &str) -> PyResult<Vec<(usize, Instruction<i64>)>> {
+    let mut sources = SourceMap::new();
+    let root = sources.add(SourceOrigin::Input, source);
+    let program = parser::assemble_sources(&mut sources, root).map_err(|errors| {
+        let diagnostics: Vec<_> = errors.iter().map(|error| Diagnostic::new(error, &sources)).collect();
+        let reports: Vec<_> = diagnostics.iter().map(|diagnostic| diagnostic.report.as_str()).collect();
+        let error = AssemblyError::new_err(reports.join("\n"));
+        Python::attach(|py| match error.value(py).setattr("diagnostics", diagnostics) {
+            Ok(()) => error,
+            Err(error) => error,
+        })
+    })?;
+
+    Ok(program.cells.iter().map(|cell| (cell.address, cell.instruction)).collect())
+}
+
+/// Assemble a program, returning the contents of every cell of memory
+///
+/// Raises `AssemblyError` if the program has errors
+#[pyfunction]
+fn assemble(source: &str) -> PyResult<Vec<i64>> {
+    let mut memory = vec![0; lmp_common::MEMORY_SIZE];
+    for (address, instruction) in assemble_cells(source)? {
+        memory[address] = instruction.into();
+    }
+    Ok(memory)
+}
+
+/// A value given to `INP`, or a character given to `INC`
+#[derive(Debug, Clone, Copy, FromPyObject, IntoPyObject)]
+enum Value {
+    Number(i64),
+    Character(char),
+}
+
+impl fmt::Display for Value {
+    /// Formats the value as Python would
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Number(number) => write!(f, "{number}"),
+            Value::Character(character) => write!(f, "'{}'", character.escape_default()),
+        }
+    }
+}
+
+impl From<Value> for i64 {
+    fn from(value: Value) -> Self {
+        match value {
+            Value::Number(number) => number,
+            Value::Character(character) => character as i64,
+        }
+    }
+}
+
+/// The result of executing one instruction
+#[pyclass(frozen, get_all, module = "lmp")]
+#[derive(Debug, Clone)]
+pub struct Step {
+    /// One of `advanced`, `output`, `input_required`, `waiting`, `interrupted`, `halted` or `fault`
+    kind: &'static str,
+    /// The value or character output, or the interrupt line handled
+    value: Option<Value>,
+    /// Description of the fault that stopped the program
+    message: Option<String>,
+}
+
+#[pymethods]
+impl Step {
+    fn __repr__(&self) -> String {
+        match (&self.value, &self.message) {
+            (Some(value), _) => format!("<Step {} {value}>", self.kind),
+            (_, Some(message)) => format!("<Step {} {message:?}>", self.kind),
+            _ => format!("<Step {}>", self.kind),
+        }
+    }
+}
+
+impl From<&VirtualMachineStep> for Step {
+    fn from(step: &VirtualMachineStep) -> Self {
+        let (kind, value, message) = match step {
+            VirtualMachineStep::Output(value) => ("output", Some(Value::Number(*value)), None),
+            VirtualMachineStep::OutputCharacter(character) => ("output", Some(Value::Character(*character)), None),
+            VirtualMachineStep::InputRequired(_) => ("input_required", None, None),
+            VirtualMachineStep::Waiting => ("waiting", None, None),
+            VirtualMachineStep::Interrupted(line) => ("interrupted", Some(Value::Number(*line as i64)), None),
+            VirtualMachineStep::Halted => ("halted", None, None),
+            VirtualMachineStep::Fault(fault) => ("fault", None, Some(fault.to_string())),
+            _ => ("advanced", None, None),
+        };
+
+        Self { kind, value, message }
+    }
+}
+
+/// The outcome of `VirtualMachine.run`
+#[pyclass(frozen, get_all, module = "lmp")]
+#[derive(Debug, Clone)]
+pub struct RunResult {
+    /// One of `halted`, `fault`, `input_required`, `waiting` or `cycle_limit`
+    status: &'static str,
+    /// Values output by `OUT` as integers, and characters output by `OTC` as strings
+    outputs: Vec<Value>,
+    /// Description of the fault that stopped the program
+    message: Option<String>,
+    /// Number of cycles run, including the one that halted or faulted
+    cycles: u64,
+}
+
+#[pymethods]
+impl RunResult {
+    fn __repr__(&self) -> String {
+        let outputs: Vec<_> = self.outputs.iter().map(Value::to_string).collect();
+        format!("<RunResult {} after {} cycles, outputs [{}]>", self.status, self.cycles, outputs.join(", "))
+    }
+}
+
+/// An instruction executed while tracing
+#[pyclass(frozen, get_all, module = "lmp")]
+#[derive(Debug, Clone)]
+pub struct TraceEntry {
+    /// Cycle the instruction was executed on
+    cycle: i64,
+    address: usize,
+    /// Disassembly of the instruction
+    instruction: String,
+    /// Kind of the step, as in `Step.kind`
+    kind: &'static str,
+    /// Accumulator after the instruction was executed
+    accumulator: i64,
+}
+
+#[pymethods]
+impl TraceEntry {
+    fn __repr__(&self) -> String {
+        format!(
+            "<TraceEntry {}: {:03} {} ACC={}>",
+            self.cycle, self.address, self.instruction, self.accumulator
+        )
+    }
+}
+
+/// A virtual machine, optionally loaded with a program
+#[pyclass(unsendable, module = "lmp")]
+#[derive(Debug, Default)]
+pub struct VirtualMachine {
+    vm: lmp_vm::VirtualMachine,
+    /// Whether executed instructions are recorded
+    #[pyo3(get, set)]
+    tracing: bool,
+    trace: Vec<TraceEntry>,
+}
+
+#[pymethods]
+impl VirtualMachine {
+    #[new]
+    #[pyo3(signature = (source=None, *, trace=false))]
+    fn new(source: Option<&str>, trace: bool) -> PyResult<Self> {
+        let mut vm = Self { tracing: trace, ..Self::default() };
+        if let Some(source) = source {
+            vm.load(source)?;
+        }
+        Ok(vm)
+    }
+
+    /// Assemble a program and load it, resetting the VM and clearing the trace
+    ///
+    /// Raises `AssemblyError` if the program has errors
+    fn load(&mut self, source: &str) -> PyResult<()> {
+        let cells = assemble_cells(source)?;
+        self.vm.load(cells);
+        self.trace.clear();
+        Ok(())
+    }
+
+    /// Queue a value to be read by `INP`, or a character to be read by `INC`
+    fn input(&mut self, value: Value) {
+        self.vm.input(value.into());
+    }
+
+    /// Execute one instruction
+    fn step(&mut self) -> Step {
+        let cycle = self.vm.cycles();
+        let address = self.vm.program_counter();
+        let halted = self.vm.halted();
+        let step = self.vm.step();
+        let result = Step::from(&step);
+
+        // Nothing is executed when waiting for input or a device, handling an interrupt, or already
+        // halted
+        let executed = !halted
+            && !matches!(
+                step,
+                VirtualMachineStep::InputRequired(_) | VirtualMachineStep::Waiting | VirtualMachineStep::Interrupted(_)
+            );
+        if self.tracing && executed {
+            let data = self.vm.memory().get(address).map_or(0, |cell| cell.data);
+            let instruction = Instruction::try_from(data).map_or_else(|_| format!("DAT {data}"), |i| i.to_string());
+            self.trace.push(TraceEntry {
+                cycle,
+                address,
+                instruction,
+                kind: result.kind,
+                accumulator: self.vm.accumulator(),
+            });
+        }
+
+        result
+    }
+
+    /// Queue `inputs` and run until the program halts, faults, needs more input, waits for a device,
+    /// or has run for `max_cycles` cycles
+    #[pyo3(signature = (inputs=Vec::new(), max_cycles=100_000))]
+    fn run(&mut self, inputs: Vec<Value>, max_cycles: u64) -> RunResult {
+        self.vm.input_all(inputs.into_iter().map(i64::from));
+
+        let mut result = RunResult {
+            status: "cycle_limit",
+            outputs: Vec::new(),
+            message: None,
+            cycles: 0,
+        };
+        while result.cycles < max_cycles {
+            let step = self.step();
+            // Devices only change as instructions execute, so a device being waited on never has a
+            // value during the run
+            if matches!(step.kind, "input_required" | "waiting") {
+                result.status = step.kind;
+                break;
+            }
+
+            result.cycles += 1;
+            match step.kind {
+                "output" => result.outputs.extend(step.value),
+                "halted" | "fault" => {
+                    result.status = step.kind;
+                    result.message = step.message;
+                    break;
+                }
+                _ => {}
+            }
+        }
+
+        result
+    }
+
+    /// Instructions executed since the program was loaded or the trace was cleared, while
+    /// `tracing` was enabled
+    fn trace(&self) -> Vec<TraceEntry> {
+        self.trace.clone()
+    }
+
+    fn clear_trace(&mut self) {
+        self.trace.clear();
+    }
+
+    /// Contents of every cell of memory
+    #[getter]
+    fn memory(&self) -> Vec<i64> {
+        self.vm.memory().iter().map(|cell| cell.data).collect()
+    }
+
+    /// Contents of a cell of memory
+    fn read(&self, address: usize) -> Option<i64> {
+        self.vm.memory().get(address).map(|cell| cell.data)
+    }
+
+    #[getter]
+    fn accumulator(&self) -> i64 {
+        self.vm.accumulator()
+    }
+
+    #[getter]
+    fn program_counter(&self) -> usize {
+        self.vm.program_counter()
+    }
+
+    #[getter]
+    fn index(&self) -> i64 {
+        self.vm.index()
+    }
+
+    #[getter]
+    fn stack_pointer(&self) -> usize {
+        self.vm.stack_pointer()
+    }
+
+    #[getter]
+    fn carry(&self) -> bool {
+        self.vm.flags().carry
+    }
+
+    #[getter]
+    fn overflow(&self) -> bool {
+        self.vm.flags().overflow
+    }
+
+    #[getter]
+    fn cycles(&self) -> i64 {
+        self.vm.cycles()
+    }
+
+    #[getter]
+    fn halted(&self) -> bool {
+        self.vm.halted()
+    }
+
+    /// Values waiting to be read, starting with the next one
+    #[getter]
+    fn queued_input(&self) -> Vec<i64> {
+        self.vm.queued_input().iter().copied().collect()
+    }
+}
+
+#[pymodule]
+fn lmp(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(assemble, m)?)?;
+    m.add("AssemblyError", m.py().get_type::<AssemblyError>())?;
+    m.add_class::<Diagnostic>()?;
+    m.add_class::<Step>()?;
+    m.add_class::<RunResult>()?;
+    m.add_class::<TraceEntry>()?;
+    m.add_class::<VirtualMachine>()?;
+    Ok(())
+}
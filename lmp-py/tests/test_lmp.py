@@ -0,0 +1,77 @@
+"""Run with `python -m unittest discover lmp-py/tests` after installing the wheel"""
+import unittest
+
+import lmp
+
+COUNTDOWN = """
+        INP
+loop    OUT
+        SUB one
+        BRP loop
+        LDA bang
+        OTC
+        HLT
+one     DAT 1
+bang    DAT 33
+"""
+
+
+class TestAssemble(unittest.TestCase):
+    def test_assemble(self):
+        memory = lmp.assemble(COUNTDOWN)
+        self.assertEqual(memory[:9], [901, 902, 2007, 8001, 5008, 922, 1, 1, 33])
+
+    def test_diagnostics(self):
+        with self.assertRaises(lmp.AssemblyError) as context:
+            lmp.VirtualMachine("LDA missing\nSTA 1000")
+
+        missing, out_of_range = context.exception.diagnostics
+        self.assertEqual(missing.message, "undefined symbol `missing`")
+        self.assertEqual((missing.line, missing.column, missing.end_column), (1, 5, 12))
+        self.assertIsNone(missing.file)
+        self.assertEqual(out_of_range.line, 2)
+
+
+class TestVirtualMachine(unittest.TestCase):
+    def test_run(self):
+        vm = lmp.VirtualMachine(COUNTDOWN)
+        result = vm.run([3])
+        self.assertEqual(result.status, "halted")
+        self.assertEqual(result.outputs, [3, 2, 1, 0, "!"])
+        self.assertEqual(result.cycles, 16)
+        self.assertTrue(vm.halted)
+        self.assertEqual(vm.accumulator, 33)
+
+        self.assertEqual(lmp.VirtualMachine(COUNTDOWN).run().status, "input_required")
+        self.assertEqual(lmp.VirtualMachine("loop BRA loop").run(max_cycles=10).status, "cycle_limit")
+
+        result = lmp.VirtualMachine("DIV zero\nzero DAT 0").run()
+        self.assertEqual(result.status, "fault")
+        self.assertIn("Division by zero", result.message)
+
+    def test_step(self):
+        vm = lmp.VirtualMachine("INC\nOTC\nHLT")
+        self.assertEqual(vm.step().kind, "input_required")
+        vm.input("x")
+        self.assertEqual(vm.step().kind, "advanced")
+        self.assertEqual(vm.program_counter, 1)
+
+        step = vm.step()
+        self.assertEqual((step.kind, step.value), ("output", "x"))
+        self.assertEqual(vm.step().kind, "halted")
+
+    def test_trace(self):
+        vm = lmp.VirtualMachine(COUNTDOWN, trace=True)
+        vm.run([1])
+        trace = vm.trace()
+        self.assertEqual(len(trace), vm.cycles)
+        self.assertEqual([entry.address for entry in trace[:6]], [0, 1, 2, 3, 1, 2])
+        self.assertEqual((trace[2].instruction, trace[2].accumulator), ("SUB 7", 0))
+        self.assertEqual(trace[-1].instruction, "HLT")
+
+        vm.clear_trace()
+        self.assertEqual(vm.trace(), [])
+
+
+if __name__ == "__main__":
+    unittest.main()
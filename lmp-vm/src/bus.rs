@@ -0,0 +1,130 @@
+use crate::devices::{DeviceError, MappedDevice};
+use crate::vm::{MapError, MemoryCell};
+use lmp_common::MEMORY_SIZE;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+/// Connects the virtual machine to its memory, dispatching accesses to cells with a device mapped
+/// to them to that device
+#[derive(Debug)]
+pub struct Bus {
+    memory: [MemoryCell; MEMORY_SIZE],
+    /// Mapped devices, kept in order of address and never overlapping
+    regions: Vec<Region>,
+}
+
+#[derive(Debug)]
+struct Region {
+    cells: Range<usize>,
+    device: Box<dyn MappedDevice>,
+}
+
+impl Bus {
+    pub fn new() -> Self {
+        Self {
+            memory: [MemoryCell::default(); MEMORY_SIZE],
+            regions: Vec::new(),
+        }
+    }
+
+    /// Map a device into memory starting from an address, unless it does not fit into memory or
+    /// overlaps with another mapped device
+    pub fn map(&mut self, start: usize, device: Box<dyn MappedDevice>) -> Result<(), MapError> {
+        if device.size() == 0 {
+            return Err(MapError::Empty);
+        }
+        let cells = start..start.saturating_add(device.size());
+        if cells.end > MEMORY_SIZE {
+            return Err(MapError::OutOfMemory { cells });
+        }
+
+        // Position of the first region after the new one
+        let index = self.regions.partition_point(|region| region.cells.start < start);
+        let previous = index.checked_sub(1).map(|index| &self.regions[index]);
+        let overlapping = previous
+            .filter(|region| region.cells.end > start)
+            .or_else(|| self.regions.get(index).filter(|region| region.cells.start < cells.end));
+        if let Some(region) = overlapping {
+            return Err(MapError::Overlap { cells, other: region.cells.clone() });
+        }
+
+        self.regions.insert(index, Region { cells, device });
+        Ok(())
+    }
+
+    /// Remove the device mapped starting from an address
+    pub fn unmap(&mut self, start: usize) -> Option<Box<dyn MappedDevice>> {
+        let index = self.regions.iter().position(|region| region.cells.start == start)?;
+
+        Some(self.regions.remove(index).device)
+    }
+
+    /// Mapped devices along with the cells they occupy, in order of address
+    pub fn mapped(&self) -> impl Iterator<Item = (Range<usize>, &dyn MappedDevice)> {
+        self.regions.iter().map(|region| (region.cells.clone(), region.device.as_ref()))
+    }
+
+    /// Read from a cell, or the device mapped to it
+    ///
+    /// # Panics
+    /// If the location is out of bounds
+    pub fn read(&mut self, loc: usize) -> Result<i64, DeviceError> {
+        if loc >= MEMORY_SIZE {
+            panic!("read loc out of bounds at {loc}")
+        }
+
+        if let Some(region) = self.region(loc) {
+            let value = region.device.read(loc - region.cells.start)?;
+            // Keep the value around so the cell shows what was last read
+            self.memory[loc].set(value);
+        }
+
+        Ok(self.memory[loc].data)
+    }
+
+    /// Write to a cell, or the device mapped to it
+    ///
+    /// # Panics
+    /// If the location is out of bounds
+    pub fn write(&mut self, loc: usize, data: i64) -> Result<(), DeviceError> {
+        if loc >= MEMORY_SIZE {
+            panic!("write loc out of bounds at {loc}")
+        }
+
+        if let Some(region) = self.region(loc) {
+            region.device.write(loc - region.cells.start, data)?;
+        }
+
+        self.memory[loc].set(data);
+        Ok(())
+    }
+
+    /// Write directly to memory, without going through mapped devices. Used to load programs
+    pub fn load(&mut self, loc: usize, data: i64) {
+        self.memory[loc].set(data);
+    }
+
+    /// Clear memory and reset mapped devices
+    pub fn clear(&mut self) {
+        self.memory = [MemoryCell::default(); MEMORY_SIZE];
+        for region in &mut self.regions {
+            region.device.reset();
+        }
+    }
+
+    /// Tick mapped devices, called after each instruction is executed
+    pub fn tick(&mut self) {
+        for region in &mut self.regions {
+            region.device.tick();
+        }
+    }
+
+    pub fn memory(&self) -> &[MemoryCell] {
+        &self.memory
+    }
+
+    fn region(&mut self, loc: usize) -> Option<&mut Region> {
+        self.regions.iter_mut().find(|region| region.cells.contains(&loc))
+    }
+}
@@ -0,0 +1,530 @@
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Debug;
+#[cfg(feature = "std")]
+use std::fs::File;
+#[cfg(feature = "std")]
+use std::io::{BufRead, Seek, Write};
+#[cfg(feature = "std")]
+use std::path::Path;
+#[cfg(feature = "std")]
+use std::{fmt, fs, io};
+use thiserror::Error;
+
+/// A device attached to a port of the virtual machine, used by `INP port` and `OUT port`
+pub trait IoDevice: Debug {
+    /// Short name shown in the interface
+    fn name(&self) -> &str;
+
+    /// Read a value from the device. `Ok(None)` means no value is available yet, and the virtual
+    /// machine waits until there is one
+    fn read(&mut self) -> Result<Option<i64>, DeviceError>;
+
+    /// Write a value to the device
+    fn write(&mut self, value: i64) -> Result<(), DeviceError>;
+
+    /// Called after each instruction the virtual machine executes, so once per cycle
+    fn tick(&mut self) {}
+
+    /// Whether the device wants to interrupt the program, checked after every tick. Only devices
+    /// on ports below [`INTERRUPT_LINES`](crate::INTERRUPT_LINES) can interrupt, raising the line of the same number
+    fn interrupt(&mut self) -> bool {
+        false
+    }
+
+    /// Return the device to its initial state, called when the virtual machine is reset
+    fn reset(&mut self) {}
+
+    /// Current state of the device to show in the interface, if any
+    fn status(&self) -> Option<String> {
+        None
+    }
+}
+
+/// A device mapped into a region of memory, so that `LDA`, `STA` and other instructions accessing
+/// its cells read from and write to the device
+pub trait MappedDevice: Debug {
+    /// Short name shown in the interface
+    fn name(&self) -> &str;
+
+    /// Number of cells the device occupies
+    fn size(&self) -> usize;
+
+    /// Read the cell at `offset` from the start of the device
+    fn read(&mut self, offset: usize) -> Result<i64, DeviceError>;
+
+    /// Write to the cell at `offset` from the start of the device
+    fn write(&mut self, offset: usize, value: i64) -> Result<(), DeviceError>;
+
+    /// Called after each instruction the virtual machine executes, so once per cycle
+    fn tick(&mut self) {}
+
+    /// Return the device to its initial state, called when the virtual machine is reset
+    fn reset(&mut self) {}
+
+    /// Current state of the device to show in the interface, if any
+    fn status(&self) -> Option<String> {
+        None
+    }
+}
+
+/// An error reported by a device, which stops the virtual machine
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("{0}")]
+pub struct DeviceError(pub String);
+
+#[cfg(feature = "std")]
+impl From<io::Error> for DeviceError {
+    fn from(error: io::Error) -> Self {
+        Self(error.to_string())
+    }
+}
+
+/// Reads integers line by line and writes them on separate lines, by default using standard input
+/// and output. Intended for running programs without the terminal interface
+#[cfg(feature = "std")]
+pub struct Console {
+    input: Box<dyn BufRead>,
+    output: Box<dyn Write>,
+}
+
+#[cfg(feature = "std")]
+impl Console {
+    pub fn new(input: Box<dyn BufRead>, output: Box<dyn Write>) -> Self {
+        Self { input, output }
+    }
+
+    pub fn stdio() -> Self {
+        Self::new(Box::new(io::stdin().lock()), Box::new(io::stdout()))
+    }
+}
+
+#[cfg(feature = "std")]
+impl Debug for Console {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Console").finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "std")]
+impl IoDevice for Console {
+    fn name(&self) -> &str {
+        "Console"
+    }
+
+    fn read(&mut self) -> Result<Option<i64>, DeviceError> {
+        let mut line = String::new();
+        if self.input.read_line(&mut line)? == 0 {
+            return Err(DeviceError("end of input".to_string()));
+        }
+
+        line.trim()
+            .parse()
+            .map(Some)
+            .map_err(|_| DeviceError(format!("`{}` is not an integer", line.trim())))
+    }
+
+    fn write(&mut self, value: i64) -> Result<(), DeviceError> {
+        writeln!(self.output, "{value}")?;
+        Ok(())
+    }
+}
+
+/// A tape of integers read from a file. Reads go through the values in the file from the start.
+/// Writes never change the file read from: written values are kept in memory, and saved to a
+/// separate output file if the tape has one
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct Tape {
+    values: Vec<i64>,
+    position: usize,
+    written: Vec<i64>,
+    /// File written values are saved to, one per line
+    output: Option<File>,
+}
+
+#[cfg(feature = "std")]
+impl Tape {
+    /// Open a tape from a file containing whitespace separated integers. A file that does not
+    /// exist is read as an empty tape
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(error) => return Err(error),
+        };
+
+        let values = text
+            .split_whitespace()
+            .map(|value| {
+                value.parse().map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("`{value}` in {} is not an integer", path.display()),
+                    )
+                })
+            })
+            .collect::<io::Result<_>>()?;
+
+        Ok(Self { values, position: 0, written: Vec::new(), output: None })
+    }
+
+    /// Save written values to a file, replacing its contents. The file is emptied again when the
+    /// tape is reset
+    pub fn with_output<P: AsRef<Path>>(mut self, path: P) -> io::Result<Self> {
+        self.output = Some(File::create(path)?);
+        Ok(self)
+    }
+
+    /// Values written to the tape since it was opened or last reset
+    pub fn written(&self) -> &[i64] {
+        &self.written
+    }
+}
+
+#[cfg(feature = "std")]
+impl IoDevice for Tape {
+    fn name(&self) -> &str {
+        "Tape"
+    }
+
+    fn read(&mut self) -> Result<Option<i64>, DeviceError> {
+        let value = *self
+            .values
+            .get(self.position)
+            .ok_or_else(|| DeviceError("end of tape".to_string()))?;
+        self.position += 1;
+
+        Ok(Some(value))
+    }
+
+    fn write(&mut self, value: i64) -> Result<(), DeviceError> {
+        if let Some(file) = &mut self.output {
+            writeln!(file, "{value}")?;
+        }
+        self.written.push(value);
+
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.position = 0;
+        self.written.clear();
+        if let Some(file) = &mut self.output {
+            // Resetting cannot report errors, so a file that cannot be emptied keeps its old values
+            let _ = file.set_len(0).and_then(|()| file.rewind());
+        }
+    }
+
+    fn status(&self) -> Option<String> {
+        Some(format!("{}/{}, {} written", self.position, self.values.len(), self.written.len()))
+    }
+}
+
+/// Produces pseudo-random numbers in a range. Writing a value reseeds the generator
+#[derive(Debug, Clone)]
+pub struct Random {
+    seed: u64,
+    state: u64,
+    min: i64,
+    max: i64,
+}
+
+impl Random {
+    /// Generator producing numbers from 0 to 999
+    pub fn new(seed: u64) -> Self {
+        Self::with_range(seed, 0, 999)
+    }
+
+    /// # Panics
+    /// If `min` is greater than `max`
+    pub fn with_range(seed: u64, min: i64, max: i64) -> Self {
+        assert!(min <= max, "empty range {min} to {max}");
+
+        let mut random = Self { seed, state: 0, min, max };
+        random.reseed(seed);
+        random
+    }
+
+    fn reseed(&mut self, seed: u64) {
+        // xorshift gets stuck at zero
+        self.state = seed.max(1);
+    }
+}
+
+impl IoDevice for Random {
+    fn name(&self) -> &str {
+        "Random"
+    }
+
+    fn read(&mut self) -> Result<Option<i64>, DeviceError> {
+        // xorshift64*
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        let random = self.state.wrapping_mul(0x2545_F491_4F6C_DD1D);
+
+        let range = self.max.abs_diff(self.min) as u128 + 1;
+        Ok(Some(self.min.wrapping_add((random as u128 % range) as i64)))
+    }
+
+    fn write(&mut self, value: i64) -> Result<(), DeviceError> {
+        self.reseed(value as u64);
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.reseed(self.seed);
+    }
+}
+
+/// Counts the cycles the virtual machine has run for. Writing a value sets the count
+#[derive(Debug, Clone, Default)]
+pub struct Timer {
+    count: i64,
+}
+
+impl Timer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl IoDevice for Timer {
+    fn name(&self) -> &str {
+        "Timer"
+    }
+
+    fn read(&mut self) -> Result<Option<i64>, DeviceError> {
+        Ok(Some(self.count))
+    }
+
+    fn write(&mut self, value: i64) -> Result<(), DeviceError> {
+        self.count = value;
+        Ok(())
+    }
+
+    fn tick(&mut self) {
+        self.count = self.count.wrapping_add(1);
+    }
+
+    fn reset(&mut self) {
+        self.count = 0;
+    }
+
+    fn status(&self) -> Option<String> {
+        Some(self.count.to_string())
+    }
+}
+
+/// Interrupts the program every `period` cycles, for which reading gives the cycles left. Writing
+/// a value sets the period and restarts the timer, with a period of 0 or less stopping it
+#[derive(Debug, Clone)]
+pub struct IntervalTimer {
+    initial: i64,
+    period: i64,
+    remaining: i64,
+    fired: bool,
+}
+
+impl IntervalTimer {
+    pub fn new(period: i64) -> Self {
+        Self {
+            initial: period,
+            period,
+            remaining: period,
+            fired: false,
+        }
+    }
+}
+
+impl IoDevice for IntervalTimer {
+    fn name(&self) -> &str {
+        "Interval Timer"
+    }
+
+    fn read(&mut self) -> Result<Option<i64>, DeviceError> {
+        Ok(Some(self.remaining))
+    }
+
+    fn write(&mut self, value: i64) -> Result<(), DeviceError> {
+        self.period = value;
+        self.remaining = value;
+        Ok(())
+    }
+
+    fn tick(&mut self) {
+        if self.period <= 0 {
+            return;
+        }
+
+        self.remaining -= 1;
+        if self.remaining <= 0 {
+            self.fired = true;
+            self.remaining = self.period;
+        }
+    }
+
+    fn interrupt(&mut self) -> bool {
+        core::mem::take(&mut self.fired)
+    }
+
+    fn reset(&mut self) {
+        *self = Self::new(self.initial);
+    }
+
+    fn status(&self) -> Option<String> {
+        Some(match self.period {
+            ..=0 => "stopped".to_string(),
+            period => format!("{}/{period}", self.remaining),
+        })
+    }
+}
+
+/// A row of LEDs, one for each bit of the last value written, starting from the lowest bit
+#[derive(Debug, Clone)]
+pub struct LedBank {
+    count: u32,
+    state: i64,
+}
+
+impl LedBank {
+    /// # Panics
+    /// If `count` is zero or more than 64
+    pub fn new(count: u32) -> Self {
+        assert!((1..=64).contains(&count), "an LED bank has 1 to 64 LEDs, not {count}");
+
+        Self { count, state: 0 }
+    }
+
+    /// Whether each LED is lit, starting from the lowest bit
+    pub fn lit(&self) -> impl Iterator<Item = bool> + '_ {
+        (0..self.count).map(|bit| self.state >> bit & 1 == 1)
+    }
+}
+
+impl IoDevice for LedBank {
+    fn name(&self) -> &str {
+        "LEDs"
+    }
+
+    fn read(&mut self) -> Result<Option<i64>, DeviceError> {
+        Ok(Some(self.state))
+    }
+
+    fn write(&mut self, value: i64) -> Result<(), DeviceError> {
+        self.state = match self.count {
+            64 => value,
+            count => value & ((1 << count) - 1),
+        };
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.state = 0;
+    }
+
+    fn status(&self) -> Option<String> {
+        // Highest bit on the left, like the number written in binary
+        let mut leds: Vec<char> = self.lit().map(|lit| if lit { '●' } else { '○' }).collect();
+        leds.reverse();
+
+        Some(leds.into_iter().collect())
+    }
+}
+
+/// Maps a port device into a single cell of memory. Reading the cell when the device has no value
+/// yet gives 0, like a keyboard with no key pressed
+#[derive(Debug)]
+pub struct Mapped(pub Box<dyn IoDevice>);
+
+impl MappedDevice for Mapped {
+    fn name(&self) -> &str {
+        self.0.name()
+    }
+
+    fn size(&self) -> usize {
+        1
+    }
+
+    fn read(&mut self, _offset: usize) -> Result<i64, DeviceError> {
+        Ok(self.0.read()?.unwrap_or(0))
+    }
+
+    fn write(&mut self, _offset: usize, value: i64) -> Result<(), DeviceError> {
+        self.0.write(value)
+    }
+
+    fn tick(&mut self) {
+        self.0.tick();
+    }
+
+    fn reset(&mut self) {
+        self.0.reset();
+    }
+
+    fn status(&self) -> Option<String> {
+        self.0.status()
+    }
+}
+
+/// A line of text, one character per cell. Cells hold code points, with 0 showing as a space
+#[derive(Debug, Clone)]
+pub struct TextDisplay {
+    cells: Vec<i64>,
+}
+
+impl TextDisplay {
+    /// # Panics
+    /// If `size` is zero
+    pub fn new(size: usize) -> Self {
+        assert!(size > 0, "a display needs at least one cell");
+
+        Self { cells: vec![0; size] }
+    }
+
+    /// Text currently shown on the display
+    pub fn text(&self) -> String {
+        self.cells
+            .iter()
+            .map(|&cell| match cell {
+                0 => ' ',
+                cell => u32::try_from(cell)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .unwrap_or(char::REPLACEMENT_CHARACTER),
+            })
+            .collect()
+    }
+}
+
+impl MappedDevice for TextDisplay {
+    fn name(&self) -> &str {
+        "Display"
+    }
+
+    fn size(&self) -> usize {
+        self.cells.len()
+    }
+
+    fn read(&mut self, offset: usize) -> Result<i64, DeviceError> {
+        Ok(self.cells[offset])
+    }
+
+    fn write(&mut self, offset: usize, value: i64) -> Result<(), DeviceError> {
+        self.cells[offset] = value;
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.cells.fill(0);
+    }
+
+    fn status(&self) -> Option<String> {
+        Some(format!("[{}]", self.text()))
+    }
+}
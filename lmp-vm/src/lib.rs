@@ -0,0 +1,32 @@
+//! The Little Man Computer virtual machine, without any interface
+//!
+//! Without the default `std` feature the crate only needs `alloc`, and programs have to be
+//! assembled ahead of time and loaded with [`VirtualMachine::load`] rather than compiled.
+//!
+//! ```
+//! use lmp_vm::assembly::Instruction;
+//! use lmp_vm::{VirtualMachine, VirtualMachineStep};
+//!
+//! let mut vm = VirtualMachine::new();
+//! vm.load([(0, Instruction::INP), (1, Instruction::ADDI(1)), (2, Instruction::OUT), (3, Instruction::HLT)]);
+//! vm.input(41);
+//!
+//! let mut outputs = Vec::new();
+//! loop {
+//!     match vm.step() {
+//!         VirtualMachineStep::Output(value) => outputs.push(value),
+//!         VirtualMachineStep::Halted => break,
+//!         _ => {}
+//!     }
+//! }
+//! assert_eq!(outputs, [42]);
+//! ```
+#![cfg_attr(not(feature = "std"), no_std)]
+extern crate alloc;
+
+pub mod devices;
+mod bus;
+mod vm;
+
+pub use lmp_common::{assembly, MEMORY_SIZE};
+pub use vm::*;
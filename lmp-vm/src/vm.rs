@@ -0,0 +1,1200 @@
+use crate::bus::Bus;
+use crate::devices::{DeviceError, IoDevice, MappedDevice};
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::vec::Vec;
+use core::fmt::Debug;
+use core::ops::Range;
+use lmp_common::{assembly, MEMORY_SIZE};
+#[cfg(feature = "std")]
+use lmp_lang::{parser, SourceMap, SourceOrigin};
+use thiserror::Error;
+
+/// Number of interrupt lines. Line 0 is raised by the user, and the others by the device attached
+/// to the port with the same number
+pub const INTERRUPT_LINES: usize = 8;
+
+/// A Little Man Computer, which programs are compiled into and then run one [`step`] at a time
+///
+/// [`step`]: VirtualMachine::step
+#[derive(Debug)]
+pub struct VirtualMachine {
+    /// Points to a location in memory that the virtual machine is currently at
+    program_counter: usize,
+    accumulator: i64,
+    /// Points to the cell at the top of the stack. The stack grows downwards, so it is empty when
+    /// this is the end of the stack region
+    stack_pointer: usize,
+    /// Set by `ADD` and `SUB`
+    flags: Flags,
+    /// Added to the address of operands using indexed addressing
+    index: i64,
+    interrupts: Interrupts,
+    /// Memory of the virtual machine, along with devices mapped into it
+    bus: Bus,
+    /// Whether each cell was emitted as an instruction by the assembler
+    code: [bool; MEMORY_SIZE],
+    /// Writes to code, recorded when protection is [`Protection::Detect`]
+    self_modifications: Vec<SelfModification>,
+    /// Whether the virtual machine has reached a halt condition
+    halted: bool,
+
+    /// I/O
+    /// Values waiting to be read by `INP` and `INC`, in order
+    input: VecDeque<i64>,
+    /// Devices attached to ports other than 0, which is always the user
+    devices: BTreeMap<usize, Box<dyn IoDevice>>,
+
+    // Debug information
+    cycles: i64,
+    /// Last accessed memory location
+    accessing: usize,
+
+    config: VirtualMachineConfig,
+}
+
+/// Settings for a [`VirtualMachine`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VirtualMachineConfig {
+    /// Cells of memory used for the stack by `CAL`, `RET`, `PSH` and `POP`
+    pub stack: Range<usize>,
+    /// Start of the interrupt vector, the [`INTERRUPT_LINES`] cells holding the address of the
+    /// handler for each interrupt line
+    pub interrupt_vector: usize,
+    /// Fault when `INP` or `INC` find no input queued, instead of asking for more
+    pub fault_on_end_of_input: bool,
+    /// How writes to cells emitted as instructions are handled
+    pub protection: Protection,
+}
+
+/// How the VM handles a program writing over its own instructions
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Protection {
+    /// Allow it, as the Little Man Computer does
+    #[default]
+    Off,
+    /// Allow it, but record the write so that self-modifying code can be spotted
+    Detect,
+    /// Fault
+    WriteProtect,
+}
+
+impl Default for VirtualMachineConfig {
+    fn default() -> Self {
+        Self {
+            // The last 32 cells, where they are least likely to overlap with a program
+            stack: MEMORY_SIZE - 32..MEMORY_SIZE,
+            // Just below the stack
+            interrupt_vector: MEMORY_SIZE - 32 - INTERRUPT_LINES,
+            fault_on_end_of_input: false,
+            protection: Protection::default(),
+        }
+    }
+}
+
+impl Default for VirtualMachine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VirtualMachine {
+    /// Create a VM with the default configuration
+    pub fn new() -> Self {
+        Self::with_config(VirtualMachineConfig::default())
+    }
+
+    /// # Panics
+    /// If the stack region is empty, or it or the interrupt vector do not fit within [`MEMORY_SIZE`]
+    pub fn with_config(config: VirtualMachineConfig) -> Self {
+        assert!(
+            config.stack.start < config.stack.end && config.stack.end <= MEMORY_SIZE,
+            "stack region {:?} must be non-empty and fit into memory",
+            config.stack
+        );
+        assert!(
+            config.interrupt_vector + INTERRUPT_LINES <= MEMORY_SIZE,
+            "interrupt vector at {} must fit into memory",
+            config.interrupt_vector
+        );
+
+        Self {
+            program_counter: 0,
+            accumulator: 0,
+            stack_pointer: config.stack.end,
+            index: 0,
+            interrupts: Interrupts::default(),
+            flags: Flags::default(),
+            bus: Bus::new(),
+            code: [false; MEMORY_SIZE],
+            self_modifications: Vec::new(),
+            cycles: 0,
+            accessing: 0,
+            halted: false,
+            input: VecDeque::new(),
+            devices: BTreeMap::new(),
+            config,
+        }
+    }
+
+    /// Compile the provided assembly program and load it into the virtual machine's memory
+    #[cfg(feature = "std")]
+    pub fn compile<S: AsRef<str>>(&mut self, program: S) -> Result<(), VirtualMachineError> {
+        let mut sources = SourceMap::new();
+        let root = sources.add(SourceOrigin::Input, program.as_ref());
+        let compiled = parser::assemble_sources(&mut sources, root).map_err(|errors| {
+            VirtualMachineError::CompilerError(
+                errors.iter().map(|error| error.report(&sources)).collect(),
+            )
+        })?;
+
+        // The assembler has already checked that the program fits into memory
+        self.load(compiled.cells.iter().map(|cell| (cell.address, cell.instruction)));
+
+        Ok(())
+    }
+
+    /// Load an assembled program into memory, given as the address of each instruction, after
+    /// resetting the VM. Cells not given are left empty, and every instruction but `DAT` is treated
+    /// as code
+    ///
+    /// # Panics
+    /// If an address does not fit within [`MEMORY_SIZE`]
+    pub fn load<I: IntoIterator<Item = (usize, assembly::Instruction<i64>)>>(&mut self, program: I) {
+        // Clear memory and reset registers
+        self.reset();
+
+        // Load program into memory cell by cell
+        for (addr, instruction) in program {
+            assert!(addr < MEMORY_SIZE, "address {addr} does not fit into memory");
+
+            self.bus.load(addr, instruction.into());
+            self.code[addr] = !matches!(instruction, assembly::Instruction::DAT(_));
+        }
+
+        // Reset halt state
+        self.halted = false;
+    }
+
+    /// Clear memory, registers and queued input, and reset attached and mapped devices. Devices
+    /// stay attached and mapped
+    pub fn reset(&mut self) {
+        self.bus.clear();
+        self.code = [false; MEMORY_SIZE];
+        self.self_modifications.clear();
+        self.cycles = 0;
+        self.accessing = 0;
+        self.accumulator = 0;
+        self.program_counter = 0;
+        self.stack_pointer = self.config.stack.end;
+        self.index = 0;
+        self.interrupts = Interrupts::default();
+        self.flags = Flags::default();
+        self.input.clear();
+        for device in self.devices.values_mut() {
+            device.reset();
+        }
+    }
+
+    /// Attach a device to a port, returning the device previously attached to it
+    ///
+    /// # Panics
+    /// If the port is 0, which is reserved for the user, or cannot be encoded in an instruction
+    pub fn attach(&mut self, port: usize, device: Box<dyn IoDevice>) -> Option<Box<dyn IoDevice>> {
+        assert!((1..=999).contains(&port), "devices can only be attached to ports 1 to 999");
+
+        self.devices.insert(port, device)
+    }
+
+    /// Detach the device attached to a port
+    pub fn detach(&mut self, port: usize) -> Option<Box<dyn IoDevice>> {
+        self.devices.remove(&port)
+    }
+
+    /// Devices attached to the VM along with their ports, in order of port
+    pub fn devices(&self) -> impl Iterator<Item = (usize, &dyn IoDevice)> {
+        self.devices.iter().map(|(port, device)| (*port, device.as_ref()))
+    }
+
+    /// Raise an interrupt line, which is handled once interrupts are enabled
+    ///
+    /// # Panics
+    /// If the line is not below [`INTERRUPT_LINES`]
+    pub fn interrupt(&mut self, line: usize) {
+        assert!(line < INTERRUPT_LINES, "there is no interrupt line {line}");
+
+        self.interrupts.pending |= 1 << line;
+    }
+
+    /// Map a device into memory starting from an address, so that accesses to its cells are
+    /// handled by the device. Fails without mapping the device if it does not fit into memory or
+    /// overlaps with another mapped device
+    pub fn map(&mut self, start: usize, device: Box<dyn MappedDevice>) -> Result<(), MapError> {
+        self.bus.map(start, device)
+    }
+
+    /// Remove the device mapped starting from an address
+    pub fn unmap(&mut self, start: usize) -> Option<Box<dyn MappedDevice>> {
+        self.bus.unmap(start)
+    }
+
+    /// Devices mapped into memory along with the cells they occupy, in order of address
+    pub fn mapped(&self) -> impl Iterator<Item = (Range<usize>, &dyn MappedDevice)> {
+        self.bus.mapped()
+    }
+
+    /// Execute the next instruction, or handle a pending interrupt
+    pub fn step(&mut self) -> VirtualMachineStep {
+        if self.halted {
+            return VirtualMachineStep::Halted;
+        }
+
+        if self.program_counter >= MEMORY_SIZE {
+            self.halted = true;
+            return VirtualMachineStep::Halted;
+        }
+
+        if self.interrupts.enabled && self.interrupts.pending != 0 {
+            return match self.service_interrupt() {
+                Ok(step) => step,
+                Err(fault) => self.fault(fault),
+            };
+        }
+
+        // Fetch, always from memory as devices cannot hold instructions
+        let cell = self.bus.memory()[self.program_counter];
+        // Decode
+        let Ok(decoded) = cell.data.try_into() else {
+            self.program_counter += 1;
+            // Skip the undecodable instruction; might change to halting the VM in the future
+            return VirtualMachineStep::Advanced;
+        };
+        // Execute
+        let cycles = self.cycles;
+        let step = match self.execute(decoded) {
+            Ok(step) => step,
+            Err(fault) => return self.fault(fault),
+        };
+        // Only instructions that ran count as a cycle, e.g. not `INP` waiting for input, so devices
+        // keep time with `cycles`
+        if self.cycles != cycles {
+            self.tick();
+        }
+        step
+    }
+
+    /// Advance devices by a cycle, raising the interrupts of those that fired
+    fn tick(&mut self) {
+        for (port, device) in self.devices.iter_mut() {
+            device.tick();
+            if *port < INTERRUPT_LINES && device.interrupt() {
+                self.interrupts.pending |= 1 << port;
+            }
+        }
+        self.bus.tick();
+    }
+
+    /// Execute a decoded instruction
+    fn execute(&mut self, instruction: assembly::Instruction<i64>) -> Result<VirtualMachineStep, VirtualMachineFault> {
+        use assembly::Instruction::*;
+        match instruction {
+            ADD(addr) => {
+                let value = self.ptr_get(addr)?;
+                self.add(value);
+                self.program_counter += 1;
+                self.cycles += 1;
+                Ok(VirtualMachineStep::Advanced)
+            }
+            SUB(addr) => {
+                let value = self.ptr_get(addr)?;
+                self.subtract(value);
+                self.program_counter += 1;
+                self.cycles += 1;
+                Ok(VirtualMachineStep::Advanced)
+            },
+            STA(addr) => {
+                self.ptr_write(addr, self.accumulator)?;
+                self.program_counter += 1;
+                self.cycles += 1;
+                Ok(VirtualMachineStep::Advanced)
+            },
+            LDA(addr) => {
+                self.accumulator = self.ptr_get(addr)?;
+                self.program_counter += 1;
+                self.cycles += 1;
+                Ok(VirtualMachineStep::Advanced)
+            },
+            BRA(addr) => {
+                self.branch(addr)?;
+                self.cycles += 1;
+                Ok(VirtualMachineStep::Advanced)
+            },
+            BRZ(addr) => {
+                if self.accumulator == 0 {
+                    self.branch(addr)?;
+                } else {
+                    self.program_counter += 1;
+                }
+
+                self.cycles += 1;
+
+                Ok(VirtualMachineStep::Advanced)
+            }
+            BRP(addr) => {
+                // BRP includes zero based on 101computing's LMC
+                if self.accumulator >= 0 {
+                    self.branch(addr)?;
+                } else {
+                    self.program_counter += 1;
+                }
+
+                self.cycles += 1;
+
+                Ok(VirtualMachineStep::Advanced)
+            },
+            BRN(addr) => {
+                if self.accumulator < 0 {
+                    self.branch(addr)?;
+                } else {
+                    self.program_counter += 1;
+                }
+
+                self.cycles += 1;
+
+                Ok(VirtualMachineStep::Advanced)
+            }
+            BNZ(addr) => {
+                if self.accumulator != 0 {
+                    self.branch(addr)?;
+                } else {
+                    self.program_counter += 1;
+                }
+
+                self.cycles += 1;
+
+                Ok(VirtualMachineStep::Advanced)
+            }
+            BRC(addr) => {
+                if self.flags.carry {
+                    self.branch(addr)?;
+                } else {
+                    self.program_counter += 1;
+                }
+
+                self.cycles += 1;
+
+                Ok(VirtualMachineStep::Advanced)
+            }
+            BRV(addr) => {
+                if self.flags.overflow {
+                    self.branch(addr)?;
+                } else {
+                    self.program_counter += 1;
+                }
+
+                self.cycles += 1;
+
+                Ok(VirtualMachineStep::Advanced)
+            }
+            BWN => {
+                self.accumulator = !self.accumulator;
+                self.program_counter += 1;
+                self.cycles += 1;
+                Ok(VirtualMachineStep::Advanced)
+            }
+            BWA(addr) => {
+                let value = self.ptr_get(addr)?;
+                self.accumulator &= value;
+                self.program_counter += 1;
+                self.cycles += 1;
+                Ok(VirtualMachineStep::Advanced)
+            },
+            BWO(addr) => {
+                let value = self.ptr_get(addr)?;
+                self.accumulator |= value;
+                self.program_counter += 1;
+                self.cycles += 1;
+                Ok(VirtualMachineStep::Advanced)
+            }
+            BWX(addr) => {
+                let value = self.ptr_get(addr)?;
+                self.accumulator ^= value;
+                self.program_counter += 1;
+                self.cycles += 1;
+                Ok(VirtualMachineStep::Advanced)
+            }
+            MUL(addr) => {
+                let value = self.ptr_get(addr)?;
+                self.accumulator = self.accumulator.wrapping_mul(value);
+                self.program_counter += 1;
+                self.cycles += 1;
+                Ok(VirtualMachineStep::Advanced)
+            }
+            DIV(addr) => {
+                let value = self.ptr_get(addr)?;
+                self.divide(value, i64::wrapping_div)
+            }
+            MOD(addr) => {
+                let value = self.ptr_get(addr)?;
+                self.divide(value, i64::wrapping_rem)
+            }
+            SHL(addr) => {
+                let value = self.ptr_get(addr)?;
+                self.accumulator = shift_left(self.accumulator, value);
+                self.program_counter += 1;
+                self.cycles += 1;
+                Ok(VirtualMachineStep::Advanced)
+            }
+            SHR(addr) => {
+                let value = self.ptr_get(addr)?;
+                self.accumulator = shift_right(self.accumulator, value);
+                self.program_counter += 1;
+                self.cycles += 1;
+                Ok(VirtualMachineStep::Advanced)
+            }
+            SAR(addr) => {
+                let value = self.ptr_get(addr)?;
+                self.accumulator = shift_right_arithmetic(self.accumulator, value);
+                self.program_counter += 1;
+                self.cycles += 1;
+                Ok(VirtualMachineStep::Advanced)
+            }
+            // Immediate operands are the value itself, so no memory is accessed
+            ADDI(value) => {
+                self.add(value);
+                self.program_counter += 1;
+                self.cycles += 1;
+                Ok(VirtualMachineStep::Advanced)
+            }
+            SUBI(value) => {
+                self.subtract(value);
+                self.program_counter += 1;
+                self.cycles += 1;
+                Ok(VirtualMachineStep::Advanced)
+            }
+            LDAI(value) => {
+                self.accumulator = value;
+                self.program_counter += 1;
+                self.cycles += 1;
+                Ok(VirtualMachineStep::Advanced)
+            }
+            BWAI(value) => {
+                self.accumulator &= value;
+                self.program_counter += 1;
+                self.cycles += 1;
+                Ok(VirtualMachineStep::Advanced)
+            }
+            BWOI(value) => {
+                self.accumulator |= value;
+                self.program_counter += 1;
+                self.cycles += 1;
+                Ok(VirtualMachineStep::Advanced)
+            }
+            BWXI(value) => {
+                self.accumulator ^= value;
+                self.program_counter += 1;
+                self.cycles += 1;
+                Ok(VirtualMachineStep::Advanced)
+            }
+            MULI(value) => {
+                self.accumulator = self.accumulator.wrapping_mul(value);
+                self.program_counter += 1;
+                self.cycles += 1;
+                Ok(VirtualMachineStep::Advanced)
+            }
+            DIVI(value) => self.divide(value, i64::wrapping_div),
+            MODI(value) => self.divide(value, i64::wrapping_rem),
+            SHLI(value) => {
+                self.accumulator = shift_left(self.accumulator, value);
+                self.program_counter += 1;
+                self.cycles += 1;
+                Ok(VirtualMachineStep::Advanced)
+            }
+            SHRI(value) => {
+                self.accumulator = shift_right(self.accumulator, value);
+                self.program_counter += 1;
+                self.cycles += 1;
+                Ok(VirtualMachineStep::Advanced)
+            }
+            SARI(value) => {
+                self.accumulator = shift_right_arithmetic(self.accumulator, value);
+                self.program_counter += 1;
+                self.cycles += 1;
+                Ok(VirtualMachineStep::Advanced)
+            }
+            STR(addr) => {
+                let target = self.ptr_get(addr)?;
+                if !(0..MEMORY_SIZE as i64).contains(&target) {
+                    return Err(VirtualMachineFault::InvalidAddress {
+                        address: self.program_counter,
+                        target,
+                    });
+                }
+                self.accessing = target as usize;
+                self.write(target as usize, self.accumulator)?;
+                self.program_counter += 1;
+                self.cycles += 1;
+                Ok(VirtualMachineStep::Advanced)
+            }
+            LDX(addr) => {
+                self.index = self.ptr_get(addr)?;
+                self.program_counter += 1;
+                self.cycles += 1;
+                Ok(VirtualMachineStep::Advanced)
+            }
+            STX(addr) => {
+                self.ptr_write(addr, self.index)?;
+                self.program_counter += 1;
+                self.cycles += 1;
+                Ok(VirtualMachineStep::Advanced)
+            }
+            TAX => {
+                self.index = self.accumulator;
+                self.program_counter += 1;
+                self.cycles += 1;
+                Ok(VirtualMachineStep::Advanced)
+            }
+            TXA => {
+                self.accumulator = self.index;
+                self.program_counter += 1;
+                self.cycles += 1;
+                Ok(VirtualMachineStep::Advanced)
+            }
+            ADDX(addr) => {
+                let loc = self.indexed_loc(addr)?;
+                let value = self.read(loc)?;
+                self.add(value);
+                self.program_counter += 1;
+                self.cycles += 1;
+                Ok(VirtualMachineStep::Advanced)
+            }
+            SUBX(addr) => {
+                let loc = self.indexed_loc(addr)?;
+                let value = self.read(loc)?;
+                self.subtract(value);
+                self.program_counter += 1;
+                self.cycles += 1;
+                Ok(VirtualMachineStep::Advanced)
+            }
+            STAX(addr) => {
+                let loc = self.indexed_loc(addr)?;
+                self.write(loc, self.accumulator)?;
+                self.program_counter += 1;
+                self.cycles += 1;
+                Ok(VirtualMachineStep::Advanced)
+            }
+            LDAX(addr) => {
+                let loc = self.indexed_loc(addr)?;
+                self.accumulator = self.read(loc)?;
+                self.program_counter += 1;
+                self.cycles += 1;
+                Ok(VirtualMachineStep::Advanced)
+            }
+            CAL(addr) => {
+                self.push(self.program_counter as i64 + 1)?;
+                self.branch(addr)?;
+                self.cycles += 1;
+                Ok(VirtualMachineStep::Advanced)
+            }
+            RET => {
+                let address = self.pop()?;
+                if !(0..MEMORY_SIZE as i64).contains(&address) {
+                    return Err(VirtualMachineFault::InvalidReturn {
+                        address: self.program_counter,
+                        target: address,
+                    });
+                }
+                self.branch(address)?;
+                self.cycles += 1;
+                Ok(VirtualMachineStep::Advanced)
+            }
+            PSH => {
+                self.push(self.accumulator)?;
+                self.program_counter += 1;
+                self.cycles += 1;
+                Ok(VirtualMachineStep::Advanced)
+            }
+            POP => {
+                self.accumulator = self.pop()?;
+                self.program_counter += 1;
+                self.cycles += 1;
+                Ok(VirtualMachineStep::Advanced)
+            }
+            LDR => {
+                self.accumulator = self.ptr_get(self.accumulator)?;
+                self.program_counter += 1;
+                self.cycles += 1;
+                Ok(VirtualMachineStep::Advanced)
+            }
+            INP | INPP(0) => self.read_input(InputKind::Number),
+            INC => self.read_input(InputKind::Character),
+            OTC => {
+                // Values that are not characters are output as the replacement character
+                let character = u32::try_from(self.accumulator)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .unwrap_or(char::REPLACEMENT_CHARACTER);
+                self.program_counter += 1;
+                self.cycles += 1;
+                Ok(VirtualMachineStep::OutputCharacter(character))
+            }
+            OUT | OUTP(0) => {
+                self.program_counter += 1;
+                self.cycles += 1;
+                Ok(VirtualMachineStep::Output(self.accumulator))
+            },
+            INPP(port) => {
+                let port = port as usize;
+                let Some(device) = self.devices.get_mut(&port) else {
+                    return Err(VirtualMachineFault::NoDevice { address: self.program_counter, port });
+                };
+
+                match device.read() {
+                    Ok(Some(value)) => self.accumulator = value,
+                    // Wait for the device without advancing the program counter
+                    Ok(None) => return Ok(VirtualMachineStep::Waiting),
+                    Err(error) => {
+                        return Err(VirtualMachineFault::Device { address: self.program_counter, port, error });
+                    }
+                }
+                self.program_counter += 1;
+                self.cycles += 1;
+                Ok(VirtualMachineStep::Advanced)
+            }
+            OUTP(port) => {
+                let port = port as usize;
+                let Some(device) = self.devices.get_mut(&port) else {
+                    return Err(VirtualMachineFault::NoDevice { address: self.program_counter, port });
+                };
+
+                if let Err(error) = device.write(self.accumulator) {
+                    return Err(VirtualMachineFault::Device { address: self.program_counter, port, error });
+                }
+                self.program_counter += 1;
+                self.cycles += 1;
+                Ok(VirtualMachineStep::Advanced)
+            }
+            EI => {
+                self.interrupts.enabled = true;
+                self.program_counter += 1;
+                self.cycles += 1;
+                Ok(VirtualMachineStep::Advanced)
+            }
+            DI => {
+                self.interrupts.enabled = false;
+                self.program_counter += 1;
+                self.cycles += 1;
+                Ok(VirtualMachineStep::Advanced)
+            }
+            RTI => {
+                self.flags = Flags::from_bits(self.pop()?);
+                let address = self.pop()?;
+                if !(0..MEMORY_SIZE as i64).contains(&address) {
+                    return Err(VirtualMachineFault::InvalidReturn {
+                        address: self.program_counter,
+                        target: address,
+                    });
+                }
+                self.interrupts.enabled = true;
+                self.branch(address)?;
+                self.cycles += 1;
+                Ok(VirtualMachineStep::Advanced)
+            }
+            HLT => {
+                self.halted = true;
+                self.cycles += 1;
+                Ok(VirtualMachineStep::Halted)
+            },
+            DAT(_) => unreachable!("DAT instruction should have been removed by the compiler"),
+        }
+    }
+
+    /// Take the next queued input into the accumulator, or if there is none, ask for more without
+    /// stepping or fault depending on the configuration
+    fn read_input(&mut self, kind: InputKind) -> Result<VirtualMachineStep, VirtualMachineFault> {
+        let Some(input) = self.input.pop_front() else {
+            if self.config.fault_on_end_of_input {
+                return Err(VirtualMachineFault::EndOfInput { address: self.program_counter });
+            }
+            return Ok(VirtualMachineStep::InputRequired(kind));
+        };
+
+        self.accumulator = input;
+        self.program_counter += 1;
+        self.cycles += 1;
+        Ok(VirtualMachineStep::Advanced)
+    }
+
+    /// Jump to the handler of the lowest pending interrupt line, pushing the program counter and
+    /// then the flags onto the stack and disabling interrupts until `RTI`
+    fn service_interrupt(&mut self) -> Result<VirtualMachineStep, VirtualMachineFault> {
+        let line = self.interrupts.pending.trailing_zeros() as usize;
+        self.interrupts.pending &= !(1 << line);
+
+        let handler = self.read(self.config.interrupt_vector + line)?;
+        if !(0..MEMORY_SIZE as i64).contains(&handler) {
+            return Err(VirtualMachineFault::InvalidHandler {
+                address: self.program_counter,
+                line,
+                target: handler,
+            });
+        }
+
+        self.push(self.program_counter as i64)?;
+        self.push(self.flags.bits())?;
+        self.interrupts.enabled = false;
+        self.branch(handler)?;
+        Ok(VirtualMachineStep::Interrupted(line))
+    }
+
+    /// Set the `program_counter` to an address, following pointers
+    fn branch(&mut self, ptr: i64) -> Result<(), VirtualMachineFault> {
+        self.program_counter = match usize::try_from(ptr) {
+            Ok(loc) if loc < MEMORY_SIZE => loc,
+            _ => self.ptr_to_loc(ptr)?,
+        };
+        Ok(())
+    }
+
+    /// Write to a location in memory, or the device mapped there
+    fn write(&mut self, loc: usize, data: i64) -> Result<(), VirtualMachineFault> {
+        if loc < MEMORY_SIZE && self.code[loc] {
+            match self.config.protection {
+                Protection::Off => {}
+                Protection::Detect => self.self_modifications.push(SelfModification {
+                    address: self.program_counter,
+                    target: loc,
+                    cycle: self.cycles,
+                }),
+                Protection::WriteProtect => {
+                    return Err(VirtualMachineFault::WriteProtected { address: self.program_counter, target: loc });
+                }
+            }
+        }
+
+        self.bus.write(loc, data).map_err(|error| self.mapped_fault(loc, error))
+    }
+
+    /// Read from a location in memory, or the device mapped there
+    fn read(&mut self, loc: usize) -> Result<i64, VirtualMachineFault> {
+        self.bus.read(loc).map_err(|error| self.mapped_fault(loc, error))
+    }
+
+    fn mapped_fault(&self, loc: usize, error: DeviceError) -> VirtualMachineFault {
+        VirtualMachineFault::MappedDevice { address: self.program_counter, target: loc, error }
+    }
+
+    /// Write to a cell in memory with an [`i64`] pointer
+    fn ptr_write(&mut self, ptr: i64, data: i64) -> Result<(), VirtualMachineFault> {
+        let loc = self.ptr_to_loc(ptr)?;
+
+        self.write(loc, data)
+    }
+
+    /// Add to the accumulator, wrapping on overflow and setting the flags
+    fn add(&mut self, value: i64) {
+        let (result, overflow) = self.accumulator.overflowing_add(value);
+        let (_, carry) = (self.accumulator as u64).overflowing_add(value as u64);
+
+        self.accumulator = result;
+        self.flags = Flags { carry, overflow };
+    }
+
+    /// Subtract from the accumulator, wrapping on overflow and setting the flags. Carry is set when
+    /// a borrow is needed
+    fn subtract(&mut self, value: i64) {
+        let (result, overflow) = self.accumulator.overflowing_sub(value);
+        let (_, carry) = (self.accumulator as u64).overflowing_sub(value as u64);
+
+        self.accumulator = result;
+        self.flags = Flags { carry, overflow };
+    }
+
+    /// Set the accumulator to `op(accumulator, divisor)`, faulting instead if the divisor is zero
+    fn divide(&mut self, divisor: i64, op: fn(i64, i64) -> i64) -> Result<VirtualMachineStep, VirtualMachineFault> {
+        if divisor == 0 {
+            return Err(VirtualMachineFault::DivisionByZero {
+                address: self.program_counter,
+            });
+        }
+
+        self.accumulator = op(self.accumulator, divisor);
+        self.program_counter += 1;
+        self.cycles += 1;
+        Ok(VirtualMachineStep::Advanced)
+    }
+
+    /// Push a value onto the stack
+    fn push(&mut self, value: i64) -> Result<(), VirtualMachineFault> {
+        if self.stack_pointer == self.config.stack.start {
+            return Err(VirtualMachineFault::StackOverflow { address: self.program_counter });
+        }
+
+        self.stack_pointer -= 1;
+        self.accessing = self.stack_pointer;
+        self.write(self.stack_pointer, value)
+    }
+
+    /// Pop the value at the top of the stack
+    fn pop(&mut self) -> Result<i64, VirtualMachineFault> {
+        if self.stack_pointer == self.config.stack.end {
+            return Err(VirtualMachineFault::StackUnderflow { address: self.program_counter });
+        }
+
+        let value = self.read(self.stack_pointer)?;
+        self.accessing = self.stack_pointer;
+        self.stack_pointer += 1;
+        Ok(value)
+    }
+
+    /// Halt the VM because the program did something invalid
+    fn fault(&mut self, fault: VirtualMachineFault) -> VirtualMachineStep {
+        self.halted = true;
+        self.cycles += 1;
+        VirtualMachineStep::Fault(fault)
+    }
+
+    /// Get the value at a location in memory from an [`i64`], following pointers
+    fn ptr_get(&mut self, ptr: i64) -> Result<i64, VirtualMachineFault> {
+        let loc = self.ptr_to_loc(ptr)?;
+
+        self.read(loc)
+    }
+
+    /// Resolve the memory address of an indexed operand, adding the index register to the address
+    /// after following pointers
+    fn indexed_loc(&mut self, ptr: i64) -> Result<usize, VirtualMachineFault> {
+        let target = (self.ptr_to_loc(ptr)? as i64).wrapping_add(self.index);
+        if !(0..MEMORY_SIZE as i64).contains(&target) {
+            return Err(VirtualMachineFault::InvalidAddress { address: self.program_counter, target });
+        }
+
+        self.accessing = target as usize;
+        Ok(target as usize)
+    }
+
+    /// Resolve the actual [`usize`] memory address of a specified [`i64`]
+    /// following pointers as needed
+    ///
+    /// Also records memory location accessed (so it requires mutability)
+    ///
+    /// Faults if the address is neither a cell nor a pointer to one, or pointers lead back to
+    /// themselves
+    fn ptr_to_loc(&mut self, ptr: i64) -> Result<usize, VirtualMachineFault> {
+        let mut target = ptr;
+        // A chain of pointers longer than memory must go around in a loop
+        for _ in 0..=MEMORY_SIZE {
+            match usize::try_from(target) {
+                Ok(loc) if loc < MEMORY_SIZE => {
+                    self.accessing = loc;
+                    return Ok(loc);
+                }
+                // Pointer (MEMORY_SIZE + LOCATION) indicates a pointer at that location
+                Ok(loc) if loc < MEMORY_SIZE * 2 => {
+                    let resolved_loc = loc - MEMORY_SIZE;
+                    self.accessing = resolved_loc;
+                    target = self.read(resolved_loc)?;
+                }
+                _ => break,
+            }
+        }
+
+        Err(VirtualMachineFault::InvalidAddress { address: self.program_counter, target })
+    }
+
+    // Public access methods
+    pub fn accumulator(&self) -> i64 {
+        self.accumulator
+    }
+
+    pub fn program_counter(&self) -> usize {
+        self.program_counter
+    }
+
+    pub fn flags(&self) -> Flags {
+        self.flags
+    }
+
+    pub fn index(&self) -> i64 {
+        self.index
+    }
+
+    pub fn interrupts(&self) -> Interrupts {
+        self.interrupts
+    }
+
+    pub fn stack_pointer(&self) -> usize {
+        self.stack_pointer
+    }
+
+    /// Cells currently on the stack, starting from the top
+    pub fn stack(&self) -> &[MemoryCell] {
+        &self.bus.memory()[self.stack_pointer..self.config.stack.end]
+    }
+
+    pub fn config(&self) -> &VirtualMachineConfig {
+        &self.config
+    }
+
+    /// Number of cycles run since the VM was last reset
+    pub fn cycles(&self) -> i64 {
+        self.cycles
+    }
+
+    pub fn halted(&self) -> bool {
+        self.halted
+    }
+
+    /// Queue a value to be read by `INP` or `INC`. Characters are provided as their code point
+    pub fn input(&mut self, input: i64) {
+        self.input.push_back(input);
+    }
+
+    /// Queue several values to be read in order
+    pub fn input_all<I: IntoIterator<Item = i64>>(&mut self, inputs: I) {
+        self.input.extend(inputs);
+    }
+
+    /// Discard all queued input
+    pub fn clear_input(&mut self) {
+        self.input.clear();
+    }
+
+    /// Values waiting to be read, starting with the next one
+    pub fn queued_input(&self) -> &VecDeque<i64> {
+        &self.input
+    }
+
+    /// Whether each cell of memory was emitted as an instruction by the assembler
+    pub fn code(&self) -> &[bool] {
+        &self.code
+    }
+
+    /// Writes the program made to its own instructions, in order. Only recorded when protection
+    /// is [`Protection::Detect`]
+    pub fn self_modifications(&self) -> &[SelfModification] {
+        &self.self_modifications
+    }
+
+    /// Cells of memory. Cells with a device mapped to them hold the last value read or written
+    pub fn memory(&self) -> &[MemoryCell] {
+        self.bus.memory()
+    }
+
+    /// Last memory location accessed
+    pub fn accessing(&self) -> usize {
+        self.accessing
+    }
+
+    /// Overwrite a cell of memory from outside the program, e.g. from a debugger. Bypasses mapped
+    /// devices and protection, and is not recorded as a self-modification
+    ///
+    /// # Panics
+    /// If the address does not fit within [`MEMORY_SIZE`]
+    pub fn set_memory(&mut self, address: usize, data: i64) {
+        assert!(address < MEMORY_SIZE, "address {address} does not fit into memory");
+        self.bus.load(address, data);
+    }
+
+    /// Overwrite the accumulator from outside the program, e.g. from a debugger
+    pub fn set_accumulator(&mut self, value: i64) {
+        self.accumulator = value;
+    }
+
+    /// Overwrite the index register from outside the program
+    pub fn set_index(&mut self, value: i64) {
+        self.index = value;
+    }
+
+    /// Move execution to another address from outside the program. Addresses past the end of
+    /// memory halt the VM when it is next stepped
+    pub fn set_program_counter(&mut self, address: usize) {
+        self.program_counter = address;
+    }
+
+    /// Overwrite the stack pointer from outside the program
+    ///
+    /// # Panics
+    /// If the stack pointer is outside of the stack configured for the VM
+    pub fn set_stack_pointer(&mut self, address: usize) {
+        let stack = &self.config.stack;
+        assert!(
+            (stack.start..=stack.end).contains(&address),
+            "stack pointer {address} is outside of the stack"
+        );
+        self.stack_pointer = address;
+    }
+}
+
+/// Flags describing the result of the last `ADD` or `SUB`
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Flags {
+    /// The result did not fit when treating the operands as unsigned, i.e., it carried out of (or
+    /// borrowed into) the highest bit
+    pub carry: bool,
+    /// The result did not fit when treating the operands as signed
+    pub overflow: bool,
+}
+
+impl Flags {
+    /// Pack the flags into a value to be pushed onto the stack, with carry as the lowest bit
+    fn bits(self) -> i64 {
+        self.carry as i64 | (self.overflow as i64) << 1
+    }
+
+    fn from_bits(bits: i64) -> Self {
+        Self {
+            carry: bits & 1 != 0,
+            overflow: bits & 2 != 0,
+        }
+    }
+}
+
+/// A write by the program to one of its own instructions
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SelfModification {
+    /// Address of the instruction that wrote
+    pub address: usize,
+    /// Address of the instruction written over
+    pub target: usize,
+    /// Cycle the write happened on
+    pub cycle: i64,
+}
+
+/// State of the interrupt controller
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Interrupts {
+    /// Set by `EI` and `RTI`, and cleared by `DI` and when an interrupt is handled
+    pub enabled: bool,
+    /// Interrupt lines that have been raised but not yet handled, one bit for each line
+    pub pending: u8,
+}
+
+impl Interrupts {
+    /// Lines that are waiting to be handled, in order of priority
+    pub fn pending_lines(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..INTERRUPT_LINES).filter(|line| self.pending >> line & 1 == 1)
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct MemoryCell {
+    pub data: i64,
+}
+
+impl MemoryCell {
+    /// Set the data of this cell
+    pub(crate) fn set(&mut self, data: i64) {
+        self.data = data
+    }
+}
+
+#[cfg(feature = "std")]
+#[derive(Debug, Error)]
+pub enum VirtualMachineError {
+    #[error("Could not compile the program.\n{}", .0.join("\n"))]
+    CompilerError(Vec<String>),
+}
+
+/// Why a device could not be mapped into memory
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum MapError {
+    #[error("A device must occupy at least one cell")]
+    Empty,
+    #[error("Cells {}-{} do not fit into memory", .cells.start, .cells.end - 1)]
+    OutOfMemory { cells: Range<usize> },
+    #[error("Cells {}-{} overlap with a device at {}-{}", .cells.start, .cells.end - 1, .other.start, .other.end - 1)]
+    Overlap { cells: Range<usize>, other: Range<usize> },
+}
+
+/// The type of input requested by the VM
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputKind {
+    /// An integer, requested by `INP`
+    Number,
+    /// A single character, requested by `INC`
+    Character,
+}
+
+/// A condition that stops the VM because the program did something invalid
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[non_exhaustive]
+pub enum VirtualMachineFault {
+    #[error("Division by zero at address {address}")]
+    DivisionByZero { address: usize },
+    #[error("Stack overflow at address {address}")]
+    StackOverflow { address: usize },
+    #[error("Stack underflow at address {address}")]
+    StackUnderflow { address: usize },
+    #[error("Access to invalid address {target} at address {address}")]
+    InvalidAddress { address: usize, target: i64 },
+    #[error("Return to invalid address {target} at address {address}")]
+    InvalidReturn { address: usize, target: i64 },
+    #[error("Handler for interrupt line {line} is at invalid address {target}, at address {address}")]
+    InvalidHandler { address: usize, line: usize, target: i64 },
+    #[error("Write to protected code at {target} at address {address}")]
+    WriteProtected { address: usize, target: usize },
+    #[error("Ran out of input at address {address}")]
+    EndOfInput { address: usize },
+    #[error("No device attached to port {port} at address {address}")]
+    NoDevice { address: usize, port: usize },
+    #[error("Device on port {port} failed at address {address}: {error}")]
+    Device {
+        address: usize,
+        port: usize,
+        error: DeviceError,
+    },
+    #[error("Device mapped at {target} failed at address {address}: {error}")]
+    MappedDevice {
+        address: usize,
+        target: usize,
+        error: DeviceError,
+    },
+}
+
+/// The result of the VM after stepping it by one cycle
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum VirtualMachineStep {
+    /// The VM has executed an instruction
+    Advanced,
+    /// The VM produced an output
+    Output(i64),
+    /// The VM produced a character to output
+    OutputCharacter(char),
+    /// The VM requires an input and will block here without advancing the program counter until the
+    /// input buffer is filled
+    InputRequired(InputKind),
+    /// The VM is waiting for a device to have a value to read, and did not advance
+    Waiting,
+    /// The VM jumped to the handler of an interrupt line instead of executing an instruction
+    Interrupted(usize),
+    /// The VM has reached a halt condition
+    Halted,
+    /// The VM has stopped because of a fault, it is halted from this point on
+    Fault(VirtualMachineFault),
+}
+
+// Shift amounts outside of 0 to 63 shift out every bit, rather than wrapping like Rust's shifts
+
+fn shift_left(value: i64, amount: i64) -> i64 {
+    u32::try_from(amount)
+        .ok()
+        .and_then(|amount| value.checked_shl(amount))
+        .unwrap_or(0)
+}
+
+fn shift_right(value: i64, amount: i64) -> i64 {
+    u32::try_from(amount)
+        .ok()
+        .and_then(|amount| (value as u64).checked_shr(amount))
+        .map_or(0, |value| value as i64)
+}
+
+fn shift_right_arithmetic(value: i64, amount: i64) -> i64 {
+    u32::try_from(amount)
+        .ok()
+        .and_then(|amount| value.checked_shr(amount))
+        // Every bit is the sign bit
+        .unwrap_or(value >> 63)
+}
\ No newline at end of file
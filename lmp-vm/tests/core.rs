@@ -0,0 +1,344 @@
+//! Only uses what is available without the `std` feature, so that configuration can be tested with
+//! `cargo test -p lmp-vm --no-default-features`
+use lmp_vm::assembly::Instruction;
+use lmp_vm::devices::{LedBank, Random};
+use lmp_vm::{
+    Flags, InputKind, MEMORY_SIZE, Protection, SelfModification, VirtualMachine, VirtualMachineConfig,
+    VirtualMachineFault, VirtualMachineStep,
+};
+
+/// Run the VM until it halts, collecting its outputs
+fn run(vm: &mut VirtualMachine) -> Result<Vec<i64>, VirtualMachineFault> {
+    let mut outputs = Vec::new();
+    loop {
+        match vm.step() {
+            VirtualMachineStep::Output(value) => outputs.push(value),
+            VirtualMachineStep::Halted => return Ok(outputs),
+            VirtualMachineStep::Fault(fault) => return Err(fault),
+            VirtualMachineStep::InputRequired(_) => panic!("ran out of input"),
+            _ => {}
+        }
+    }
+}
+
+#[test]
+fn test_load() {
+    use Instruction::*;
+
+    // Count down from the input, through a subroutine that outputs the accumulator
+    let mut vm = VirtualMachine::new();
+    vm.load([
+        (0, INP),
+        (1, CAL(5)),
+        (2, SUBI(1)),
+        (3, BRP(1)),
+        (4, HLT),
+        (5, OUT),
+        (6, RET),
+    ]);
+    vm.input(3);
+    assert_eq!(run(&mut vm), Ok(vec![3, 2, 1, 0]));
+
+    // Encoding round trips through memory
+    assert_eq!(vm.memory()[1].data, 40005);
+    assert_eq!(Instruction::try_from(vm.memory()[1].data), Ok(CAL(5)));
+    assert_eq!(&vm.code()[..8], [true, true, true, true, true, true, true, false]);
+
+    let mut vm = VirtualMachine::new();
+    vm.load([(0, DIV(2)), (1, HLT), (2, DAT(0))]);
+    assert_eq!(run(&mut vm), Err(VirtualMachineFault::DivisionByZero { address: 0 }));
+}
+
+#[test]
+fn test_devices() {
+    use Instruction::*;
+
+    let mut vm = VirtualMachine::new();
+    vm.attach(1, Box::new(Random::with_range(7, 1, 6)));
+    vm.attach(2, Box::new(LedBank::new(4)));
+    vm.load([(0, INPP(1)), (1, OUTP(2)), (2, OUT), (3, HLT)]);
+
+    let outputs = run(&mut vm).unwrap();
+    assert!((1..=6).contains(&outputs[0]));
+    let (_, leds) = vm.devices().nth(1).unwrap();
+    assert_eq!(leds.status().unwrap().chars().filter(|&led| led == '●').count(), outputs[0].count_ones() as usize);
+}
+
+#[test]
+fn test_reload() {
+    use Instruction::*;
+
+    // Loading resets a VM that has not halted, including its queued input
+    let mut vm = VirtualMachine::new();
+    vm.load([(0, INP), (1, HLT), (100, DAT(77))]);
+    vm.input(5);
+    vm.load([(0, LDA(100)), (1, OUT), (2, HLT)]);
+    assert_eq!(run(&mut vm), Ok(vec![0]));
+    assert!(vm.queued_input().is_empty());
+}
+
+#[test]
+fn test_invalid_addresses() {
+    use Instruction::*;
+
+    // `LDR` takes its address from the accumulator, which the program controls
+    let mut vm = VirtualMachine::new();
+    vm.load([(0, INP), (1, LDR), (2, HLT)]);
+    vm.input(-1);
+    assert_eq!(run(&mut vm), Err(VirtualMachineFault::InvalidAddress { address: 1, target: -1 }));
+
+    // 512 is a pointer to cell 0, rather than a cell past the end of memory
+    let mut vm = VirtualMachine::new();
+    vm.load([(0, DAT(5)), (1, LDA(512 + 4)), (2, OUT), (3, HLT), (4, DAT(512)), (5, DAT(42))]);
+    vm.set_program_counter(1);
+    assert_eq!(run(&mut vm), Ok(vec![42]));
+
+    // Pointers that lead back to themselves
+    let mut vm = VirtualMachine::new();
+    vm.load([(0, LDA(512 + 2)), (1, HLT), (2, DAT(512 + 2))]);
+    assert_eq!(run(&mut vm), Err(VirtualMachineFault::InvalidAddress { address: 0, target: 512 + 2 }));
+
+    // Branches follow pointers
+    let mut vm = VirtualMachine::new();
+    vm.load([(0, LDAI(7)), (1, BRA(512 + 5)), (2, HLT), (3, OUT), (4, HLT), (5, DAT(3))]);
+    assert_eq!(run(&mut vm), Ok(vec![7]));
+
+    let mut vm = VirtualMachine::new();
+    vm.load([(0, BRA(512 + 2)), (1, HLT), (2, DAT(-1))]);
+    assert_eq!(run(&mut vm), Err(VirtualMachineFault::InvalidAddress { address: 0, target: -1 }));
+}
+
+#[test]
+fn test_protection() {
+    use Instruction::*;
+
+    // Overwrite the `OUT` at 2 with a `HLT`
+    let with_protection = |protection| {
+        let mut vm = VirtualMachine::with_config(VirtualMachineConfig { protection, ..Default::default() });
+        vm.load([(0, LDA(10)), (1, STA(2)), (2, OUT), (3, OUT), (4, HLT), (10, DAT(HLT.into()))]);
+        vm
+    };
+
+    let mut vm = with_protection(Protection::Off);
+    assert_eq!(run(&mut vm), Ok(vec![]));
+    assert!(vm.self_modifications().is_empty());
+
+    let mut vm = with_protection(Protection::Detect);
+    assert_eq!(run(&mut vm), Ok(vec![]));
+    assert_eq!(vm.self_modifications(), [SelfModification { address: 1, target: 2, cycle: 1 }]);
+
+    let mut vm = with_protection(Protection::WriteProtect);
+    assert_eq!(run(&mut vm), Err(VirtualMachineFault::WriteProtected { address: 1, target: 2 }));
+    assert_eq!(vm.memory()[2].data, i64::from(OUT));
+
+    // Cells given with `DAT` can still be written
+    let mut vm = VirtualMachine::with_config(VirtualMachineConfig {
+        protection: Protection::WriteProtect,
+        ..Default::default()
+    });
+    vm.load([(0, LDAI(4)), (1, STA(10)), (2, LDA(10)), (3, OUT), (4, HLT), (10, DAT(0))]);
+    assert_eq!(run(&mut vm), Ok(vec![4]));
+}
+
+#[test]
+fn test_end_of_input() {
+    use Instruction::*;
+
+    // Running out of input asks for more, and carries on from the same instruction once given
+    let mut vm = VirtualMachine::new();
+    vm.load([(0, INP), (1, OUT), (2, HLT)]);
+    assert!(matches!(vm.step(), VirtualMachineStep::InputRequired(InputKind::Number)));
+    assert_eq!(vm.program_counter(), 0);
+    vm.input(6);
+    assert_eq!(run(&mut vm), Ok(vec![6]));
+
+    let mut vm = VirtualMachine::with_config(VirtualMachineConfig {
+        fault_on_end_of_input: true,
+        ..Default::default()
+    });
+    vm.load([(0, INP), (1, OUT), (2, INC), (3, HLT)]);
+    vm.input(6);
+    assert_eq!(run(&mut vm), Err(VirtualMachineFault::EndOfInput { address: 2 }));
+}
+
+/// Run a single instruction with the accumulator set to `accumulator` and cell 10 set to
+/// `operand`, returning the accumulator after it
+fn apply(instruction: Instruction<i64>, accumulator: i64, operand: i64) -> Result<i64, VirtualMachineFault> {
+    let mut vm = VirtualMachine::new();
+    vm.load([(0, instruction), (1, Instruction::HLT), (10, Instruction::DAT(operand))]);
+    vm.set_accumulator(accumulator);
+    run(&mut vm).map(|_| vm.accumulator())
+}
+
+#[test]
+fn test_arithmetic() {
+    use Instruction::*;
+
+    // The remainder takes the sign of the dividend
+    assert_eq!(apply(MOD(10), 17, 5), Ok(2));
+    assert_eq!(apply(MOD(10), -17, 5), Ok(-2));
+    assert_eq!(apply(MOD(10), 17, -5), Ok(2));
+    assert_eq!(apply(MODI(5), -17, 0), Ok(-2));
+    assert_eq!(apply(DIVI(5), -17, 0), Ok(-3));
+
+    // Dividing the smallest value by -1 wraps
+    assert_eq!(apply(DIV(10), i64::MIN, -1), Ok(i64::MIN));
+    assert_eq!(apply(MOD(10), i64::MIN, -1), Ok(0));
+
+    // The address of a division by zero is that of the instruction
+    assert_eq!(apply(DIV(10), 17, 0), Err(VirtualMachineFault::DivisionByZero { address: 0 }));
+    assert_eq!(apply(MOD(10), 17, 0), Err(VirtualMachineFault::DivisionByZero { address: 0 }));
+    assert_eq!(apply(DIVI(0), 17, 0), Err(VirtualMachineFault::DivisionByZero { address: 0 }));
+    assert_eq!(apply(MODI(0), 17, 0), Err(VirtualMachineFault::DivisionByZero { address: 0 }));
+}
+
+#[test]
+fn test_shifts() {
+    use Instruction::*;
+
+    assert_eq!(apply(SHL(10), 3, 2), Ok(12));
+    assert_eq!(apply(SHLI(63), 1, 0), Ok(i64::MIN));
+    assert_eq!(apply(SHR(10), 12, 2), Ok(3));
+    assert_eq!(apply(SARI(2), -12, 0), Ok(-3));
+
+    // Logical shifts bring in zeros above a negative value
+    assert_eq!(apply(SHRI(1), -2, 0), Ok(i64::MAX));
+    assert_eq!(apply(SHR(10), -1, 60), Ok(0xf));
+
+    // Shifting by 64 or more, or a negative amount, shifts out every bit
+    for amount in [64, 65, 1000, -1] {
+        assert_eq!(apply(SHL(10), -1, amount), Ok(0));
+        assert_eq!(apply(SHR(10), -1, amount), Ok(0));
+        assert_eq!(apply(SAR(10), -5, amount), Ok(-1));
+        assert_eq!(apply(SAR(10), 5, amount), Ok(0));
+    }
+    assert_eq!(apply(SHLI(64), 1, 0), Ok(0));
+    assert_eq!(apply(SARI(64), i64::MIN, 0), Ok(-1));
+}
+
+#[test]
+fn test_stack() {
+    use Instruction::*;
+
+    // A stack of two cells, pushing the values 1 to 3 and popping them back
+    let with_stack = |program: &[(usize, Instruction<i64>)]| {
+        let mut vm = VirtualMachine::with_config(VirtualMachineConfig { stack: 100..102, ..Default::default() });
+        vm.load(program.iter().copied());
+        vm
+    };
+
+    let mut vm = with_stack(&[
+        (0, LDAI(1)),
+        (1, PSH),
+        (2, LDAI(2)),
+        (3, PSH),
+        (4, POP),
+        (5, OUT),
+        (6, POP),
+        (7, OUT),
+        (8, HLT),
+    ]);
+    vm.step();
+    vm.step();
+    assert_eq!(vm.stack_pointer(), 101);
+    vm.step();
+    vm.step();
+    assert_eq!(vm.stack_pointer(), 100);
+    assert_eq!(vm.stack().iter().map(|cell| cell.data).collect::<Vec<_>>(), [2, 1]);
+    assert_eq!(vm.memory()[101].data, 1);
+    assert_eq!(run(&mut vm), Ok(vec![2, 1]));
+    assert_eq!(vm.stack_pointer(), 102);
+
+    let mut vm = with_stack(&[(0, PSH), (1, PSH), (2, PSH), (3, HLT)]);
+    assert_eq!(run(&mut vm), Err(VirtualMachineFault::StackOverflow { address: 2 }));
+    assert_eq!(vm.stack_pointer(), 100);
+    assert_eq!(vm.memory()[99].data, 0);
+
+    let mut vm = with_stack(&[(0, PSH), (1, POP), (2, POP), (3, HLT)]);
+    assert_eq!(run(&mut vm), Err(VirtualMachineFault::StackUnderflow { address: 2 }));
+
+    // Calls push their return address, so they overflow too
+    let mut vm = with_stack(&[(0, CAL(0))]);
+    assert_eq!(run(&mut vm), Err(VirtualMachineFault::StackOverflow { address: 0 }));
+
+    let mut vm = with_stack(&[(0, RET)]);
+    assert_eq!(run(&mut vm), Err(VirtualMachineFault::StackUnderflow { address: 0 }));
+
+    // The default stack takes up the last 32 cells of memory
+    let mut vm = VirtualMachine::new();
+    vm.load([(0, PSH), (1, BRA(0))]);
+    assert_eq!(run(&mut vm), Err(VirtualMachineFault::StackOverflow { address: 0 }));
+    assert_eq!(vm.stack_pointer(), MEMORY_SIZE - 32);
+}
+
+#[test]
+#[should_panic(expected = "must be non-empty and fit into memory")]
+fn test_empty_stack() {
+    VirtualMachine::with_config(VirtualMachineConfig { stack: 100..100, ..Default::default() });
+}
+
+#[test]
+#[should_panic(expected = "must be non-empty and fit into memory")]
+fn test_stack_past_memory() {
+    VirtualMachine::with_config(VirtualMachineConfig { stack: 500..MEMORY_SIZE + 1, ..Default::default() });
+}
+
+#[test]
+fn test_flags() {
+    use Instruction::*;
+
+    let flags = |instruction, accumulator, operand| {
+        let mut vm = VirtualMachine::new();
+        vm.load([(0, instruction), (1, HLT), (10, DAT(operand))]);
+        vm.set_accumulator(accumulator);
+        run(&mut vm).unwrap();
+        let Flags { carry, overflow } = vm.flags();
+        (carry, overflow)
+    };
+
+    assert_eq!(flags(ADD(10), 1, 1), (false, false));
+    assert_eq!(flags(ADD(10), -1, 1), (true, false));
+    assert_eq!(flags(ADD(10), i64::MAX, 1), (false, true));
+    assert_eq!(flags(ADD(10), i64::MIN, -1), (true, true));
+    assert_eq!(flags(SUB(10), 5, 3), (false, false));
+    assert_eq!(flags(SUB(10), 0, 1), (true, false));
+    assert_eq!(flags(SUB(10), i64::MIN, 1), (false, true));
+    assert_eq!(flags(SUB(10), 0, i64::MIN), (true, true));
+    assert_eq!(flags(ADDI(1), -1, 0), (true, false));
+    assert_eq!(flags(SUBI(1), 0, 0), (true, false));
+
+    // Flags stay set until the next `ADD` or `SUB`, which clears them
+    let mut vm = VirtualMachine::new();
+    vm.load([(0, ADDI(1)), (1, LDAI(1)), (2, STA(10)), (3, ADDI(1)), (4, HLT)]);
+    vm.set_accumulator(i64::MAX);
+    vm.step();
+    vm.step();
+    vm.step();
+    assert_eq!(vm.flags(), Flags { carry: false, overflow: true });
+    run(&mut vm).unwrap();
+    assert_eq!(vm.flags(), Flags::default());
+}
+
+#[test]
+fn test_branches() {
+    use Instruction::*;
+
+    // Whether a branch is taken after adding `operand` to `accumulator`
+    let taken = |branch: fn(i64) -> Instruction<i64>, accumulator, operand| {
+        let mut vm = VirtualMachine::new();
+        vm.load([(0, ADD(10)), (1, branch(4)), (2, OUT), (3, HLT), (4, HLT), (10, DAT(operand))]);
+        vm.set_accumulator(accumulator);
+        run(&mut vm).unwrap().is_empty()
+    };
+
+    assert!(taken(BRC, -1, 1));
+    assert!(!taken(BRC, 1, 1));
+    assert!(taken(BRV, i64::MAX, 1));
+    assert!(!taken(BRV, -1, 1));
+    assert!(taken(BNZ, 1, 1));
+    assert!(taken(BNZ, -3, 1));
+    assert!(!taken(BNZ, -1, 1));
+    assert!(taken(BRN, 0, -1));
+    assert!(!taken(BRN, 0, 0));
+    assert!(!taken(BRN, 0, 1));
+}
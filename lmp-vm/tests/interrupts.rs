@@ -0,0 +1,81 @@
+use lmp_vm::assembly::Instruction::{self, *};
+use lmp_vm::devices::{IntervalTimer, Timer};
+use lmp_vm::{MEMORY_SIZE, VirtualMachine, VirtualMachineStep};
+
+/// The default interrupt vector, just below the stack
+const VECTOR: usize = MEMORY_SIZE - 32 - 8;
+
+/// A handler for line 0 at address 10 that outputs 99, keeping the accumulator
+const HANDLER: [(usize, Instruction<i64>); 6] =
+    [(10, PSH), (11, LDAI(99)), (12, OUT), (13, POP), (14, RTI), (VECTOR, DAT(10))];
+
+/// Run the VM until it halts, collecting its outputs
+fn run(vm: &mut VirtualMachine) -> Vec<i64> {
+    let mut outputs = Vec::new();
+    loop {
+        match vm.step() {
+            VirtualMachineStep::Output(value) => outputs.push(value),
+            VirtualMachineStep::Halted => return outputs,
+            VirtualMachineStep::Fault(fault) => panic!("{fault}"),
+            VirtualMachineStep::InputRequired(_) => panic!("ran out of input"),
+            _ => {}
+        }
+    }
+}
+
+#[test]
+fn test_dispatch() {
+    // The interrupt waits for `EI`, then returns to where it was taken with the accumulator kept
+    let mut vm = VirtualMachine::new();
+    vm.load([(0, LDAI(1)), (1, EI), (2, OUT), (3, HLT)].into_iter().chain(HANDLER));
+    vm.interrupt(0);
+    assert!(matches!(vm.step(), VirtualMachineStep::Advanced));
+    assert!(matches!(vm.step(), VirtualMachineStep::Advanced));
+    assert!(matches!(vm.step(), VirtualMachineStep::Interrupted(0)));
+    assert_eq!(vm.program_counter(), 10);
+    assert!(!vm.interrupts().enabled);
+    assert_eq!(run(&mut vm), [99, 1]);
+    assert!(vm.interrupts().enabled);
+}
+
+#[test]
+fn test_masking() {
+    // Raised after `DI`, so handled only once interrupts are enabled again
+    let mut vm = VirtualMachine::new();
+    vm.load([(0, EI), (1, DI), (2, LDAI(1)), (3, OUT), (4, EI), (5, HLT)].into_iter().chain(HANDLER));
+    vm.step();
+    vm.step();
+    vm.interrupt(0);
+    assert_eq!(run(&mut vm), [1, 99]);
+    assert_eq!(vm.interrupts().pending, 0);
+}
+
+#[test]
+fn test_timer() {
+    // Fires every 3 instructions executed, not counting entering the handler
+    let mut vm = VirtualMachine::new();
+    vm.attach(1, Box::new(IntervalTimer::new(3)));
+    vm.load([(0, EI), (1, BRA(1)), (10, RTI), (VECTOR + 1, DAT(10))]);
+
+    let mut interrupted = Vec::new();
+    while interrupted.len() < 3 {
+        if let VirtualMachineStep::Interrupted(line) = vm.step() {
+            assert_eq!(line, 1);
+            interrupted.push(vm.cycles());
+        }
+    }
+    assert_eq!(interrupted, [3, 6, 9]);
+
+    // Devices do not tick while waiting for input
+    let mut vm = VirtualMachine::new();
+    vm.attach(2, Box::new(Timer::new()));
+    vm.load([(0, INP), (1, HLT)]);
+    for _ in 0..3 {
+        assert!(matches!(vm.step(), VirtualMachineStep::InputRequired(_)));
+    }
+    vm.input(5);
+    run(&mut vm);
+    let (_, timer) = vm.devices().next().unwrap();
+    assert_eq!(timer.status().as_deref(), Some("2"));
+    assert_eq!(vm.cycles(), 2);
+}
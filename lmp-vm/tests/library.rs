@@ -0,0 +1,117 @@
+//! Runs the macros of the standard library included with `INCLUDE <file.lmc>`
+#![cfg(feature = "std")]
+
+use lmp_vm::{VirtualMachine, VirtualMachineStep};
+
+/// Assemble and run a program until it halts, returning the values and text it output
+fn run(source: &str) -> (Vec<i64>, String) {
+    let mut vm = VirtualMachine::new();
+    vm.compile(source).unwrap();
+
+    let (mut values, mut text) = (Vec::new(), String::new());
+    loop {
+        match vm.step() {
+            VirtualMachineStep::Output(value) => values.push(value),
+            VirtualMachineStep::OutputCharacter(character) => text.push(character),
+            VirtualMachineStep::Halted => return (values, text),
+            VirtualMachineStep::Fault(fault) => panic!("faulted with {fault:?}"),
+            VirtualMachineStep::InputRequired(_) => panic!("ran out of input"),
+            _ => {}
+        }
+    }
+}
+
+/// Result of a macro taking `x, y, result` from `file`, along with `x` afterwards
+fn binary(file: &str, name: &str, x: i64, y: i64) -> (i64, i64) {
+    let (values, _) = run(&format!(
+        "        INCLUDE <{file}>
+                 {name} x, y, z
+                 LDA z
+                 OUT
+                 LDA x
+                 OUT
+                 HLT
+         x       DAT {x}
+         y       DAT {y}
+         z       DAT"
+    ));
+    (values[0], values[1])
+}
+
+/// Result of a macro taking `x, y, x` from `file`, storing the result over its first argument
+fn in_place(file: &str, name: &str, x: i64, y: i64) -> i64 {
+    let (values, _) = run(&format!(
+        "        INCLUDE <{file}>
+                 {name} x, y, x
+                 LDA x
+                 OUT
+                 HLT
+         x       DAT {x}
+         y       DAT {y}"
+    ));
+    values[0]
+}
+
+#[test]
+fn test_math() {
+    assert_eq!(binary("math.lmc", "MULTIPLY", 6, 7), (42, 6));
+    assert_eq!(binary("math.lmc", "MULTIPLY", 0, 7).0, 0);
+    assert_eq!(binary("math.lmc", "MULTIPLY", 6, 0).0, 0);
+    assert_eq!(in_place("math.lmc", "MULTIPLY", 6, 7), 42);
+
+    assert_eq!(binary("math.lmc", "DIVIDE", 17, 5), (3, 17));
+    assert_eq!(binary("math.lmc", "DIVIDE", 15, 5).0, 3);
+    assert_eq!(binary("math.lmc", "DIVIDE", 4, 5).0, 0);
+    assert_eq!(binary("math.lmc", "DIVIDE", 0, 5).0, 0);
+    assert_eq!(in_place("math.lmc", "DIVIDE", 17, 5), 3);
+
+    assert_eq!(binary("math.lmc", "MODULO", 17, 5), (2, 17));
+    assert_eq!(binary("math.lmc", "MODULO", 15, 5).0, 0);
+    assert_eq!(binary("math.lmc", "MODULO", 4, 5).0, 4);
+    assert_eq!(binary("math.lmc", "MODULO", 0, 5).0, 0);
+    assert_eq!(in_place("math.lmc", "MODULO", 17, 5), 2);
+}
+
+#[test]
+fn test_shift() {
+    assert_eq!(binary("shift.lmc", "SHIFTL", 3, 4), (48, 3));
+    assert_eq!(binary("shift.lmc", "SHIFTL", 3, 0).0, 3);
+    assert_eq!(binary("shift.lmc", "SHIFTL", 0, 4).0, 0);
+    assert_eq!(in_place("shift.lmc", "SHIFTL", 3, 4), 48);
+
+    assert_eq!(binary("shift.lmc", "SHIFTR", 50, 2), (12, 50));
+    assert_eq!(binary("shift.lmc", "SHIFTR", 50, 0).0, 50);
+    assert_eq!(binary("shift.lmc", "SHIFTR", 3, 2).0, 0);
+    assert_eq!(in_place("shift.lmc", "SHIFTR", 50, 2), 12);
+}
+
+#[test]
+fn test_print() {
+    let print = |value: i64| {
+        let (values, text) = run(&format!(
+            "        INCLUDE <print.lmc>
+                     PRINTNUM x
+                     LDA x
+                     OUT
+                     HLT
+             x       DAT {value}"
+        ));
+        // The value is left untouched
+        assert_eq!(values, [value]);
+        text
+    };
+    for value in [0, 7, 10, 99, 305, 1000, 4096] {
+        assert_eq!(print(value), value.to_string());
+    }
+
+    let (_, text) = run(
+        "        INCLUDE <print.lmc>
+                 PRINTSTR greeting
+                 PRINTSTR empty
+                 PRINTSTR greeting
+                 HLT
+         greeting DAT \"Hi there\", 0
+         empty   DAT 0",
+    );
+    assert_eq!(text, "Hi thereHi there");
+}
@@ -0,0 +1,75 @@
+use lmp_vm::assembly::Instruction::*;
+use lmp_vm::devices::{Mapped, Random, TextDisplay};
+use lmp_vm::{MapError, VirtualMachine, VirtualMachineStep};
+
+/// Run the VM until it halts, collecting its outputs
+fn run(vm: &mut VirtualMachine) -> Vec<i64> {
+    let mut outputs = Vec::new();
+    loop {
+        match vm.step() {
+            VirtualMachineStep::Output(value) => outputs.push(value),
+            VirtualMachineStep::Halted => return outputs,
+            VirtualMachineStep::Fault(fault) => panic!("faulted with {fault:?}"),
+            VirtualMachineStep::InputRequired(_) => panic!("ran out of input"),
+            _ => {}
+        }
+    }
+}
+
+#[test]
+fn test_mapped_access() {
+    let mut vm = VirtualMachine::new();
+    vm.map(100, Box::new(TextDisplay::new(3))).unwrap();
+    vm.map(200, Box::new(Mapped(Box::new(Random::with_range(7, 1, 6))))).unwrap();
+    vm.load([
+        (0, LDAI('H' as i64)),
+        (1, STA(100)),
+        (2, LDAI('i' as i64)),
+        (3, STA(101)),
+        (4, LDA(100)),
+        (5, OUT),
+        (6, LDA(200)),
+        (7, OUT),
+        (8, LDA(50)),
+        (9, OUT),
+        (10, HLT),
+        (50, DAT(9)),
+    ]);
+
+    let outputs = run(&mut vm);
+    assert_eq!(outputs[0], 'H' as i64);
+    assert!((1..=6).contains(&outputs[1]));
+    assert_eq!(outputs[2], 9);
+
+    let (cells, display) = vm.mapped().next().unwrap();
+    assert_eq!(cells, 100..103);
+    assert_eq!(display.status().unwrap(), "[Hi ]");
+
+    // Mapped cells show the last value read or written, and other cells are untouched
+    let memory = vm.memory();
+    assert_eq!(memory[100].data, 'H' as i64);
+    assert_eq!(memory[101].data, 'i' as i64);
+    assert_eq!(memory[102].data, 0);
+    assert_eq!(memory[200].data, outputs[1]);
+    assert_eq!(memory[50].data, 9);
+    assert_eq!(memory[99].data, 0);
+    assert!(!vm.code()[100]);
+}
+
+#[test]
+fn test_map_errors() {
+    let mut vm = VirtualMachine::new();
+    vm.map(100, Box::new(TextDisplay::new(3))).unwrap();
+
+    let overlap = MapError::Overlap { cells: 102..103, other: 100..103 };
+    assert_eq!(vm.map(102, Box::new(TextDisplay::new(1))), Err(overlap));
+    let overlap = MapError::Overlap { cells: 98..101, other: 100..103 };
+    assert_eq!(vm.map(98, Box::new(TextDisplay::new(3))), Err(overlap));
+    assert_eq!(vm.map(510, Box::new(TextDisplay::new(3))), Err(MapError::OutOfMemory { cells: 510..513 }));
+
+    // Failed mappings leave the others in place, and neighbouring devices can still be mapped
+    vm.map(103, Box::new(TextDisplay::new(1))).unwrap();
+    vm.map(97, Box::new(TextDisplay::new(3))).unwrap();
+    let mapped: Vec<_> = vm.mapped().map(|(cells, _)| cells).collect();
+    assert_eq!(mapped, [97..100, 100..103, 103..104]);
+}
@@ -0,0 +1,39 @@
+#![cfg(feature = "std")]
+
+use lmp_vm::devices::{IoDevice, Tape};
+use std::{env, fs, process};
+
+#[test]
+fn test_tape() {
+    let directory = env::temp_dir().join(format!("lmp-tape-{}", process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    let input = directory.join("input.txt");
+    let output = directory.join("output.txt");
+    fs::write(&input, "1 2\n3").unwrap();
+
+    let mut tape = Tape::open(&input).unwrap().with_output(&output).unwrap();
+    assert_eq!(tape.read().unwrap(), Some(1));
+    tape.write(7).unwrap();
+    tape.write(8).unwrap();
+    assert_eq!(tape.read().unwrap(), Some(2));
+    assert_eq!(tape.written(), [7, 8]);
+    assert_eq!(fs::read_to_string(&input).unwrap(), "1 2\n3");
+    assert_eq!(fs::read_to_string(&output).unwrap(), "7\n8\n");
+
+    // Resetting rewinds the input and starts the output over
+    tape.reset();
+    assert_eq!(tape.read().unwrap(), Some(1));
+    tape.write(9).unwrap();
+    assert_eq!(tape.written(), [9]);
+    assert_eq!(fs::read_to_string(&output).unwrap(), "9\n");
+
+    let mut tape = Tape::open(&input).unwrap();
+    assert_eq!(tape.read().unwrap(), Some(1));
+    tape.write(4).unwrap();
+    assert_eq!(tape.read().unwrap(), Some(2));
+    assert_eq!(tape.read().unwrap(), Some(3));
+    assert!(tape.read().is_err());
+    assert_eq!(fs::read_to_string(&input).unwrap(), "1 2\n3");
+
+    fs::remove_dir_all(&directory).unwrap();
+}
@@ -0,0 +1,260 @@
+//! WebAssembly bindings for the simulator, for embedding it in web pages
+//!
+//! Values are 64-bit integers, so they are passed to and from JavaScript as `BigInt`s, while
+//! addresses and cycle counts are plain numbers.
+use js_sys::Function;
+use lmp_common::MEMORY_SIZE;
+use lmp_lang::{parser, SourceMap, SourceOrigin};
+use lmp_vm::{InputKind, VirtualMachine, VirtualMachineError, VirtualMachineStep};
+use wasm_bindgen::prelude::*;
+
+/// Assemble a program, returning the contents of every cell of memory
+#[wasm_bindgen]
+pub fn assemble(source: &str) -> Result<Vec<i64>, JsError> {
+    let mut sources = SourceMap::new();
+    let root = sources.add(SourceOrigin::Input, source);
+    let program = parser::assemble_sources(&mut sources, root).map_err(|errors| {
+        let reports: Vec<_> = errors.iter().map(|error| error.report(&sources)).collect();
+        JsError::new(&reports.join("\n"))
+    })?;
+
+    let mut memory = vec![0; MEMORY_SIZE];
+    for (address, data) in program.image() {
+        memory[address] = data;
+    }
+    Ok(memory)
+}
+
+/// What happened when the simulator was stepped
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepKind {
+    Advanced = "advanced",
+    Output = "output",
+    OutputCharacter = "outputCharacter",
+    InputRequired = "inputRequired",
+    Waiting = "waiting",
+    Interrupted = "interrupted",
+    Halted = "halted",
+    Fault = "fault",
+    Breakpoint = "breakpoint",
+    CycleLimit = "cycleLimit",
+}
+
+/// The result of stepping the simulator
+#[wasm_bindgen]
+#[derive(Debug, Clone)]
+pub struct Step {
+    kind: StepKind,
+    value: Option<i64>,
+    message: Option<String>,
+}
+
+#[wasm_bindgen]
+impl Step {
+    #[wasm_bindgen(getter)]
+    pub fn kind(&self) -> StepKind {
+        self.kind
+    }
+
+    /// The value output, the code point of the character output, or the interrupt line handled
+    #[wasm_bindgen(getter)]
+    pub fn value(&self) -> Option<i64> {
+        self.value
+    }
+
+    /// Description of the fault that stopped the program
+    #[wasm_bindgen(getter)]
+    pub fn message(&self) -> Option<String> {
+        self.message.clone()
+    }
+}
+
+impl Step {
+    fn new(kind: StepKind) -> Self {
+        Self { kind, value: None, message: None }
+    }
+}
+
+impl From<VirtualMachineStep> for Step {
+    fn from(step: VirtualMachineStep) -> Self {
+        let with_value = |kind, value| Self { kind, value: Some(value), message: None };
+        match step {
+            VirtualMachineStep::Output(value) => with_value(StepKind::Output, value),
+            VirtualMachineStep::OutputCharacter(character) => {
+                with_value(StepKind::OutputCharacter, character as i64)
+            }
+            VirtualMachineStep::InputRequired(_) => Self::new(StepKind::InputRequired),
+            VirtualMachineStep::Waiting => Self::new(StepKind::Waiting),
+            VirtualMachineStep::Interrupted(line) => with_value(StepKind::Interrupted, line as i64),
+            VirtualMachineStep::Halted => Self::new(StepKind::Halted),
+            VirtualMachineStep::Fault(fault) => Self {
+                kind: StepKind::Fault,
+                value: None,
+                message: Some(fault.to_string()),
+            },
+            _ => Self::new(StepKind::Advanced),
+        }
+    }
+}
+
+/// A virtual machine that programs are assembled into and run
+#[wasm_bindgen]
+#[derive(Debug, Default)]
+pub struct Simulator {
+    vm: VirtualMachine,
+    /// Called with the value and whether it is a character whenever the program outputs
+    on_output: Option<Function>,
+    /// Called with whether a character is wanted when the program needs input and none is
+    /// queued, returning the input or `undefined` to wait
+    on_input: Option<Function>,
+}
+
+#[wasm_bindgen]
+impl Simulator {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Assemble a program and load it, resetting the simulator
+    pub fn assemble(&mut self, source: &str) -> Result<(), JsError> {
+        self.vm.compile(source).map_err(compile_error)
+    }
+
+    /// Reset registers and memory, unloading the program
+    pub fn reset(&mut self) {
+        self.vm = VirtualMachine::new();
+    }
+
+    /// Set the function called with `(value, isCharacter)` when the program outputs
+    #[wasm_bindgen(js_name = onOutput)]
+    pub fn on_output(&mut self, callback: Option<Function>) {
+        self.on_output = callback;
+    }
+
+    /// Set the function called with `(isCharacter)` when the program needs input, which returns
+    /// the input as a `BigInt` or number, or `undefined` to wait for [`Simulator::input`]
+    #[wasm_bindgen(js_name = onInput)]
+    pub fn on_input(&mut self, callback: Option<Function>) {
+        self.on_input = callback;
+    }
+
+    /// Queue a value to be read by `INP` or `INC`
+    pub fn input(&mut self, value: i64) {
+        self.vm.input(value);
+    }
+
+    /// Execute one instruction, calling the I/O callbacks as needed
+    pub fn step(&mut self) -> Result<Step, JsError> {
+        let step = self.vm.step();
+        match &step {
+            VirtualMachineStep::Output(value) => self.output(*value, false)?,
+            VirtualMachineStep::OutputCharacter(character) => self.output(*character as i64, true)?,
+            VirtualMachineStep::InputRequired(kind) => {
+                if let Some(callback) = &self.on_input {
+                    let character = matches!(kind, InputKind::Character);
+                    let input = callback.call1(&JsValue::NULL, &character.into()).map_err(js_error)?;
+                    if !input.is_undefined() {
+                        let value = i64::try_from(input.clone())
+                            .ok()
+                            .or_else(|| input.as_f64().map(|number| number as i64))
+                            .ok_or_else(|| JsError::new("input must be a BigInt or number"))?;
+                        self.vm.input(value);
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        Ok(step.into())
+    }
+
+    /// Step until the program halts, faults, waits for input that the input callback did not
+    /// give, reaches an address in `breakpoints`, or runs for `maxCycles` steps
+    #[wasm_bindgen(js_name = runUntil)]
+    pub fn run_until(&mut self, max_cycles: u32, breakpoints: Option<Vec<usize>>) -> Result<Step, JsError> {
+        let breakpoints = breakpoints.unwrap_or_default();
+        for cycle in 0..max_cycles {
+            // Allow continuing from a breakpoint
+            if cycle > 0 && breakpoints.contains(&self.vm.program_counter()) {
+                return Ok(Step::new(StepKind::Breakpoint));
+            }
+
+            let step = self.step()?;
+            match step.kind {
+                StepKind::Halted | StepKind::Fault => return Ok(step),
+                StepKind::InputRequired if self.vm.queued_input().is_empty() => return Ok(step),
+                _ => {}
+            }
+        }
+
+        Ok(Step::new(StepKind::CycleLimit))
+    }
+
+    /// Contents of every cell of memory
+    pub fn memory(&self) -> Vec<i64> {
+        self.vm.memory().iter().map(|cell| cell.data).collect()
+    }
+
+    #[wasm_bindgen(js_name = readMemory)]
+    pub fn read_memory(&self, address: usize) -> Option<i64> {
+        self.vm.memory().get(address).map(|cell| cell.data)
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn accumulator(&self) -> i64 {
+        self.vm.accumulator()
+    }
+
+    #[wasm_bindgen(getter, js_name = programCounter)]
+    pub fn program_counter(&self) -> usize {
+        self.vm.program_counter()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn index(&self) -> i64 {
+        self.vm.index()
+    }
+
+    #[wasm_bindgen(getter, js_name = stackPointer)]
+    pub fn stack_pointer(&self) -> usize {
+        self.vm.stack_pointer()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn carry(&self) -> bool {
+        self.vm.flags().carry
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn overflow(&self) -> bool {
+        self.vm.flags().overflow
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn cycles(&self) -> i64 {
+        self.vm.cycles()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn halted(&self) -> bool {
+        self.vm.halted()
+    }
+
+    fn output(&self, value: i64, character: bool) -> Result<(), JsError> {
+        if let Some(callback) = &self.on_output {
+            callback.call2(&JsValue::NULL, &value.into(), &character.into()).map_err(js_error)?;
+        }
+        Ok(())
+    }
+}
+
+fn compile_error(error: VirtualMachineError) -> JsError {
+    JsError::new(&error.to_string())
+}
+
+/// Describe an exception thrown by a callback
+fn js_error(error: JsValue) -> JsError {
+    JsError::new(&format!("callback threw {error:?}"))
+}
@@ -0,0 +1,68 @@
+//! Run with `cargo test -p lmp-wasm --target wasm32-unknown-unknown`, which uses
+//! `wasm-bindgen-test-runner` to run the tests headlessly in Node.js
+#![cfg(target_arch = "wasm32")]
+use js_sys::{Array, Function};
+use lmp_wasm::{Simulator, StepKind, assemble};
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_test::wasm_bindgen_test;
+
+const COUNTDOWN: &str = "
+        INP
+loop    OUT
+        SUB one
+        BRP loop
+        HLT
+one     DAT 1
+";
+
+#[wasm_bindgen_test]
+fn test_assemble() {
+    let memory = assemble(COUNTDOWN).unwrap();
+    assert_eq!(memory.len(), lmp_common::MEMORY_SIZE);
+    assert_eq!(&memory[..6], [901, 902, 2005, 8001, 1, 1]);
+
+    let mut simulator = Simulator::new();
+    assert!(simulator.assemble("LDA missing").is_err());
+}
+
+#[wasm_bindgen_test]
+fn test_callbacks() {
+    let mut simulator = Simulator::new();
+    simulator.assemble(COUNTDOWN).unwrap();
+
+    // Outputs are collected into an array, and the only input is 2
+    let outputs = Array::new();
+    let collect = Function::new_with_args("outputs, value", "outputs.push(value)").bind1(&JsValue::NULL, &outputs);
+    simulator.on_output(Some(collect.unchecked_into()));
+    simulator.on_input(Some(Function::new_no_args("return 2n")));
+
+    let step = simulator.run_until(1000, None).unwrap();
+    assert_eq!(step.kind(), StepKind::Halted);
+    let outputs: Vec<i64> = outputs.iter().map(|value| i64::try_from(value).unwrap()).collect();
+    assert_eq!(outputs, [2, 1, 0]);
+    assert!(simulator.halted());
+}
+
+#[wasm_bindgen_test]
+fn test_run_until() {
+    let mut simulator = Simulator::new();
+    simulator.assemble(COUNTDOWN).unwrap();
+
+    // Waits for input without a callback
+    assert_eq!(simulator.run_until(1000, None).unwrap().kind(), StepKind::InputRequired);
+    simulator.input(5);
+
+    // Stops before the instruction at a breakpoint, and continues past it when run again
+    assert_eq!(simulator.run_until(1000, Some(vec![2])).unwrap().kind(), StepKind::Breakpoint);
+    assert_eq!(simulator.program_counter(), 2);
+    assert_eq!(simulator.accumulator(), 5);
+    assert_eq!(simulator.run_until(1000, Some(vec![2])).unwrap().kind(), StepKind::Breakpoint);
+    assert_eq!(simulator.accumulator(), 4);
+
+    assert_eq!(simulator.run_until(3, None).unwrap().kind(), StepKind::CycleLimit);
+
+    simulator.assemble("DIV zero\nHLT\nzero DAT 0").unwrap();
+    let step = simulator.step().unwrap();
+    assert_eq!(step.kind(), StepKind::Fault);
+    assert!(step.message().unwrap().contains("zero"));
+}